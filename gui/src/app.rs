@@ -30,6 +30,39 @@ pub struct BuilderGui {
 pub struct StorageSettings {
     pub game_dir: String,
     pub output_dir: String,
+    /// FGD files used to classify entity keys, separated by `;`.
+    #[serde(default)]
+    pub fgd_paths: String,
+    /// Write a `maps/<map>_particles.txt` manifest for every map.
+    #[serde(default)]
+    pub particle_manifests: bool,
+    /// Names of VMF visgroups to skip, separated by `;`.
+    #[serde(default)]
+    pub exclude_visgroups: String,
+    /// Skip VMF entities and brushes hidden through visgroups.
+    #[serde(default)]
+    pub exclude_hidden: bool,
+    /// Only collect the model materials used by placed skins.
+    #[serde(default)]
+    pub minimal_skins: bool,
+    /// Largest texture side before a warning, empty for the default and `0` to disable.
+    #[serde(default)]
+    pub max_texture_size: String,
+    /// Number of the largest mip levels removed from copied textures, empty or `0` keeps all.
+    #[serde(default)]
+    pub strip_mips: String,
+    /// Only strip textures with a side above this size, empty for all.
+    #[serde(default)]
+    pub strip_mips_above: String,
+    /// Extract the custom content packed into BSPs into the output.
+    #[serde(default)]
+    pub extract_embedded: bool,
+    /// Write copies of the BSPs with the collected assets packed into them.
+    #[serde(default)]
+    pub pack_bsp: bool,
+    /// Write a bspzip addlist for each BSP.
+    #[serde(default)]
+    pub bspzip_addlist: bool,
     pub maps: Vec<Map>,
     pub theme: ui::themes::Themes,
 }
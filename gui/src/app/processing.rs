@@ -1,5 +1,9 @@
 use sourcemods_builder::UniqueAssets;
+use sourcemods_builder::asset_processor::materials::DEFAULT_MAX_TEXTURE_SIZE;
 use sourcemods_builder::find_asset_directories;
+use sourcemods_builder::parsers::{ParseOptions, fgd::Fgd};
+use sourcemods_builder::pipeline::{self, OutputOptions};
+use sourcemods_builder::vtf::MipStrip;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
@@ -16,6 +20,8 @@ pub enum ProcessingMessage {
     SetProcessingStatus(ProcessingStatus),
     /// Update a specific map's status.
     MapStatus { index: usize, status: MapStatus },
+    /// Problems found in the textures used by a map, one line per texture.
+    TextureIssues { index: usize, issues: Vec<String> },
     /// Update the count of unique assets.
     UniqueAssetsCount(u32),
     /// Update the count of found assets.
@@ -26,6 +32,30 @@ pub enum ProcessingMessage {
     Complete,
 }
 
+/// Settings copied from the GUI config for the processing thread.
+pub struct ProcessingSettings {
+    /// FGD files used to classify entity keys.
+    pub fgd_paths: Vec<PathBuf>,
+    /// Names of VMF visgroups whose entities and brushes are skipped.
+    pub exclude_visgroups: Vec<String>,
+    /// Skip VMF entities and brushes hidden through visgroups.
+    pub exclude_hidden: bool,
+    /// Only collect the model materials used by placed skins.
+    pub minimal_skins: bool,
+    /// Largest texture side before a warning, `0` disables the check.
+    pub max_texture_size: u32,
+    /// Mip stripping, embedded content and manifests of the output.
+    pub output: OutputOptions,
+}
+
+/// Splits a `;`-separated settings field into its non-empty items.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
 // Helper function to change map status in GUI
 fn change_map_status(tx: &Sender<ProcessingMessage>, index: usize, status: MapStatus) {
     let _ = tx.send(ProcessingMessage::MapStatus { index, status });
@@ -41,19 +71,6 @@ fn extract_panic_message(payload: Box<dyn std::any::Any + Send + 'static>) -> St
     }
 }
 
-// Helper function to process assets and send the count to the GUI.
-fn process_and_send<T, F>(process_fn: F, tx: &Sender<ProcessingMessage>) -> Vec<T>
-where
-    F: FnOnce() -> Vec<T>,
-{
-    // Execute the processing function
-    let paths = process_fn();
-    // Send the count of found assets
-    let count = paths.len() as u32;
-    let _ = tx.send(ProcessingMessage::AssetsFoundCount(count));
-    paths
-}
-
 impl BuilderGui {
     pub fn process_maps(&mut self, cancel_flag: Arc<AtomicBool>) -> Result<(), String> {
         let game_path = Path::new(&self.config.game_dir).to_path_buf();
@@ -78,10 +95,43 @@ impl BuilderGui {
         self.internal.unique_assets_ui = 0;
 
         let maps_clone = self.config.maps.clone(); // It's not the best idea, but it works for now
+        let settings = ProcessingSettings {
+            fgd_paths: split_list(&self.config.fgd_paths)
+                .map(PathBuf::from)
+                .collect(),
+            exclude_visgroups: split_list(&self.config.exclude_visgroups)
+                .map(String::from)
+                .collect(),
+            exclude_hidden: self.config.exclude_hidden,
+            minimal_skins: self.config.minimal_skins,
+            max_texture_size: self
+                .config
+                .max_texture_size
+                .trim()
+                .parse()
+                .unwrap_or(DEFAULT_MAX_TEXTURE_SIZE),
+            output: OutputOptions {
+                mip_strip: MipStrip {
+                    mips: self.config.strip_mips.trim().parse().unwrap_or(0),
+                    min_size: self.config.strip_mips_above.trim().parse().unwrap_or(0),
+                },
+                particle_manifests: self.config.particle_manifests,
+                extract_embedded: self.config.extract_embedded,
+                pack_bsp: self.config.pack_bsp,
+                bspzip_addlist: self.config.bspzip_addlist,
+            },
+        };
 
         std::thread::spawn(move || {
             if let Err(err) = std::panic::catch_unwind(|| {
-                BuilderGui::_process_maps(&tx, maps_clone, game_path, output_path, cancel_flag);
+                BuilderGui::_process_maps(
+                    &tx,
+                    maps_clone,
+                    game_path,
+                    output_path,
+                    settings,
+                    cancel_flag,
+                );
             }) {
                 let err = extract_panic_message(err);
                 let msg = format!(
@@ -107,9 +157,26 @@ impl BuilderGui {
         maps_clone: Vec<Map>,
         game_dir: PathBuf,
         output_dir: PathBuf,
+        settings: ProcessingSettings,
         is_cancelled: Arc<AtomicBool>
     ) {
         log::info!("Start processing {} maps.", maps_clone.len());
+        let mut options = ParseOptions {
+            exclude_visgroups: settings.exclude_visgroups,
+            exclude_hidden: settings.exclude_hidden,
+            minimal_skins: settings.minimal_skins,
+            ..Default::default()
+        };
+        if !settings.fgd_paths.is_empty() {
+            match Fgd::load(&settings.fgd_paths) {
+                Ok(fgd) => options.fgd = Some(fgd),
+                Err(err) => {
+                    let _ = tx.send(ProcessingMessage::Error(format!("Failed to load FGD: {}", err)));
+                    return;
+                }
+            }
+        }
+
         let mut u_assets = UniqueAssets::default();
         let mut unique_count: u32 = 0;
 
@@ -120,7 +187,7 @@ impl BuilderGui {
             if matches!(map.status, MapStatus::Completed) { continue }
 
             // Notify GUI that this map processing has started
-            change_map_status(&tx, idx, MapStatus::Processing);
+            change_map_status(tx, idx, MapStatus::Processing);
             let _ = tx.send(ProcessingMessage::SetProcessingStatus(
                 ProcessingStatus::ScanMap(idx),
             ));
@@ -128,16 +195,16 @@ impl BuilderGui {
 
             let parse_result: Result<(), String> = if map.is_vmf {
                 // Extract unique assets from the VMF file
-                u_assets.parse_vmf(&map.path)
+                u_assets.parse_vmf(&map.path, &options)
                     .map_err(|err| err.to_string()) 
             } else {
                 // Extract unique assets from the BSP file
-                u_assets.parse_bsp(&map.path)
+                u_assets.parse_bsp(&map.path, &options)
                     .map_err(|err| err.to_string())
             };
 
             if let Err(err_string) = parse_result {
-                change_map_status(&tx, idx, MapStatus::Error(err_string));
+                change_map_status(tx, idx, MapStatus::Error(err_string));
                 continue;
             }
 
@@ -145,14 +212,14 @@ impl BuilderGui {
             let len = u_assets.len() as u32;
             // If no unique assets found, send warning status
             if len - unique_count == 0 {
-                change_map_status(&tx, idx, MapStatus::Warning(WarningReason::NotFoundAssets));
+                change_map_status(tx, idx, MapStatus::Warning(WarningReason::NotFoundAssets));
                 continue;
             }
             unique_count = len;
             let _ = tx.send(ProcessingMessage::UniqueAssetsCount(unique_count));
 
             // Mark map as completed
-            change_map_status(&tx, idx, MapStatus::Completed);
+            change_map_status(tx, idx, MapStatus::Completed);
         }
 
         // Notify GUI that asset search is starting
@@ -161,49 +228,32 @@ impl BuilderGui {
         ));
 
         // Locate asset directories
-        let (models_dirs, materials_dirs, sounds_dirs) = find_asset_directories(&game_dir);
+        let dirs = find_asset_directories(&game_dir);
         if is_cancelled.load(Ordering::SeqCst) { return }
 
-        //-- region: processing paths
-        // Process models using the helper function
-        let models_paths = process_and_send(
-            || sourcemods_builder::asset_processor::models::process(&mut u_assets, &models_dirs),
-            &tx,
-        );
-        // Process materials using the helper function
-        let materials_paths = process_and_send(
-            || sourcemods_builder::asset_processor::materials::process(&u_assets, &materials_dirs),
-            &tx,
-        );
-        // Process sounds using the helper function
-        let sounds_paths = process_and_send(
-            || sourcemods_builder::asset_processor::sounds::process(&u_assets, &sounds_dirs),
-            &tx,
-        );
+        // Find the asset files, sending the count found by every pass
+        let files = pipeline::find_files(&mut u_assets, &dirs, |count| {
+            let _ = tx.send(ProcessingMessage::AssetsFoundCount(count as u32));
+        });
 
         // If new unique assets were found during the processing, we update count in GUI
         let _ = tx.send(ProcessingMessage::UniqueAssetsCount(u_assets.len() as u32));
         if is_cancelled.load(Ordering::SeqCst) { return }
-        //-- Endregion
+
+        // Show the texture problems on the maps using them
+        for (map, issues) in files.texture_issues(&u_assets, settings.max_texture_size) {
+            let Some(index) = maps_clone.iter().position(|m| m.path == map) else { continue };
+            let _ = tx.send(ProcessingMessage::TextureIssues { index, issues });
+        }
 
         // Notify GUI that asset copying is starting
         let _ = tx.send(ProcessingMessage::SetProcessingStatus(
             ProcessingStatus::CopyAssets,
         ));
 
-        // Copy model files
-        if let Err(err) = sourcemods_builder::utils::copy_files(&models_paths, &output_dir, "models") {
-            let _ = tx.send(ProcessingMessage::Error(format!("Failed to copy models: {}", err)));
-            return;
-        }
-        // Copy material files
-        if let Err(err) = sourcemods_builder::utils::copy_files(&materials_paths, &output_dir, "materials") {
-            let _ = tx.send(ProcessingMessage::Error(format!("Failed to copy materials: {}", err)));
-            return;
-        }
-        // Copy sound files
-        if let Err(err) = sourcemods_builder::utils::copy_files(&sounds_paths, &output_dir, "sound") {
-            let _ = tx.send(ProcessingMessage::Error(format!("Failed to copy sounds: {}", err)));
+        // Copy the files, write the generated ones and pack the maps
+        if let Err(err) = files.write(&u_assets, &dirs, &output_dir, &settings.output) {
+            let _ = tx.send(ProcessingMessage::Error(err));
             return;
         }
 
@@ -228,9 +278,19 @@ impl BuilderGui {
                     ProcessingMessage::MapStatus { index, status } => {
                         // Update the status of a specific map if the index is valid
                         if let Some(map) = self.config.maps.get_mut(index) {
+                            // A map being processed again gets its texture issues reported anew
+                            if status == MapStatus::Processing {
+                                map.texture_issues.clear();
+                            }
                             map.status = status;
                         }
                     }
+                    ProcessingMessage::TextureIssues { index, issues } => {
+                        // Shown next to the map status, which stays completed
+                        if let Some(map) = self.config.maps.get_mut(index) {
+                            map.texture_issues = issues;
+                        }
+                    }
                     ProcessingMessage::UniqueAssetsCount(count) => {
                         // Update the unique assets count in the internal state
                         self.internal.unique_assets = count;
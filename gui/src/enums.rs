@@ -32,8 +32,9 @@ impl Display for ProcessingStatus {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
 pub enum MapStatus {
+    #[default]
     Pending,
     Processing,
     Warning(WarningReason),
@@ -41,12 +42,6 @@ pub enum MapStatus {
     Completed,
 }
 
-impl Default for MapStatus {
-    fn default() -> Self {
-        MapStatus::Pending
-    }
-}
-
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum WarningReason {
     NotFoundAssets,
@@ -87,6 +82,9 @@ pub struct Map {
     pub name: String,
     #[serde(skip)]
     pub status: MapStatus,
+    /// Problems found in the textures used by the map, one line per texture.
+    #[serde(skip)]
+    pub texture_issues: Vec<String>,
     pub is_vmf: bool,
 }
 
@@ -96,6 +94,7 @@ impl Map {
             path: path.to_path_buf(),
             name: path.file_name().unwrap().to_string_lossy().to_string(),
             status: MapStatus::Pending,
+            texture_issues: Vec::new(),
             is_vmf,
         }
     }
@@ -14,11 +14,8 @@ fn build_left_ui(ui: &mut egui::Ui, app: &mut App) {
         ui.horizontal(|ui| {
             ui.label(app.process_status.to_string())
                 .on_hover_cursor(egui::CursorIcon::Wait);
-            match app.process_status {
-                crate::enums::ProcessingStatus::ScanMap(idx) => {
-                    ui.label(&format!("{idx}/{total}", total = app.config.maps.len()));
-                }
-                _ => {}
+            if let crate::enums::ProcessingStatus::ScanMap(idx) = app.process_status {
+                ui.label(format!("{idx}/{total}", total = app.config.maps.len()));
             }
         });
         return;
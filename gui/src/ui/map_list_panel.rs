@@ -47,6 +47,14 @@ pub fn build(ui: &mut egui::Ui, app: &mut App) {
                                 .on_hover_cursor(egui::CursorIcon::Help);
                         }
                     };
+                    if !map.texture_issues.is_empty() {
+                        ui.label_sized("⚠", 8.0)
+                            .on_hover_text(format!(
+                                "Texture problems:\n{}",
+                                map.texture_issues.join("\n")
+                            ))
+                            .on_hover_cursor(egui::CursorIcon::Help);
+                    }
                     ui.label(&map.name)
                         .on_hover_text(map.path.as_os_str().to_str().unwrap());
 
@@ -13,10 +13,10 @@ pub fn build(ui: &mut Ui, app: &mut App) {
         ui.label("Game Dir:");
         ui.allocate_space(Vec2::default()); // Small hack avoid text size differences between Game Dir & Output Dir
         ui.singleline_on_screen(&mut app.config.game_dir, BUTTON_WIDTH + spacing);
-        if ui.button("Browse").clicked() {
-            if let Some(path) = FileDialog::new().pick_folder() {
-                app.config.game_dir = path.display().to_string();
-            }
+        if ui.button("Browse").clicked()
+            && let Some(path) = FileDialog::new().pick_folder()
+        {
+            app.config.game_dir = path.display().to_string();
         }
     });
 
@@ -24,10 +24,76 @@ pub fn build(ui: &mut Ui, app: &mut App) {
     ui.horizontal(|ui| {
         ui.label("Output Dir:");
         ui.singleline_on_screen(&mut app.config.output_dir, BUTTON_WIDTH + spacing);
-        if ui.button("Browse").clicked() {
-            if let Some(path) = FileDialog::new().pick_folder() {
-                app.config.output_dir = path.display().to_string();
-            }
+        if ui.button("Browse").clicked()
+            && let Some(path) = FileDialog::new().pick_folder()
+        {
+            app.config.output_dir = path.display().to_string();
         }
     });
+
+    // FGD files
+    ui.horizontal(|ui| {
+        ui.label("FGD:");
+        ui.allocate_space(Vec2::default());
+        ui.singleline_on_screen(&mut app.config.fgd_paths, BUTTON_WIDTH + spacing)
+            .on_hover_text("Optional. FGD files used to detect asset keys, separated by ';'");
+        if ui.button("Browse").clicked()
+            && let Some(paths) = FileDialog::new()
+                .add_filter("Forge Game Data", &["fgd"])
+                .pick_files()
+        {
+            app.config.fgd_paths = paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(";");
+        }
+    });
+
+    // Excluded visgroups
+    ui.horizontal(|ui| {
+        ui.label("Exclude visgroups:");
+        ui.singleline_on_screen(&mut app.config.exclude_visgroups, 0.)
+            .on_hover_text("Optional. VMF visgroups to skip, separated by ';'");
+    });
+
+    ui.checkbox(&mut app.config.exclude_hidden, "Skip hidden visgroups")
+        .on_hover_text("Skip VMF entities and brushes in hidden visgroups");
+    ui.checkbox(&mut app.config.minimal_skins, "Only copy used model skins")
+        .on_hover_text("Copy only the model materials of the skins placed in the maps");
+    ui.horizontal(|ui| {
+        ui.label("Max texture size:");
+        ui.singleline_on_screen(&mut app.config.max_texture_size, 0.)
+            .on_hover_text("Optional. Warn about textures larger than this size (4096 by default, 0 disables the check)");
+    });
+    ui.horizontal(|ui| {
+        ui.label("Strip texture mips:");
+        ui.singleline_on_screen(&mut app.config.strip_mips, 0.)
+            .on_hover_text(
+                "Optional. Remove this many of the largest mip levels from copied textures",
+            );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Strip only above size:");
+        ui.singleline_on_screen(&mut app.config.strip_mips_above, 0.)
+            .on_hover_text(
+                "Optional. Only strip textures larger than this size, and never below it",
+            );
+    });
+    ui.checkbox(
+        &mut app.config.particle_manifests,
+        "Write map particle manifests",
+    )
+    .on_hover_text("Write maps/<map>_particles.txt listing the particle files each map uses");
+    ui.checkbox(
+        &mut app.config.extract_embedded,
+        "Extract embedded map content",
+    )
+    .on_hover_text("Copy the custom content packed into BSPs to the output folder");
+    ui.checkbox(&mut app.config.pack_bsp, "Pack assets into maps")
+        .on_hover_text(
+            "Write copies of the BSPs to <output>/maps with the collected assets packed into them",
+        );
+    ui.checkbox(&mut app.config.bspzip_addlist, "Write bspzip addlists")
+        .on_hover_text("Write <map>_addlist.txt for Valve's bspzip -addlist");
 }
@@ -0,0 +1,141 @@
+//! Collection of the files that ship alongside a map: navigation meshes, node graphs,
+//! lump patches, descriptions, level sounds and overviews.
+//!
+//! Companion files are looked up next to the map first, then in the `maps/` and
+//! `resource/overviews/` directories of the content roots.
+
+use super::{Path, PathBuf, UniqueAssets, utils};
+use crate::keyvalues;
+use crate::parsers::common::{map_name, material_name};
+use log::{debug, info, warn};
+use std::{fs, io};
+
+/// A companion file and where it goes in the output.
+#[derive(Debug, Clone)]
+pub struct CompanionFile {
+    pub path: PathBuf,
+    /// Destination relative to the output directory, e.g. `maps/graphs/foo.ain`.
+    pub destination: PathBuf,
+}
+
+/// Processes the companion files of every parsed map.
+///
+/// Materials used by overviews are added to the unique assets, so this has to run
+/// before the material pass. Returns the found files.
+pub fn process(u_assets: &mut UniqueAssets, roots: &[PathBuf]) -> Vec<CompanionFile> {
+    let mut companions: Vec<CompanionFile> = Vec::new();
+
+    for map_path in u_assets.map_paths.clone() {
+        let map = map_name(&map_path);
+        let map_dir = map_path.parent().unwrap_or(Path::new(""));
+
+        let mut relative: Vec<PathBuf> = [
+            format!("{}.nav", map),
+            format!("graphs/{}.ain", map),
+            format!("{}.res", map),
+            format!("{}_level_sounds.txt", map),
+            format!("{}.txt", map),
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+        relative.extend(lump_patches(&map, map_dir, roots));
+
+        for file in relative {
+            let destination = Path::new("maps").join(&file);
+            let found = utils::find_file(map_dir, &file).or_else(|| {
+                roots
+                    .iter()
+                    .find_map(|root| utils::find_file(root, &destination))
+            });
+            if let Some(path) = found {
+                debug!("Found companion file of {}: {}", map, path.display());
+                companions.push(CompanionFile { path, destination });
+            }
+        }
+
+        let overview = PathBuf::from(format!("resource/overviews/{}.txt", map));
+        if let Some(path) = roots
+            .iter()
+            .find_map(|root| utils::find_file(root, &overview))
+        {
+            debug!("Found overview of {}: {}", map, path.display());
+            u_assets.add_map_assets(&map_path, |materials| {
+                add_overview_materials(&path, materials)
+            });
+            companions.push(CompanionFile {
+                path,
+                destination: overview,
+            });
+        }
+    }
+
+    info!(
+        "Companion processing finished. Found {} companion files.",
+        companions.len()
+    );
+    companions
+}
+
+/// Copies companion files to their destinations in the output directory.
+pub fn copy_files(companions: &[CompanionFile], output_dir: &Path) -> io::Result<()> {
+    for companion in companions {
+        let destination = output_dir.join(&companion.destination);
+        if let Some(parent_dir) = destination.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        fs::copy(&companion.path, &destination)?;
+    }
+    Ok(())
+}
+
+/// Returns the lump patch files (`<map>_l_<N>.lmp`) found next to the map or in `maps/`,
+/// relative to the maps directory.
+fn lump_patches(map: &str, map_dir: &Path, roots: &[PathBuf]) -> Vec<PathBuf> {
+    let prefix = format!("{}_l_", map);
+    let dirs = std::iter::once(map_dir.to_path_buf()).chain(
+        roots
+            .iter()
+            .filter_map(|root| utils::find_dir(root, "maps")),
+    );
+
+    let mut patches: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            let is_patch = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".lmp"))
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if is_patch && !patches.iter().any(|p| *p == Path::new(&name)) {
+                patches.push(name.into());
+            }
+        }
+    }
+    patches
+}
+
+/// Adds the `material` of an overview description to the unique assets.
+fn add_overview_materials(path: &Path, u_assets: &mut UniqueAssets) {
+    let kv = match keyvalues::parse_file(path) {
+        Ok(kv) => kv,
+        Err(e) => {
+            warn!("Failed to read overview {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for entry in &kv {
+        entry.visit(&mut |kv| {
+            if kv.key.eq_ignore_ascii_case("material")
+                && let Some(material) = kv.as_str().filter(|m| !m.trim().is_empty())
+            {
+                debug!("Found overview material: {}", material);
+                u_assets.materials_name.insert(material_name(material));
+            }
+        });
+    }
+}
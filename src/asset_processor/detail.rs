@@ -0,0 +1,54 @@
+use super::{Path, PathBuf, UniqueAssets, utils};
+use crate::keyvalues;
+use log::{debug, info, warn};
+
+/// Processes `detail.vbsp` files referenced by maps.
+///
+/// Finds each file in the content roots and adds the models used by its detail types
+/// to the model set. Returns the paths of the found files.
+pub fn process(u_assets: &mut UniqueAssets, roots: &Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut found: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    for dir in roots {
+        for vbsp in &u_assets.detail_vbsp_name {
+            if let Some(path) = utils::find_file(dir, vbsp) {
+                found.push((vbsp.clone(), path));
+            }
+        }
+    }
+
+    for (vbsp, path) in &found {
+        u_assets.add_children(vbsp, |models| add_detail_models(path, models));
+    }
+    let detail_paths: Vec<PathBuf> = found.into_iter().map(|(_, path)| path).collect();
+
+    info!(
+        "Detail processing finished. Found {} detail files.",
+        detail_paths.len()
+    );
+    detail_paths
+}
+
+/// Adds every `model` referenced by the detail types of a `detail.vbsp` file.
+fn add_detail_models(path: &Path, u_assets: &mut UniqueAssets) {
+    let kv = match keyvalues::parse_file(path) {
+        Ok(kv) => kv,
+        Err(e) => {
+            warn!("Failed to read detail file {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for entry in &kv {
+        entry.visit(&mut |kv| {
+            if kv.key.eq_ignore_ascii_case("model")
+                && let Some(model) = kv.as_str().filter(|m| !m.is_empty())
+            {
+                debug!("Found detail model in {}: {}", path.display(), model);
+                u_assets
+                    .models_name
+                    .insert(model.replace('\\', "/").to_lowercase().into());
+            }
+        });
+    }
+}
@@ -0,0 +1,215 @@
+//! Content embedded in the pakfile of processed BSPs: extraction of what mappers packed,
+//! and packing of the collected assets (a replacement for Valve's `bspzip`).
+
+use super::{Path, PathBuf, UniqueAssets, utils};
+use crate::pakfile::{self, Pakfile};
+use crate::parsers::common::map_name;
+use log::{debug, info, warn};
+use std::{fs, io};
+
+/// A file to pack into a BSP.
+#[derive(Debug, Clone)]
+pub struct PackEntry {
+    /// Name inside the pakfile, relative to a content root, e.g. `models/foo.mdl`.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Builds pack entries for files named from their `base_folder` component on, like
+/// `utils::copy_files` does (e.g. `models`, `materials`, `sound`).
+pub fn folder_entries(paths: &[PathBuf], base_folder: &str) -> Vec<PackEntry> {
+    paths
+        .iter()
+        .map(|path| PackEntry {
+            name: entry_name(&utils::folder_relative_path(path, base_folder)),
+            path: path.clone(),
+        })
+        .collect()
+}
+
+/// Builds pack entries for files that live in content roots, like `utils::copy_root_files`.
+pub fn root_entries(paths: &[PathBuf], roots: &[PathBuf]) -> Vec<PackEntry> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            Some(PackEntry {
+                name: entry_name(utils::root_relative_path(path, roots)?),
+                path: path.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Writes a copy of every processed BSP to `maps/` in the output directory, with the
+/// `entries` it uses added to its pakfile. The map's soundscape file, level sounds and
+/// particle manifest are packed too when they were written to the output. Entries copied
+/// to the output are packed from their copy, so textures keep their stripped mip levels.
+/// Existing pakfile entries are kept. Returns the number of written maps.
+pub fn pack_maps(
+    u_assets: &UniqueAssets,
+    entries: &[PackEntry],
+    output_dir: &Path,
+) -> io::Result<usize> {
+    let entry_maps = entry_maps(u_assets, entries);
+    let mut packed = 0;
+
+    for map_path in bsp_paths(u_assets) {
+        let Some(file_name) = map_path.file_name() else {
+            continue;
+        };
+        let files: Vec<(String, PathBuf)> = map_entries(map_path, &entry_maps, output_dir)
+            .into_iter()
+            .map(|entry| (entry.name, entry.path))
+            .collect();
+
+        let bsp = pakfile::write_bsp(&fs::read(map_path)?, &files)?;
+        let destination = output_dir.join("maps").join(file_name);
+        fs::create_dir_all(output_dir.join("maps"))?;
+        fs::write(&destination, bsp)?;
+        info!(
+            "Packed {} files into {}.",
+            files.len(),
+            destination.display()
+        );
+        packed += 1;
+    }
+    Ok(packed)
+}
+
+/// Writes a bspzip addlist (`<map>_addlist.txt`) to the output directory for every
+/// processed BSP, listing the same files as `pack_maps`, for use with `bspzip -addlist`.
+pub fn write_addlists(
+    u_assets: &UniqueAssets,
+    entries: &[PackEntry],
+    output_dir: &Path,
+) -> io::Result<()> {
+    let entry_maps = entry_maps(u_assets, entries);
+    fs::create_dir_all(output_dir)?;
+    for map_path in bsp_paths(u_assets) {
+        let mut addlist = String::new();
+        for entry in map_entries(map_path, &entry_maps, output_dir) {
+            // bspzip resolves relative paths against its own working directory.
+            let path = fs::canonicalize(&entry.path).unwrap_or(entry.path);
+            addlist.push_str(&entry.name);
+            addlist.push('\n');
+            addlist.push_str(&path.to_string_lossy());
+            addlist.push('\n');
+        }
+
+        let destination = output_dir.join(format!("{}_addlist.txt", map_name(map_path)));
+        fs::write(&destination, addlist)?;
+        debug!("Wrote bspzip addlist {}", destination.display());
+    }
+    Ok(())
+}
+
+/// Processed maps that are BSPs.
+fn bsp_paths(u_assets: &UniqueAssets) -> impl Iterator<Item = &PathBuf> {
+    u_assets.map_paths.iter().filter(|path| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bsp"))
+    })
+}
+
+/// Pairs each entry with the maps using it. Entries no map leads to are reported and
+/// left out.
+fn entry_maps<'a>(
+    u_assets: &'a UniqueAssets,
+    entries: &'a [PackEntry],
+) -> Vec<(&'a PackEntry, Vec<&'a Path>)> {
+    entries
+        .iter()
+        .map(|entry| (entry, u_assets.source_maps(&entry.name)))
+        .filter(|(entry, maps)| {
+            if maps.is_empty() {
+                warn!("No map uses {}, not packing it.", entry.name);
+            }
+            !maps.is_empty()
+        })
+        .collect()
+}
+
+/// The entries used by the map, plus the files generated for it in the output directory.
+fn map_entries(
+    map_path: &Path,
+    entry_maps: &[(&PackEntry, Vec<&Path>)],
+    output_dir: &Path,
+) -> Vec<PackEntry> {
+    let map = map_name(map_path);
+    let generated = [
+        format!("scripts/soundscapes_{}.txt", map),
+        format!("maps/{}_level_sounds.txt", map),
+        format!("maps/{}_particles.txt", map),
+    ];
+
+    let mut map_entries: Vec<PackEntry> = entry_maps
+        .iter()
+        .filter(|(_, maps)| maps.contains(&map_path))
+        .map(|(entry, _)| {
+            let copy = output_dir.join(&entry.name);
+            let path = if copy.is_file() {
+                copy
+            } else {
+                entry.path.clone()
+            };
+            PackEntry {
+                name: entry.name.clone(),
+                path,
+            }
+        })
+        .collect();
+    for name in generated {
+        let path = output_dir.join(&name);
+        if path.is_file() {
+            map_entries.push(PackEntry { name, path });
+        }
+    }
+    map_entries
+}
+
+/// Converts a relative path to a pakfile entry name.
+fn entry_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Extracts the files packed into the parsed BSPs into the output directory.
+///
+/// Content generated by the compile tools for a single map (cubemaps and patched
+/// materials under `materials/maps/<map>/`) is skipped. Returns the number of extracted files.
+pub fn extract(u_assets: &UniqueAssets, output_dir: &Path) -> io::Result<usize> {
+    let mut extracted = 0;
+
+    for map_path in bsp_paths(u_assets) {
+        let Some(mut pakfile) = Pakfile::read(&fs::read(map_path)?) else {
+            continue;
+        };
+        let generated = format!("materials/maps/{}/", map_name(map_path));
+        let mut names: Vec<String> = pakfile
+            .names()
+            .filter(|name| !name.starts_with(&generated))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+
+        for name in names {
+            let Some(relative) = pakfile::enclosed_path(&name) else {
+                warn!("Skipping embedded file with unsafe path: {}", name);
+                continue;
+            };
+            let Some(data) = pakfile.get(&name) else {
+                continue;
+            };
+
+            let destination = output_dir.join(relative);
+            if let Some(parent_dir) = destination.parent() {
+                fs::create_dir_all(parent_dir)?;
+            }
+            fs::write(&destination, data)?;
+            debug!("Extracted embedded file: {}", name);
+            extracted += 1;
+        }
+    }
+
+    info!("Extracted {} files embedded in maps.", extracted);
+    Ok(extracted)
+}
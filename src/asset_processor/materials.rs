@@ -1,61 +1,53 @@
-use super::{HashSet, PathBuf, UniqueAssets, utils};
-use log::info;
-use regex::Regex;
-use std::sync::OnceLock;
-
-// Static regex for extracting texture names from VMT files.
-static RE: OnceLock<Regex> = OnceLock::new();
-
-/// Returns a static Regex instance for parsing VMT texture paths.
-fn get_regex() -> &'static Regex {
-    RE.get_or_init(|| {
-        Regex::new(r#"\$[^\s]+?"?\s+"?(([A-Z]|[a-z])[^\["\n\r.]+)"#).expect("Invalid regex pattern")
-    })
-}
+use super::{HashSet, Path, PathBuf, UniqueAssets, utils};
+use crate::vmt::Vmt;
+use crate::vtf::VtfHeader;
+use log::{debug, info, warn};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+/// Default limit for texture dimensions, the largest size most engine branches load.
+pub const DEFAULT_MAX_TEXTURE_SIZE: u32 = 4096;
+/// Bytes read to parse a VTF header, enough for the resource table of 7.3+ files.
+const VTF_HEADER_READ_SIZE: u64 = 512;
 
 /// Processes material assets to find VMT and VTF files.
 ///
-/// Searches for VMT files based on unique material names and extracts VTF texture names.
+/// Searches for VMT files based on unique material names and reads their texture and
+/// material parameters. Referenced materials are resolved recursively, and their
+/// surface properties are recorded.
 /// Then, searches for VTF files based on the extracted texture names.
-pub fn process(u_assets: &UniqueAssets, materials_dirs: &Vec<PathBuf>) -> Vec<PathBuf> {
-    let re = get_regex();
+pub fn process(u_assets: &mut UniqueAssets, materials_dirs: &Vec<PathBuf>) -> Vec<PathBuf> {
     let mut materials_paths: Vec<PathBuf> = Vec::new();
-    let mut textures_name: HashSet<String> = HashSet::with_capacity(512);
+    let mut textures_name: HashSet<PathBuf> = HashSet::with_capacity(512);
 
-    // Search for VMT files based on unique material names.
-    for dir in materials_dirs {
-        for vmt in &u_assets.materials_name {
-            
-
-            #[cfg(not(unix))]
-            let path = dir.join(vmt).with_extension("vmt");
-            #[cfg(unix)] // Source engine is not case-sensitive, unlike unix-like filesystems
-            let path = match utils::find_asset_case_insensitive(dir, &vmt.clone().with_extension("vmt")) {
-                Ok(Some(correct_path)) => correct_path,
-                Ok(None) => {
-                    log::debug!("Asset not found: {} in {}", vmt.display(), dir.display());
-                    continue
-                },
-                Err(e) => {
-                    log::warn!(
-                        "Error searching for asset {} in {}: {}",
-                        vmt.display(),
-                        dir.display(),
-                        e
-                    );
-                    continue;
-                }
-            };
-
-            if !path.exists() { continue; }
-            
-            // Extract VTF texture names from VMT file content.
-            info!("Extracting VTF texture names from VMT: {}", path.display());
-            if let Ok(matches) = utils::find_all_groups_in_file(&path, re) {
-                info!("  Found VTF textures in VMT: {:?}", matches);
-                textures_name.extend(matches);
-            } else {
-                info!("  No VTF textures found in VMT or error reading file.");
+    // Search for VMT files based on unique material names, then for the materials they
+    // reference (patch includes, `$bottommaterial`...) until no new ones turn up.
+    // Materials are visited once, so reference cycles end.
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    let mut queue: Vec<PathBuf> = u_assets.materials_name.iter().cloned().collect();
+    while !queue.is_empty() {
+        let names: HashSet<PathBuf> = queue
+            .drain(..)
+            .filter(|name| visited.insert(name.to_string_lossy().to_lowercase().into()))
+            .collect();
+        for (name, path, vmt) in find_vmts(u_assets, materials_dirs, &names) {
+            let vmt_file = material_file(&name, "vmt");
+            for texture in vmt.textures() {
+                u_assets.add_parent(material_file(&texture, "vtf"), &vmt_file);
+                textures_name.insert(texture);
+            }
+            u_assets.add_children(&vmt_file, |found| {
+                found.surfaceprops_name.extend(vmt.surface_props())
+            });
+            for material in vmt.materials() {
+                debug!(
+                    "Found material referenced by {}: {}",
+                    path.display(),
+                    material.display()
+                );
+                u_assets.add_parent(material_file(&material, "vmt"), &vmt_file);
+                queue.push(material);
             }
             materials_paths.push(path);
         }
@@ -64,18 +56,22 @@ pub fn process(u_assets: &UniqueAssets, materials_dirs: &Vec<PathBuf>) -> Vec<Pa
     // Search for VTF files based on extracted texture names.
     info!("Searching for VTF files based on extracted texture names...");
     for dir in materials_dirs {
-        for vmt in &textures_name {
+        for vtf in &textures_name {
+            let vtf = asset_file(vtf, "vtf");
+            if u_assets.is_embedded(&Path::new("materials").join(&vtf)) {
+                continue;
+            }
             #[cfg(not(unix))]
-            let path = dir.join(vmt).with_extension("vtf");
+            let path = dir.join(&vtf);
             // Source engine is not case-sensitive, unlike unix-like filesystems
             #[cfg(unix)] 
-            let path = match utils::find_asset_case_insensitive(dir, &PathBuf::from(vmt).with_extension("vtf")) {
+            let path = match utils::find_asset_case_insensitive(dir, &vtf) {
                 Ok(Some(correct_path)) => correct_path,
                 Ok(None) => continue,
                 Err(e) => {
-                    log::warn!(
+                    warn!(
                         "Error searching for asset {} in {}: {}",
-                        vmt,
+                        vtf.display(),
                         dir.display(),
                         e
                     );
@@ -95,3 +91,190 @@ pub fn process(u_assets: &UniqueAssets, materials_dirs: &Vec<PathBuf>) -> Vec<Pa
 
     materials_paths
 }
+
+/// Finds and reads the VMT files of the given materials in every materials directory.
+/// Returns the material names with their found files.
+fn find_vmts(
+    u_assets: &UniqueAssets,
+    materials_dirs: &Vec<PathBuf>,
+    names: &HashSet<PathBuf>,
+) -> Vec<(PathBuf, PathBuf, Vmt)> {
+    let mut vmts = Vec::new();
+
+    for dir in materials_dirs {
+        for name in names {
+            // Packed into the map, nothing to search for.
+            let vmt = asset_file(name, "vmt");
+            if u_assets.is_embedded(&Path::new("materials").join(&vmt)) {
+                continue;
+            }
+
+            #[cfg(not(unix))]
+            let path = dir.join(&vmt);
+            #[cfg(unix)] // Source engine is not case-sensitive, unlike unix-like filesystems
+            let path = match utils::find_asset_case_insensitive(dir, &vmt) {
+                Ok(Some(correct_path)) => correct_path,
+                Ok(None) => {
+                    debug!("Asset not found: {} in {}", vmt.display(), dir.display());
+                    continue;
+                }
+                Err(e) => {
+                    warn!(
+                        "Error searching for asset {} in {}: {}",
+                        vmt.display(),
+                        dir.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            if !path.exists() {
+                continue;
+            }
+
+            // Extract VTF texture names from VMT file content.
+            info!("Extracting VTF texture names from VMT: {}", path.display());
+            match Vmt::read(&path) {
+                Ok(parsed) => {
+                    info!("  Found VTF textures in VMT: {:?}", parsed.textures());
+                    vmts.push((name.clone(), path, parsed));
+                }
+                Err(e) => {
+                    info!("  Error reading VMT: {}", e);
+                    vmts.push((name.clone(), path, Vmt::default()));
+                }
+            }
+        }
+    }
+    vmts
+}
+
+/// Appends `extension` to an asset name, unless it already has it. Unlike
+/// `Path::with_extension`, this keeps dots in names like `glass.v2`.
+pub(crate) fn asset_file(name: &Path, extension: &str) -> PathBuf {
+    if name
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+    {
+        return name.to_path_buf();
+    }
+    let mut file = name.as_os_str().to_owned();
+    file.push(".");
+    file.push(extension);
+    file.into()
+}
+
+/// Path of a material or texture file relative to a content root, e.g. `materials/foo.vtf`.
+pub(crate) fn material_file(name: &Path, extension: &str) -> PathBuf {
+    asset_file(&Path::new("materials").join(name), extension)
+}
+
+/// A problem found in a texture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureProblem {
+    /// The file is empty.
+    Empty,
+    /// The file isn't a VTF, or its header is cut off.
+    InvalidHeader,
+    /// The file is shorter than its header implies.
+    Truncated { expected: usize, actual: usize },
+    /// A dimension isn't a power of two.
+    NotPowerOfTwo,
+    /// A dimension is above the configured limit.
+    TooLarge { limit: u32 },
+}
+
+impl fmt::Display for TextureProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureProblem::Empty => f.write_str("empty file"),
+            TextureProblem::InvalidHeader => f.write_str("not a valid VTF"),
+            TextureProblem::Truncated { expected, actual } => {
+                write!(f, "truncated ({} of {} bytes)", actual, expected)
+            }
+            TextureProblem::NotPowerOfTwo => f.write_str("dimensions aren't powers of two"),
+            TextureProblem::TooLarge { limit } => write!(f, "larger than {}", limit),
+        }
+    }
+}
+
+/// The problems found in a texture file.
+#[derive(Debug, Clone)]
+pub struct TextureReport {
+    pub path: PathBuf,
+    /// The header, when it could be read.
+    pub header: Option<VtfHeader>,
+    pub problems: Vec<TextureProblem>,
+}
+
+/// Reads the header of every VTF in `paths` and checks for empty or truncated files,
+/// dimensions that aren't powers of two and dimensions above `max_size` (0 disables
+/// the size check). Problems are logged as warnings.
+/// Returns the reports of the textures with problems.
+pub fn validate_textures(paths: &[PathBuf], max_size: u32) -> Vec<TextureReport> {
+    let mut reports = Vec::new();
+    let vtf_paths = paths.iter().filter(|path| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("vtf"))
+    });
+
+    for path in vtf_paths {
+        let report = match validate_texture(path, max_size) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("Failed to read texture {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if let Some(header) = &report.header {
+            debug!("{}: {}", path.display(), header);
+        }
+        if !report.problems.is_empty() {
+            let problems: Vec<String> = report.problems.iter().map(ToString::to_string).collect();
+            warn!("Texture {}: {}", path.display(), problems.join(", "));
+            reports.push(report);
+        }
+    }
+
+    info!(
+        "Texture validation finished. {} textures with problems.",
+        reports.len()
+    );
+    reports
+}
+
+fn validate_texture(path: &Path, max_size: u32) -> io::Result<TextureReport> {
+    let actual = usize::try_from(path.metadata()?.len()).unwrap_or(usize::MAX);
+    let mut data = Vec::new();
+    File::open(path)?
+        .take(VTF_HEADER_READ_SIZE)
+        .read_to_end(&mut data)?;
+
+    let header = VtfHeader::read(&data);
+    let mut problems = Vec::new();
+    match &header {
+        _ if actual == 0 => problems.push(TextureProblem::Empty),
+        None => problems.push(TextureProblem::InvalidHeader),
+        Some(header) => {
+            if let Some(expected) = header.expected_file_size()
+                && actual < expected
+            {
+                problems.push(TextureProblem::Truncated { expected, actual });
+            }
+            if !header.is_power_of_two() {
+                problems.push(TextureProblem::NotPowerOfTwo);
+            }
+            let size = u32::from(header.width.max(header.height));
+            if max_size > 0 && size > max_size {
+                problems.push(TextureProblem::TooLarge { limit: max_size });
+            }
+        }
+    }
+
+    Ok(TextureReport {
+        path: path.to_path_buf(),
+        header,
+        problems,
+    })
+}
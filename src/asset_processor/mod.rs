@@ -6,19 +6,58 @@
 
 use log::info;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
 };
 use vbsp::BspResult;
 use vmf_forge::VmfResult;
 
 use crate::BuilderResult;
-use crate::parsers::{bsp, vmf};
+use crate::parsers::{ParseOptions, bsp, vmf};
 use crate::utils;
 
+pub mod companions;
+pub mod detail;
+pub mod embedded;
 pub mod materials;
 pub mod models;
+pub mod particles;
+pub mod propdata;
 pub mod sounds;
+pub mod soundscapes;
+pub mod soundscripts;
+pub mod surfaceprops;
+pub mod vscripts;
+
+/// Definitions tracked by name in `UniqueAssets::asset_parents`, as they aren't files of
+/// their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedAsset {
+    Soundscript,
+    Soundscape,
+    Particle,
+    PropData,
+    SurfaceProp,
+}
+
+/// Key of an asset file in `UniqueAssets::asset_parents`: its path relative to a content
+/// root, lowercase and `/`-separated.
+pub fn asset_key(path: &Path) -> String {
+    crate::pakfile::normalize(&path.to_string_lossy())
+}
+
+/// Key of a named definition (soundscript entry, surface property...) in
+/// `UniqueAssets::asset_parents`, e.g. `soundscript:weather.rain`.
+pub fn named_key(kind: NamedAsset, name: &str) -> String {
+    let kind = match kind {
+        NamedAsset::Soundscript => "soundscript",
+        NamedAsset::Soundscape => "soundscape",
+        NamedAsset::Particle => "particle",
+        NamedAsset::PropData => "propdata",
+        NamedAsset::SurfaceProp => "surfaceprop",
+    };
+    format!("{}:{}", kind, name.trim().to_lowercase())
+}
 
 /// Struct to hold unique asset names found in map files.
 #[derive(Debug, Default)]
@@ -26,11 +65,46 @@ pub struct UniqueAssets {
     pub models_name: HashSet<PathBuf>,
     pub materials_name: HashSet<PathBuf>,
     pub sounds_name: HashSet<PathBuf>,
+    /// Soundscript entry names (lowercase), e.g. `weather.rain`.
+    pub soundscripts_name: HashSet<String>,
+    /// Soundscape names (lowercase) selected by `env_soundscape` entities, keyed by map name.
+    pub soundscapes_name: HashMap<String, HashSet<String>>,
+    /// Particle system names (lowercase) used by entities, keyed by map name.
+    pub particles_name: HashMap<String, HashSet<String>>,
+    /// VScript files, relative to `scripts/vscripts/` and possibly without the `.nut` extension.
+    pub vscripts_name: HashSet<PathBuf>,
+    /// Skins placed for each model (lowercase path), recorded with `ParseOptions::minimal_skins`.
+    /// Models without an entry use every skin.
+    pub model_skins: HashMap<PathBuf, HashSet<i32>>,
+    /// Models (lowercase path) given a skin at runtime that isn't known from the map, which
+    /// use every skin even when they have a `model_skins` entry.
+    pub all_skins_models: HashSet<PathBuf>,
+    /// Prop types and gib sets (lowercase) of `scripts/propdata.txt` used by breakable models.
+    pub propdata_name: HashSet<String>,
+    /// Surface property names (lowercase) used by materials and models (`$surfaceprop`).
+    pub surfaceprops_name: HashSet<String>,
+    /// `detail.vbsp`-style files referenced by worldspawn `detailvbsp`, relative to a content root.
+    pub detail_vbsp_name: HashSet<PathBuf>,
+    /// Files embedded in the pakfile of each parsed BSP, as normalized entry names
+    /// (e.g. `materials/custom/wall.vmt`). References to them are satisfied by the map.
+    pub embedded_files: HashMap<PathBuf, HashSet<String>>,
+    /// The assets referencing each collected dependency (a texture's materials, a material's
+    /// models...), keyed by [`asset_key`] or [`named_key`].
+    pub asset_parents: HashMap<String, HashSet<String>>,
+    /// The maps referencing each asset directly, keyed like `asset_parents`.
+    pub asset_maps: HashMap<String, Vec<PathBuf>>,
+    /// Successfully parsed map files. These aren't assets and aren't counted by `len`.
+    pub map_paths: Vec<PathBuf>,
 }
 
 impl UniqueAssets {
     /// Parses a map directory to find unique assets (models, materials, etc.).
-    pub fn parse_dir(mapdir: &Path, process_vmf: bool, process_bsp: bool) -> BuilderResult<Self> {
+    pub fn parse_dir(
+        mapdir: &Path,
+        process_vmf: bool,
+        process_bsp: bool,
+        options: &ParseOptions,
+    ) -> BuilderResult<Self> {
         let mut u_assets = UniqueAssets::default();
 
         for entry in utils::iter_files(mapdir) {
@@ -38,8 +112,8 @@ impl UniqueAssets {
 
             if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
                 match ext {
-                    "vmf" if process_vmf => u_assets.parse_vmf(path)?, // Parse VMF file
-                    "bsp" if process_bsp => u_assets.parse_bsp(path)?, // Parse BSP file
+                    "vmf" if process_vmf => u_assets.parse_vmf(path, options)?, // Parse VMF file
+                    "bsp" if process_bsp => u_assets.parse_bsp(path, options)?, // Parse BSP file
                     _ => continue,
                 }
             }
@@ -50,19 +124,314 @@ impl UniqueAssets {
         Ok(u_assets)
     }
 
-    pub fn parse_vmf(&mut self, vmf_path: &Path) -> VmfResult<()> {
-        vmf::get_uniques(vmf_path, self)
+    pub fn parse_vmf(&mut self, vmf_path: &Path, options: &ParseOptions) -> VmfResult<()> {
+        self.add_map_assets(vmf_path, |found| vmf::get_uniques(vmf_path, found, options))?;
+        self.add_map_path(vmf_path);
+        Ok(())
+    }
+
+    pub fn parse_bsp(&mut self, bsp_path: &Path, options: &ParseOptions) -> BspResult<()> {
+        self.add_map_assets(bsp_path, |found| bsp::get_uniques(bsp_path, found, options))?;
+        self.add_map_path(bsp_path);
+        Ok(())
     }
 
-    pub fn parse_bsp(&mut self, bsp_path: &Path) -> BspResult<()> {
-        bsp::get_uniques(bsp_path, self)
+    fn add_map_path(&mut self, path: &Path) {
+        if !self.map_paths.iter().any(|p| p == path) {
+            self.map_paths.push(path.to_path_buf());
+        }
+    }
+
+    /// Runs `collect` on an empty set of assets and adds what it found, recording `map`
+    /// as referencing each found asset.
+    pub fn add_map_assets<R>(&mut self, map: &Path, collect: impl FnOnce(&mut Self) -> R) -> R {
+        let mut found = Self::default();
+        let result = collect(&mut found);
+        for key in self.merge(found) {
+            let maps = self.asset_maps.entry(key).or_default();
+            if !maps.iter().any(|p| p == map) {
+                maps.push(map.to_path_buf());
+            }
+        }
+        result
+    }
+
+    /// Runs `collect` on an empty set of assets and adds what it found, recording `parent`
+    /// (an asset path relative to a content root, or a [`named_key`]) as their parent.
+    pub fn add_children<R>(
+        &mut self,
+        parent: impl AsRef<Path>,
+        collect: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let mut found = Self::default();
+        let result = collect(&mut found);
+        for key in self.merge(found) {
+            self.add_parent(key, parent.as_ref());
+        }
+        result
+    }
+
+    /// Records that `parent` references `asset`. Both are asset paths relative to a content
+    /// root or [`named_key`]s.
+    pub fn add_parent(&mut self, asset: impl AsRef<Path>, parent: impl AsRef<Path>) {
+        let asset = asset_key(asset.as_ref());
+        let parent = asset_key(parent.as_ref());
+        if asset != parent {
+            self.asset_parents.entry(asset).or_default().insert(parent);
+        }
+    }
+
+    /// Returns every map whose references led to `asset` (relative to a content root),
+    /// following the recorded parents.
+    pub fn source_maps(&self, asset: impl AsRef<Path>) -> Vec<&Path> {
+        let mut maps: Vec<&Path> = Vec::new();
+        let mut visited: HashSet<&str> = HashSet::new();
+        let key = asset_key(asset.as_ref());
+        let mut queue: Vec<&str> = vec![key.as_str()];
+
+        while let Some(current) = queue.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
+            for map in self.asset_maps.get(current).into_iter().flatten() {
+                if !maps.contains(&map.as_path()) {
+                    maps.push(map);
+                }
+            }
+            if let Some(parents) = self.asset_parents.get(current) {
+                queue.extend(parents.iter().map(String::as_str));
+            }
+        }
+        maps
+    }
+
+    /// Moves the assets of `other` into `self`. Returns the keys of the moved assets.
+    fn merge(&mut self, other: Self) -> Vec<String> {
+        let keys = other.asset_keys();
+        self.models_name.extend(other.models_name);
+        self.materials_name.extend(other.materials_name);
+        self.sounds_name.extend(other.sounds_name);
+        self.soundscripts_name.extend(other.soundscripts_name);
+        for (map, names) in other.soundscapes_name {
+            self.soundscapes_name.entry(map).or_default().extend(names);
+        }
+        for (map, names) in other.particles_name {
+            self.particles_name.entry(map).or_default().extend(names);
+        }
+        self.vscripts_name.extend(other.vscripts_name);
+        for (model, skins) in other.model_skins {
+            self.model_skins.entry(model).or_default().extend(skins);
+        }
+        self.all_skins_models.extend(other.all_skins_models);
+        self.propdata_name.extend(other.propdata_name);
+        self.surfaceprops_name.extend(other.surfaceprops_name);
+        self.detail_vbsp_name.extend(other.detail_vbsp_name);
+        for (map, files) in other.embedded_files {
+            self.embedded_files.entry(map).or_default().extend(files);
+        }
+        for (asset, parents) in other.asset_parents {
+            self.asset_parents.entry(asset).or_default().extend(parents);
+        }
+        for (asset, maps) in other.asset_maps {
+            let known = self.asset_maps.entry(asset).or_default();
+            for map in maps {
+                if !known.contains(&map) {
+                    known.push(map);
+                }
+            }
+        }
+        for path in other.map_paths {
+            self.add_map_path(&path);
+        }
+        keys
+    }
+
+    /// Keys of the collected assets, as used by `asset_parents` and `asset_maps`.
+    fn asset_keys(&self) -> Vec<String> {
+        let paths = self
+            .models_name
+            .iter()
+            .cloned()
+            .chain(
+                self.materials_name
+                    .iter()
+                    .map(|name| materials::material_file(name, "vmt")),
+            )
+            .chain(
+                self.sounds_name
+                    .iter()
+                    .map(|name| Path::new("sound").join(name)),
+            )
+            .chain(
+                self.vscripts_name
+                    .iter()
+                    .map(|name| Path::new(vscripts::VSCRIPTS_DIR).join(name)),
+            )
+            .chain(self.detail_vbsp_name.iter().cloned())
+            .map(|path| asset_key(&path));
+
+        let names = self
+            .soundscripts_name
+            .iter()
+            .map(|name| named_key(NamedAsset::Soundscript, name))
+            .chain(
+                self.soundscapes_name
+                    .values()
+                    .flatten()
+                    .map(|name| named_key(NamedAsset::Soundscape, name)),
+            )
+            .chain(
+                self.particles_name
+                    .values()
+                    .flatten()
+                    .map(|name| named_key(NamedAsset::Particle, name)),
+            )
+            .chain(
+                self.propdata_name
+                    .iter()
+                    .map(|name| named_key(NamedAsset::PropData, name)),
+            )
+            .chain(
+                self.surfaceprops_name
+                    .iter()
+                    .map(|name| named_key(NamedAsset::SurfaceProp, name)),
+            );
+        paths.chain(names).collect()
+    }
+
+    /// Returns whether a file, relative to a content root, is embedded in every map using
+    /// it. Files some of their maps lack are searched for, copied and packed as usual.
+    pub fn is_embedded(&self, path: &Path) -> bool {
+        if self.embedded_files.is_empty() {
+            return false;
+        }
+        let name = crate::pakfile::normalize(&path.to_string_lossy());
+        let maps = self.source_maps(path);
+        !maps.is_empty()
+            && maps.iter().all(|map| {
+                self.embedded_files
+                    .get(*map)
+                    .is_some_and(|files| files.contains(&name))
+            })
     }
 
     pub fn is_empty(&self) -> bool {
-        self.models_name.is_empty() && self.materials_name.is_empty() && self.sounds_name.is_empty()
+        self.models_name.is_empty()
+            && self.materials_name.is_empty()
+            && self.sounds_name.is_empty()
+            && self.soundscripts_name.is_empty()
+            && self.soundscapes_name.is_empty()
+            && self.particles_name.is_empty()
+            && self.vscripts_name.is_empty()
+            && self.detail_vbsp_name.is_empty()
+            && self.propdata_name.is_empty()
+            && self.surfaceprops_name.is_empty()
     }
 
     pub fn len(&self) -> usize {
-        self.models_name.len() + self.materials_name.len() + self.sounds_name.len()
+        self.models_name.len()
+            + self.materials_name.len()
+            + self.sounds_name.len()
+            + self.soundscripts_name.len()
+            + self
+                .soundscapes_name
+                .values()
+                .map(HashSet::len)
+                .sum::<usize>()
+            + self
+                .particles_name
+                .values()
+                .map(HashSet::len)
+                .sum::<usize>()
+            + self.vscripts_name.len()
+            + self.detail_vbsp_name.len()
+            + self.propdata_name.len()
+            + self.surfaceprops_name.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_maps_follow_every_parent() {
+        let mut u_assets = UniqueAssets::default();
+        for map in ["maps/a.vmf", "maps/b.bsp"] {
+            u_assets.add_map_assets(Path::new(map), |found| {
+                found.materials_name.insert("Custom/Wall".into());
+            });
+        }
+        u_assets.add_map_assets(Path::new("maps/c.vmf"), |found| {
+            found.models_name.insert("models/props/crate.mdl".into());
+        });
+        u_assets.add_children("models/props/crate.mdl", |found| {
+            found.materials_name.insert("custom/wall".into());
+            found.surfaceprops_name.insert("wood_crate".into());
+        });
+        u_assets.add_parent("materials/custom/wall.vtf", "materials/custom/wall.vmt");
+
+        let mut maps = u_assets.source_maps("Materials\\Custom\\Wall.vtf");
+        maps.sort();
+        assert_eq!(
+            maps,
+            [
+                Path::new("maps/a.vmf"),
+                Path::new("maps/b.bsp"),
+                Path::new("maps/c.vmf")
+            ]
+        );
+        assert_eq!(
+            u_assets.source_maps(named_key(NamedAsset::SurfaceProp, "Wood_Crate")),
+            [Path::new("maps/c.vmf")]
+        );
+        assert!(u_assets.source_maps("materials/unused.vtf").is_empty());
+    }
+
+    #[test]
+    fn source_maps_stop_at_cycles() {
+        let mut u_assets = UniqueAssets::default();
+        u_assets.add_map_assets(Path::new("maps/a.vmf"), |found| {
+            found.materials_name.insert("custom/cycle_a".into());
+        });
+        u_assets.add_parent(
+            "materials/custom/cycle_a.vmt",
+            "materials/custom/cycle_b.vmt",
+        );
+        u_assets.add_parent(
+            "materials/custom/cycle_b.vmt",
+            "materials/custom/cycle_a.vmt",
+        );
+
+        assert_eq!(
+            u_assets.source_maps("materials/custom/cycle_b.vmt"),
+            [Path::new("maps/a.vmf")]
+        );
+    }
+
+    #[test]
+    fn is_embedded_requires_every_source_map() {
+        let mut u_assets = UniqueAssets::default();
+        for (map, embedded) in [("maps/a.bsp", true), ("maps/b.bsp", false)] {
+            u_assets.add_map_assets(Path::new(map), |found| {
+                found.models_name.insert("models/props/crate.mdl".into());
+                if embedded {
+                    let files = HashSet::from(["models/props/crate.mdl".to_string()]);
+                    found.embedded_files.insert(map.into(), files);
+                }
+            });
+        }
+        u_assets.add_map_assets(Path::new("maps/a.bsp"), |found| {
+            found.sounds_name.insert("ambient/Hum.wav".into());
+            found.embedded_files.insert(
+                "maps/a.bsp".into(),
+                HashSet::from(["sound/ambient/hum.wav".to_string()]),
+            );
+        });
+
+        assert!(!u_assets.is_embedded(Path::new("models/props/crate.mdl")));
+        assert!(u_assets.is_embedded(Path::new("sound/ambient/Hum.wav")));
+        // Files no map uses are never satisfied by one.
+        assert!(!u_assets.is_embedded(Path::new("sound/unused.wav")));
     }
 }
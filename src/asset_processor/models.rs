@@ -1,23 +1,57 @@
-use std::fs;
+use std::{fs, panic};
 
-use super::{PathBuf, UniqueAssets, utils};
-use log::{info, warn};
+use super::propdata::{ModelBreakData, PropData};
+use super::{HashMap, HashSet, Path, PathBuf, UniqueAssets, utils};
+use crate::mdl::MdlHeader;
+use crate::pakfile::Pakfile;
+use crate::parsers::common::{has_sound_suffix, model_key, sound_name};
+use log::{debug, info, warn};
 use vmdl::Mdl; // Crate for parsing MDL files.
 
 // List of model file extensions to check.
 static EXTENSIONS: [&str; 7] = ["vtx", "dx90.vtx", "dx80.vtx", "sw.vtx", "vvd", "phy", "ani"];
 
 /// Processes model assets, finding MDL and associated files.
-/// Extracts material paths from MDL files.
-pub fn process(u_assets: &mut UniqueAssets, models_dirs: &Vec<PathBuf>) -> Vec<PathBuf> {
+/// Extracts material paths from MDL files and follows `$includemodel` references
+/// and the gib models of breakable props. Models embedded in every map using them aren't
+/// copied, but their references are still collected.
+pub fn process(
+    u_assets: &mut UniqueAssets,
+    models_dirs: &Vec<PathBuf>,
+    propdata: &PropData,
+) -> Vec<PathBuf> {
     let mut models_paths: Vec<PathBuf> = Vec::new();
+    let mut queue: Vec<PathBuf> = u_assets.models_name.iter().cloned().collect();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    let mut pakfiles: HashMap<PathBuf, Option<Pakfile>> = HashMap::new();
 
-    for dir in models_dirs {
-        for mdl in &u_assets.models_name {
+    while let Some(mdl) = queue.pop() {
+        if !visited.insert(mdl.clone()) {
+            continue;
+        }
+        // Packed into its maps, only the references are collected.
+        if u_assets.is_embedded(&mdl) {
+            match read_embedded(u_assets, &mdl, models_dirs, &mut pakfiles) {
+                Some((data, phy)) => {
+                    debug!("Reading references of embedded model {}", mdl.display());
+                    queue.extend(add_references(
+                        u_assets,
+                        &mdl,
+                        &data,
+                        phy.as_deref(),
+                        propdata,
+                    ));
+                }
+                None => warn!("Failed to read embedded model {}", mdl.display()),
+            }
+            continue;
+        }
+
+        for dir in models_dirs {
             #[cfg(not(unix))]
-            let path = dir.join(mdl);
+            let path = dir.join(&mdl);
             #[cfg(unix)] // Source engine is not case-sensitive, unlike unix-like filesystems
-            let path = match utils::find_asset_case_insensitive(dir, mdl) {
+            let path = match utils::find_asset_case_insensitive(dir, &mdl) {
                 Ok(Some(correct_path)) => correct_path,
                 Ok(None) => continue,
                 Err(e) => {
@@ -38,35 +72,250 @@ pub fn process(u_assets: &mut UniqueAssets, models_dirs: &Vec<PathBuf>) -> Vec<P
                 let new_path = path.with_extension(ext);
                 if new_path.exists() {
                     info!("Found associated model file: {}", new_path.display());
+                    u_assets.add_parent(utils::folder_relative_path(&new_path, "models"), &mdl);
                     models_paths.push(new_path);
                 }
             }
 
-            // Process materials from MDL file
             let data = fs::read(&path).unwrap_or_default();
-            if let Ok(info) = Mdl::read(&data) {
-                // todo [panic]: not yet implemented: read animation from animation block
-                for info in info.textures {
-                    for up in info.search_paths {
-                        let relative_path = PathBuf::from(up).join(&info.name);
-                        info!(
-                            "Extracted material path from MDL: {}",
-                            relative_path.display()
-                        );
-                        u_assets.materials_name.insert(relative_path);
-                    }
-                }
-            } else {
-                warn!("Error parsing MDL file or no materials found.");
-            }
-
+            let phy = fs::read(path.with_extension("phy")).ok();
+            queue.extend(add_references(
+                u_assets,
+                &mdl,
+                &data,
+                phy.as_deref(),
+                propdata,
+            ));
             models_paths.push(path);
         }
     }
 
+    u_assets.models_name.extend(visited);
     info!(
         "Model processing finished. Found {} model paths.",
         models_paths.len()
     );
     models_paths
 }
+
+/// Collects what a model references: materials, event sounds, surface property, included
+/// models and gibs. Returns the models to process next (includes and gibs).
+fn add_references(
+    u_assets: &mut UniqueAssets,
+    mdl: &Path,
+    data: &[u8],
+    phy: Option<&[u8]>,
+    propdata: &PropData,
+) -> Vec<PathBuf> {
+    let mut models = Vec::new();
+    let header = MdlHeader::read(data);
+    for include in header.iter().flat_map(|header| &header.include_models) {
+        debug!("Found included model: {}", include);
+        u_assets.add_parent(Path::new(include), mdl);
+        models.push(include.into());
+    }
+    let key = model_key(&mdl.to_string_lossy());
+    let skins = u_assets
+        .model_skins
+        .get(&key)
+        .filter(|_| !u_assets.all_skins_models.contains(&key));
+    let materials = match (&header, skins) {
+        // Only the skins placed in the maps, see `ParseOptions::minimal_skins`.
+        (Some(header), Some(skins)) => {
+            debug!("Using skins {:?} of {}", skins, mdl.display());
+            Some(header.skin_materials(skins))
+        }
+        _ => read_materials(data, header.as_ref()),
+    };
+    let break_data = ModelBreakData::read(
+        header
+            .as_ref()
+            .and_then(|header| header.key_values.as_deref()),
+        phy,
+    );
+    let gibs = u_assets.add_children(mdl, |found| {
+        if let Some(materials) = materials {
+            for relative_path in materials {
+                info!(
+                    "Extracted material path from MDL: {}",
+                    relative_path.display()
+                );
+                found.materials_name.insert(relative_path);
+            }
+        } else {
+            warn!("Error parsing MDL file or no materials found.");
+        }
+        if let Some(header) = &header {
+            add_event_sounds(header, found);
+            if !header.surface_prop.is_empty() {
+                found
+                    .surfaceprops_name
+                    .insert(header.surface_prop.to_lowercase());
+            }
+        }
+        gib_models(&break_data, propdata, found)
+    });
+    for gib in gibs {
+        u_assets.add_parent(&gib, mdl);
+        models.push(gib);
+    }
+    models
+}
+
+/// Reads an embedded model and its `.phy` file from a loose copy in the models
+/// directories, or else from the pakfile of a map using it. `pakfiles` caches the
+/// pakfiles read so far.
+fn read_embedded(
+    u_assets: &UniqueAssets,
+    mdl: &Path,
+    models_dirs: &[PathBuf],
+    pakfiles: &mut HashMap<PathBuf, Option<Pakfile>>,
+) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    for dir in models_dirs {
+        #[cfg(not(unix))]
+        let path = dir.join(mdl);
+        #[cfg(unix)] // Source engine is not case-sensitive, unlike unix-like filesystems
+        let Ok(Some(path)) = utils::find_asset_case_insensitive(dir, mdl) else {
+            continue;
+        };
+        if let Ok(data) = fs::read(&path) {
+            return Some((data, fs::read(path.with_extension("phy")).ok()));
+        }
+    }
+
+    let name = mdl.to_string_lossy();
+    for map in u_assets.source_maps(mdl) {
+        let pakfile = pakfiles
+            .entry(map.to_path_buf())
+            .or_insert_with(|| fs::read(map).ok().and_then(|data| Pakfile::read(&data)));
+        if let Some(pakfile) = pakfile
+            && let Some(data) = pakfile.get(&name)
+        {
+            let phy = pakfile.get(&mdl.with_extension("phy").to_string_lossy());
+            return Some((data, phy));
+        }
+    }
+    None
+}
+
+/// Adds the sounds played by the model's animation events. Waves go to the sounds,
+/// anything else is treated as a soundscript entry.
+fn add_event_sounds(header: &MdlHeader, u_assets: &mut UniqueAssets) {
+    for sound in header.sound_events() {
+        if has_sound_suffix(sound) {
+            debug!("Found sound in model event: {}", sound);
+            u_assets.sounds_name.insert(sound_name(sound));
+        } else {
+            debug!("Found soundscript entry in model event: {}", sound);
+            u_assets.soundscripts_name.insert(sound.to_lowercase());
+        }
+    }
+}
+
+/// Returns the gib models of a breakable model, from its `break` sections and the gib sets
+/// of its prop data. Records the used prop data names.
+fn gib_models(
+    break_data: &ModelBreakData,
+    propdata: &PropData,
+    u_assets: &mut UniqueAssets,
+) -> Vec<PathBuf> {
+    let mut gibs = break_data.gibs.clone();
+    for name in &break_data.propdata_names {
+        debug!("Found prop data: {}", name);
+        u_assets.propdata_name.insert(name.clone());
+        gibs.extend(propdata.gibs(name));
+    }
+    gibs.into_iter()
+        .inspect(|gib| debug!("Found gib model: {}", gib))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Reads the material paths of a model with `vmdl`, falling back to the in-crate header
+/// reader when `vmdl` fails or panics (it does on some animation blocks). The default panic
+/// hook is silenced for the call, so a caught panic doesn't print a backtrace.
+fn read_materials(data: &[u8], header: Option<&MdlHeader>) -> Option<Vec<PathBuf>> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(|| Mdl::read(data));
+    panic::set_hook(hook);
+
+    match result {
+        Ok(Ok(info)) => {
+            let mut materials = Vec::new();
+            for info in info.textures {
+                for up in info.search_paths {
+                    materials.push(PathBuf::from(up).join(&info.name));
+                }
+            }
+            Some(materials)
+        }
+        Ok(Err(e)) => {
+            debug!(
+                "vmdl failed to read the model ({}), using the header reader.",
+                e
+            );
+            header.map(MdlHeader::materials)
+        }
+        Err(_) => {
+            warn!("vmdl panicked while reading the model, using the header reader.");
+            header.map(MdlHeader::materials)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::lump;
+
+    /// Writes a BSP embedding `files` (entry name, data) into the temporary directory.
+    fn write_map(test: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("models-test-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        let mut entries = Vec::new();
+        for (name, data) in files {
+            let path = dir.join(Path::new(name).file_name().unwrap());
+            fs::write(&path, data).unwrap();
+            entries.push((name.to_string(), path));
+        }
+        let mut data = vec![0; 8 + lump::HEADER_LUMPS * 16];
+        data[..4].copy_from_slice(b"VBSP");
+        data[4..8].copy_from_slice(&20i32.to_le_bytes());
+        let map = dir.join("map.bsp");
+        fs::write(&map, crate::pakfile::write_bsp(&data, &entries).unwrap()).unwrap();
+        map
+    }
+
+    #[test]
+    fn read_embedded_prefers_loose_copies_over_the_pakfile() {
+        let map = write_map(
+            "embedded",
+            &[
+                ("Models/Props/Crate.mdl", b"IDST packed"),
+                ("models/props/crate.phy", b"VPHY packed"),
+            ],
+        );
+        let mdl = Path::new("models/props/crate.mdl");
+        let mut u_assets = UniqueAssets::default();
+        u_assets.add_map_assets(&map, |found| {
+            found.models_name.insert(mdl.into());
+        });
+        let mut pakfiles = HashMap::new();
+
+        let (data, phy) = read_embedded(&u_assets, mdl, &[], &mut pakfiles).unwrap();
+        assert_eq!(data, b"IDST packed");
+        assert_eq!(phy.as_deref(), Some(&b"VPHY packed"[..]));
+        assert!(
+            read_embedded(&u_assets, Path::new("models/other.mdl"), &[], &mut pakfiles).is_none()
+        );
+
+        let content = map.parent().unwrap().join("content");
+        fs::create_dir_all(content.join("models/props")).unwrap();
+        fs::write(content.join(mdl), b"IDST loose").unwrap();
+        let (data, phy) = read_embedded(&u_assets, mdl, &[content], &mut pakfiles).unwrap();
+        assert_eq!(data, b"IDST loose");
+        assert_eq!(phy, None);
+        fs::remove_dir_all(map.parent().unwrap()).unwrap();
+    }
+}
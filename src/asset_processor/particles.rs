@@ -0,0 +1,330 @@
+//! Resolution of particle systems to the `.pcf` files defining them.
+//!
+//! Particle files are loaded from `particles/particles_manifest.txt` and from every `.pcf`
+//! found under `particles/` in the content roots. Materials and models used by a system's
+//! renderers and operators are added to the unique assets, and child systems are followed.
+
+use super::{NamedAsset, Path, PathBuf, UniqueAssets, named_key, utils};
+use crate::dmx::{self, Attribute, Dmx};
+use crate::keyvalues::{self, KeyValue};
+use crate::parsers::common::material_name;
+use log::{debug, info, trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::{fs, io};
+
+/// Path of the particle manifest, relative to a content root.
+const MANIFEST: &str = "particles/particles_manifest.txt";
+
+/// A particle system definition read from a PCF.
+#[derive(Debug, Clone, Default)]
+pub struct ParticleSystem {
+    /// Lowercase name of the system.
+    pub name: String,
+    /// The PCF defining the system.
+    pub file: PathBuf,
+    /// Materials used by the system, relative to `materials/`.
+    pub materials: Vec<PathBuf>,
+    /// Models used by the system (model renderers, emitters).
+    pub models: Vec<PathBuf>,
+    /// Lowercase names of the child systems.
+    pub children: Vec<String>,
+}
+
+/// The particle files used by a single map.
+#[derive(Debug, Clone)]
+pub struct MapParticles {
+    /// Lowercase map name.
+    pub map: String,
+    pub files: Vec<PathBuf>,
+}
+
+/// Particle systems available in the content roots, keyed by lowercase name.
+#[derive(Debug, Default)]
+pub struct ParticleSystems {
+    systems: HashMap<String, ParticleSystem>,
+}
+
+impl ParticleSystems {
+    /// Loads every particle file of the content roots. The first definition of a system wins.
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let mut systems = Self::default();
+        for path in particle_files(roots) {
+            systems.add_file(&path);
+        }
+        systems
+    }
+
+    /// Returns the system with the given name (case-insensitive).
+    pub fn get(&self, name: &str) -> Option<&ParticleSystem> {
+        self.systems.get(&name.to_lowercase())
+    }
+
+    /// Resolves `names` and all of their child systems.
+    pub fn resolve<'a>(
+        &'a self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<&'a ParticleSystem> {
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut used: Vec<&ParticleSystem> = Vec::new();
+        let mut queue: Vec<String> = names.into_iter().map(str::to_lowercase).collect();
+
+        while let Some(name) = queue.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let Some(system) = self.systems.get(&name) else {
+                warn!("Particle system \"{}\" not found.", name);
+                continue;
+            };
+            used.push(system);
+            queue.extend(system.children.iter().cloned());
+        }
+        used
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to read particle file {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let Some(dmx) = dmx::read(&data) else {
+            warn!("Unsupported or malformed particle file: {}", path.display());
+            return;
+        };
+
+        for (name, system) in read_systems(&dmx, path) {
+            if self.systems.contains_key(&name) {
+                trace!("Particle system {} redefined in {}", name, path.display());
+                continue;
+            }
+            self.systems.insert(name, system);
+        }
+    }
+}
+
+/// Processes the particle systems used by each map.
+///
+/// Adds the materials and models of the used systems to the unique assets, so this has to
+/// run before the model and material passes. Returns the particle files used by each map.
+pub fn process(u_assets: &mut UniqueAssets, roots: &[PathBuf]) -> Vec<MapParticles> {
+    if u_assets.particles_name.is_empty() {
+        return Vec::new();
+    }
+
+    let systems = ParticleSystems::load(roots);
+    let mut maps: Vec<(String, HashSet<String>)> =
+        u_assets.particles_name.clone().into_iter().collect();
+    maps.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut map_particles: Vec<MapParticles> = Vec::new();
+
+    for (map, names) in maps {
+        let mut files: Vec<PathBuf> = Vec::new();
+        for system in systems.resolve(names.iter().map(String::as_str)) {
+            add_system_assets(system, u_assets, roots);
+            if !files.contains(&system.file) {
+                files.push(system.file.clone());
+            }
+        }
+
+        if !files.is_empty() {
+            debug!("Map {} uses {} particle files.", map, files.len());
+            map_particles.push(MapParticles { map, files });
+        }
+    }
+
+    info!(
+        "Particle processing finished. Found {} particle files.",
+        files(&map_particles).len()
+    );
+    map_particles
+}
+
+/// Adds the materials and models of a system to the unique assets. The system is recorded
+/// as the parent of them, of its file and of its child systems.
+fn add_system_assets(system: &ParticleSystem, u_assets: &mut UniqueAssets, roots: &[PathBuf]) {
+    let key = named_key(NamedAsset::Particle, &system.name);
+    if let Some(file) = utils::root_relative_path(&system.file, roots) {
+        u_assets.add_parent(file, &key);
+    }
+    for child in &system.children {
+        u_assets.add_parent(named_key(NamedAsset::Particle, child), &key);
+    }
+    u_assets.add_children(&key, |found| {
+        found
+            .materials_name
+            .extend(system.materials.iter().cloned());
+        found.models_name.extend(system.models.iter().cloned());
+    });
+}
+
+/// Returns the distinct particle files used by all maps.
+pub fn files(map_particles: &[MapParticles]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for file in map_particles.iter().flat_map(|m| &m.files) {
+        if !files.contains(file) {
+            files.push(file.clone());
+        }
+    }
+    files
+}
+
+/// Writes a `maps/<map>_particles.txt` manifest listing the particle files of every map.
+pub fn write_map_manifests(
+    map_particles: &[MapParticles],
+    roots: &[PathBuf],
+    output_dir: &Path,
+) -> io::Result<()> {
+    for map in map_particles {
+        let entries: Vec<KeyValue> = map
+            .files
+            .iter()
+            .filter_map(|file| utils::root_relative_path(file, roots))
+            .map(|file| KeyValue {
+                key: "file".to_string(),
+                value: keyvalues::Value::String(file.to_string_lossy().replace('\\', "/")),
+                condition: None,
+            })
+            .collect();
+        let manifest = KeyValue {
+            key: "particles_manifest".to_string(),
+            value: keyvalues::Value::Block(entries),
+            condition: None,
+        };
+
+        let destination = output_dir
+            .join("maps")
+            .join(format!("{}_particles.txt", map.map));
+        if let Some(parent_dir) = destination.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        fs::write(destination, keyvalues::to_string(&[manifest]))?;
+    }
+    Ok(())
+}
+
+/// Returns the particle files of the content roots: manifest entries first, in order,
+/// then any other `.pcf` under `particles/`.
+fn particle_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for manifest in roots
+        .iter()
+        .filter_map(|root| utils::find_file(root, Path::new(MANIFEST)))
+    {
+        let kv = match keyvalues::parse_file(&manifest) {
+            Ok(kv) => kv,
+            Err(e) => {
+                warn!("Failed to read {}: {}", manifest.display(), e);
+                continue;
+            }
+        };
+
+        let entries = kv
+            .iter()
+            .flat_map(KeyValue::children)
+            .filter(|kv| kv.key.eq_ignore_ascii_case("file") && kv.is_enabled())
+            .filter_map(KeyValue::as_str);
+        for file in entries {
+            // `!` marks files that are preloaded.
+            let file = file.trim().trim_start_matches('!').replace('\\', "/");
+            let found = roots
+                .iter()
+                .find_map(|root| utils::find_file(root, Path::new(&file)));
+            match found {
+                Some(path) if !files.contains(&path) => files.push(path),
+                Some(_) => {}
+                None => trace!("Particle file {} listed in manifest not found.", file),
+            }
+        }
+    }
+
+    for dir in roots
+        .iter()
+        .filter_map(|root| utils::find_dir(root, "particles"))
+    {
+        let mut found: Vec<PathBuf> = utils::iter_files(&dir)
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("pcf"))
+            })
+            .filter(|path| !files.contains(path))
+            .collect();
+        found.sort();
+        files.extend(found);
+    }
+
+    files
+}
+
+/// Reads the particle system definitions of a PCF, keyed by lowercase name.
+fn read_systems(dmx: &Dmx, path: &Path) -> Vec<(String, ParticleSystem)> {
+    let is_definition = |index: usize| {
+        dmx.elements[index]
+            .class
+            .eq_ignore_ascii_case("DmeParticleSystemDefinition")
+    };
+
+    let mut systems = Vec::new();
+    for (index, element) in dmx.elements.iter().enumerate() {
+        if !is_definition(index) {
+            continue;
+        }
+
+        let mut system = ParticleSystem {
+            name: element.name.to_lowercase(),
+            file: path.to_path_buf(),
+            ..Default::default()
+        };
+
+        // Walk operators, renderers and child references, stopping at other definitions.
+        let mut visited: HashSet<usize> = HashSet::from([index]);
+        let mut queue: Vec<usize> = vec![index];
+        while let Some(current) = queue.pop() {
+            for (name, attr) in &dmx.elements[current].attributes {
+                add_string_assets(name, attr, &mut system);
+            }
+            for reference in dmx.elements[current].references() {
+                if is_definition(reference) {
+                    let child = dmx.elements[reference].name.to_lowercase();
+                    if reference != index && !system.children.contains(&child) {
+                        system.children.push(child);
+                    }
+                } else if visited.insert(reference) {
+                    queue.push(reference);
+                }
+            }
+        }
+
+        systems.push((system.name.clone(), system));
+    }
+    systems
+}
+
+/// Adds the materials and models referenced by a string attribute.
+fn add_string_assets(name: &str, attr: &Attribute, system: &mut ParticleSystem) {
+    let values: &[String] = match attr {
+        Attribute::String(value) => std::slice::from_ref(value),
+        Attribute::Strings(values) => values,
+        _ => return,
+    };
+
+    for value in values.iter().map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let lower = value.to_ascii_lowercase();
+        if lower.ends_with(".mdl") {
+            let model = PathBuf::from(value.replace('\\', "/"));
+            if !system.models.contains(&model) {
+                system.models.push(model);
+            }
+        } else if name.to_ascii_lowercase().contains("material") {
+            let material = material_name(value);
+            if !system.materials.contains(&material) {
+                system.materials.push(material);
+            }
+        }
+    }
+}
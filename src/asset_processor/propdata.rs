@@ -0,0 +1,171 @@
+//! Breakable prop data: the `prop_data` and `break` sections of model keyvalues, and
+//! the prop types and gib sets of `scripts/propdata.txt`.
+
+use super::{NamedAsset, Path, PathBuf, UniqueAssets, named_key, utils};
+use crate::keyvalues::{self, KeyValue};
+use crate::mdl;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+
+/// Path of the prop data file, relative to a content root.
+const PROPDATA: &str = "scripts/propdata.txt";
+
+/// A `propdata.txt` of a content root.
+#[derive(Debug, Clone, Default)]
+pub struct PropDataFile {
+    pub path: PathBuf,
+    /// Lowercase names of the prop types (`sections`), mapped to their `breakable_model` set.
+    pub sections: HashMap<String, Option<String>>,
+    /// Lowercase names of the gib sets (`BreakableModels`), mapped to their models.
+    pub breakable_models: HashMap<String, Vec<String>>,
+}
+
+/// The prop data files of the content roots, in root order.
+#[derive(Debug, Default)]
+pub struct PropData {
+    files: Vec<PropDataFile>,
+}
+
+impl PropData {
+    /// Loads `scripts/propdata.txt` from every content root.
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let files = roots
+            .iter()
+            .filter_map(|root| utils::find_file(root, Path::new(PROPDATA)))
+            .filter_map(|path| PropDataFile::read(&path))
+            .collect();
+        Self { files }
+    }
+
+    /// Returns the gib models of a prop type or gib set name (case-insensitive).
+    pub fn gibs(&self, name: &str) -> Vec<String> {
+        let name = name.to_lowercase();
+        let set = self
+            .files
+            .iter()
+            .find_map(|file| file.sections.get(&name).cloned().flatten())
+            .unwrap_or(name);
+        self.files
+            .iter()
+            .find_map(|file| file.breakable_models.get(&set.to_lowercase()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the first file defining the prop type or gib set `name`.
+    pub fn defining_file(&self, name: &str) -> Option<&Path> {
+        let name = name.to_lowercase();
+        self.files
+            .iter()
+            .find(|file| {
+                file.sections.contains_key(&name) || file.breakable_models.contains_key(&name)
+            })
+            .map(|file| file.path.as_path())
+    }
+}
+
+impl PropDataFile {
+    fn read(path: &Path) -> Option<Self> {
+        let kv = match keyvalues::parse_file(path) {
+            Ok(kv) => kv,
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let mut file = PropDataFile {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        for block in kv.iter().flat_map(KeyValue::children) {
+            if block.key.eq_ignore_ascii_case("sections") {
+                for section in block.children() {
+                    let set = section.get_str("breakable_model").map(str::to_lowercase);
+                    file.sections.insert(section.key.to_lowercase(), set);
+                }
+            } else if block.key.eq_ignore_ascii_case("BreakableModels") {
+                for set in block.children() {
+                    let models = set
+                        .children()
+                        .iter()
+                        .map(|model| mdl::model_name(&model.key))
+                        .collect();
+                    file.breakable_models.insert(set.key.to_lowercase(), models);
+                }
+            }
+        }
+        Some(file)
+    }
+}
+
+/// Breakable data of a model.
+#[derive(Debug, Clone, Default)]
+pub struct ModelBreakData {
+    /// Gib models of `break` sections.
+    pub gibs: Vec<String>,
+    /// Prop types (`base`) and gib sets (`breakable_model`) used by `prop_data`.
+    pub propdata_names: Vec<String>,
+}
+
+impl ModelBreakData {
+    /// Reads the `prop_data` and `break` sections of a model's keyvalues and of the text
+    /// section of its `.phy` (`$collisiontext`).
+    pub fn read(key_values: Option<&str>, phy: Option<&[u8]>) -> Self {
+        let mut data = Self::default();
+        let phy_text = phy.and_then(mdl::read_phy_text);
+        for text in key_values.into_iter().chain(phy_text.as_deref()) {
+            for entry in keyvalues::parse(text) {
+                entry.visit(&mut |kv| data.add_section(kv));
+            }
+        }
+        data
+    }
+
+    fn add_section(&mut self, kv: &KeyValue) {
+        if kv.key.eq_ignore_ascii_case("break") {
+            if let Some(model) = kv.get_str("model").filter(|m| !m.trim().is_empty()) {
+                self.gibs.push(mdl::model_name(model));
+            }
+        } else if kv.key.eq_ignore_ascii_case("prop_data") {
+            for key in ["base", "breakable_model"] {
+                if let Some(name) = kv.get_str(key).filter(|n| !n.trim().is_empty()) {
+                    self.propdata_names.push(name.trim().to_lowercase());
+                }
+            }
+        }
+    }
+}
+
+/// Finds the `propdata.txt` files defining the prop types and gib sets used by models.
+/// The stock file lives in the game's VPKs, which aren't searched, so names found in a
+/// loose `propdata.txt` are custom ones. Returns the paths of the found files.
+pub fn process(
+    u_assets: &mut UniqueAssets,
+    propdata: &PropData,
+    roots: &[PathBuf],
+) -> Vec<PathBuf> {
+    if u_assets.propdata_name.is_empty() {
+        return Vec::new();
+    }
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for name in u_assets.propdata_name.clone() {
+        let Some(path) = propdata.defining_file(&name) else {
+            debug!("Prop data \"{}\" not found, assuming a stock one.", name);
+            continue;
+        };
+        if let Some(file) = utils::root_relative_path(path, roots) {
+            u_assets.add_parent(file, named_key(NamedAsset::PropData, &name));
+        }
+        if !paths.iter().any(|p| p == path) {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    info!(
+        "Prop data processing finished. Found {} propdata files.",
+        paths.len()
+    );
+    paths
+}
@@ -1,4 +1,4 @@
-use super::{PathBuf, UniqueAssets, utils};
+use super::{Path, PathBuf, UniqueAssets, utils};
 
 /// Processes sound assets, finding sound files.
 pub fn process(u_assets: &UniqueAssets, sounds_dirs: &Vec<PathBuf>) -> Vec<PathBuf> {
@@ -6,6 +6,10 @@ pub fn process(u_assets: &UniqueAssets, sounds_dirs: &Vec<PathBuf>) -> Vec<PathB
 
     for dir in sounds_dirs {
         for sound in &u_assets.sounds_name {
+            // Packed into the map, nothing to search for.
+            if u_assets.is_embedded(&Path::new("sound").join(sound)) {
+                continue;
+            }
             #[cfg(not(unix))]
             let path = dir.join(sound);
             #[cfg(unix)] // Source engine is not case-sensitive, unlike unix-like filesystems
@@ -0,0 +1,221 @@
+//! Resolution of soundscapes selected by `env_soundscape` entities.
+//!
+//! Soundscapes are loaded from the files listed in `scripts/soundscapes_manifest.txt` and
+//! from every `scripts/soundscapes_*.txt`. The soundscapes used by a map are written to
+//! `scripts/soundscapes_<map>.txt`, which the engine loads together with the map.
+
+use super::{NamedAsset, Path, PathBuf, UniqueAssets, named_key, utils};
+use crate::keyvalues::{self, KeyValue};
+use crate::parsers::common::sound_name;
+use log::{debug, info, trace, warn};
+use std::collections::HashMap;
+use std::{fs, io};
+
+/// Path of the soundscape manifest, relative to a content root.
+const MANIFEST: &str = "scripts/soundscapes_manifest.txt";
+
+/// The soundscapes used by a single map, including nested ones.
+#[derive(Debug, Clone)]
+pub struct MapSoundscapes {
+    /// Lowercase map name.
+    pub map: String,
+    /// Soundscape definitions, as found in the game's soundscape files.
+    pub soundscapes: Vec<KeyValue>,
+}
+
+/// Soundscape definitions available in the content roots, keyed by lowercase name.
+#[derive(Debug, Default)]
+pub struct Soundscapes {
+    entries: HashMap<String, KeyValue>,
+}
+
+impl Soundscapes {
+    /// Loads every soundscape file of the content roots. The first definition wins.
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let mut soundscapes = Self::default();
+        for path in soundscape_files(roots) {
+            soundscapes.add_file(&path);
+        }
+        soundscapes
+    }
+
+    /// Returns the soundscape with the given name (case-insensitive).
+    pub fn get(&self, name: &str) -> Option<&KeyValue> {
+        self.entries.get(&name.to_lowercase())
+    }
+
+    /// Resolves `names` and every soundscape they play through `playsoundscape`.
+    /// The result is sorted by name.
+    pub fn resolve<'a>(&'a self, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a KeyValue> {
+        let mut used: Vec<&KeyValue> = Vec::new();
+        let mut queue: Vec<String> = names.into_iter().map(str::to_lowercase).collect();
+
+        while let Some(name) = queue.pop() {
+            let Some(soundscape) = self.get(&name) else {
+                warn!("Soundscape \"{}\" not found.", name);
+                continue;
+            };
+            if used.iter().any(|s| std::ptr::eq(*s, soundscape)) {
+                continue;
+            }
+            used.push(soundscape);
+            queue.extend(nested_soundscapes(soundscape));
+        }
+
+        used.sort_by_key(|s| s.key.to_lowercase());
+        used
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        let kv = match keyvalues::parse_file(path) {
+            Ok(kv) => kv,
+            Err(e) => {
+                warn!("Failed to read soundscape file {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        for entry in kv.into_iter().filter(|kv| !kv.children().is_empty()) {
+            let name = entry.key.to_lowercase();
+            if self.entries.contains_key(&name) {
+                trace!("Soundscape {} redefined in {}", name, path.display());
+                continue;
+            }
+            self.entries.insert(name, entry);
+        }
+    }
+}
+
+/// Processes the soundscapes used by each map.
+///
+/// Adds the waves and soundscript entries played by the used soundscapes (and the ones they
+/// nest) to the unique assets, so this has to run before the soundscript and sound passes.
+/// Returns the soundscapes of each map that uses any.
+pub fn process(u_assets: &mut UniqueAssets, roots: &[PathBuf]) -> Vec<MapSoundscapes> {
+    if u_assets.soundscapes_name.is_empty() {
+        return Vec::new();
+    }
+
+    let soundscapes = Soundscapes::load(roots);
+    let mut maps: Vec<(&String, _)> = u_assets.soundscapes_name.iter().collect();
+    maps.sort_by_key(|(map, _)| *map);
+
+    let mut map_soundscapes: Vec<MapSoundscapes> = Vec::new();
+    for (map, names) in maps {
+        let used = soundscapes.resolve(names.iter().map(String::as_str));
+        if used.is_empty() {
+            continue;
+        }
+        debug!("Map {} uses {} soundscapes.", map, used.len());
+        map_soundscapes.push(MapSoundscapes {
+            map: map.clone(),
+            soundscapes: used.into_iter().cloned().collect(),
+        });
+    }
+
+    for soundscape in map_soundscapes.iter().flat_map(|m| &m.soundscapes) {
+        let key = named_key(NamedAsset::Soundscape, &soundscape.key);
+        for nested in nested_soundscapes(soundscape) {
+            u_assets.add_parent(named_key(NamedAsset::Soundscape, &nested), &key);
+        }
+        u_assets.add_children(&key, |found| add_sounds(soundscape, found));
+    }
+
+    info!(
+        "Soundscape processing finished. Found soundscapes for {} maps.",
+        map_soundscapes.len()
+    );
+    map_soundscapes
+}
+
+/// Writes `scripts/soundscapes_<map>.txt` for every map.
+pub fn write_map_files(map_soundscapes: &[MapSoundscapes], output_dir: &Path) -> io::Result<()> {
+    for map in map_soundscapes {
+        let destination = output_dir
+            .join("scripts")
+            .join(format!("soundscapes_{}.txt", map.map));
+        if let Some(parent_dir) = destination.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        fs::write(destination, keyvalues::to_string(&map.soundscapes))?;
+    }
+    Ok(())
+}
+
+/// Returns the soundscape files of the content roots: manifest entries first, in order,
+/// then any other `scripts/soundscapes_*.txt`.
+fn soundscape_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for manifest in roots
+        .iter()
+        .filter_map(|root| utils::find_file(root, Path::new(MANIFEST)))
+    {
+        let kv = match keyvalues::parse_file(&manifest) {
+            Ok(kv) => kv,
+            Err(e) => {
+                warn!("Failed to read {}: {}", manifest.display(), e);
+                continue;
+            }
+        };
+
+        let entries = kv
+            .iter()
+            .flat_map(KeyValue::children)
+            .filter(|kv| kv.key.eq_ignore_ascii_case("file") && kv.is_enabled())
+            .filter_map(KeyValue::as_str);
+        for file in entries {
+            let file = file.trim().replace('\\', "/");
+            let found = roots
+                .iter()
+                .find_map(|root| utils::find_file(root, Path::new(&file)));
+            match found {
+                Some(path) if !files.contains(&path) => files.push(path),
+                Some(_) => {}
+                None => trace!("Soundscape file {} listed in manifest not found.", file),
+            }
+        }
+    }
+
+    for path in utils::find_scripts(roots, "soundscapes_") {
+        let is_manifest = path
+            .file_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case("soundscapes_manifest.txt"));
+        if !is_manifest && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// Returns the names of the soundscapes played through `playsoundscape` blocks.
+fn nested_soundscapes(soundscape: &KeyValue) -> Vec<String> {
+    soundscape
+        .children()
+        .iter()
+        .filter(|kv| kv.key.eq_ignore_ascii_case("playsoundscape") && kv.is_enabled())
+        .filter_map(|kv| kv.get_str("name"))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Adds the `wave` files and `soundname` soundscript entries played by a soundscape.
+fn add_sounds(soundscape: &KeyValue, u_assets: &mut UniqueAssets) {
+    soundscape.visit(&mut |kv| {
+        if !kv.is_enabled() {
+            return;
+        }
+        let Some(value) = kv.as_str().map(str::trim).filter(|v| !v.is_empty()) else {
+            return;
+        };
+
+        if kv.key.eq_ignore_ascii_case("wave") {
+            trace!("Found soundscape wave: {}", value);
+            u_assets.sounds_name.insert(sound_name(value));
+        } else if kv.key.eq_ignore_ascii_case("soundname") {
+            trace!("Found soundscape soundscript entry: {}", value);
+            u_assets.soundscripts_name.insert(value.to_lowercase());
+        }
+    });
+}
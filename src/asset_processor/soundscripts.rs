@@ -0,0 +1,294 @@
+//! Resolution of soundscript entries (`Weather.Rain`, `Door.Open`, ...) to wave files.
+//!
+//! Soundscripts are loaded from the files listed in `scripts/game_sounds_manifest.txt`
+//! and from every `scripts/game_sounds_*.txt` found in the content roots. For packed maps,
+//! the entries each map uses are written to `maps/<map>_level_sounds.txt`, which the engine
+//! loads together with the map.
+
+use super::{NamedAsset, Path, PathBuf, UniqueAssets, named_key, utils};
+use crate::keyvalues::{self, KeyValue};
+use crate::parsers::common::{map_name, sound_name};
+use log::{debug, info, trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::{fs, io};
+
+/// Path of the soundscript manifest, relative to a content root.
+const MANIFEST: &str = "scripts/game_sounds_manifest.txt";
+
+/// A single soundscript entry.
+#[derive(Debug, Clone)]
+pub struct SoundEntry {
+    /// The soundscript file defining the entry.
+    pub file: PathBuf,
+    /// Waves played by the entry, relative to `sound/`.
+    pub waves: Vec<PathBuf>,
+    /// The entry's definition, as found in the file.
+    pub definition: KeyValue,
+}
+
+/// Soundscript entries available in the content roots, keyed by lowercase name.
+#[derive(Debug, Default)]
+pub struct Soundscripts {
+    entries: HashMap<String, SoundEntry>,
+}
+
+impl Soundscripts {
+    /// Loads every soundscript file of the content roots.
+    /// Like the engine, the first definition of an entry wins.
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let mut scripts = Self::default();
+        for path in soundscript_files(roots) {
+            scripts.add_file(&path);
+        }
+        scripts
+    }
+
+    /// Returns the entry with the given name (case-insensitive).
+    pub fn get(&self, name: &str) -> Option<&SoundEntry> {
+        self.entries.get(&name.to_lowercase())
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        let kv = match keyvalues::parse_file(path) {
+            Ok(kv) => kv,
+            Err(e) => {
+                warn!("Failed to read soundscript {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        for entry in kv.iter().filter(|kv| !kv.children().is_empty()) {
+            let name = entry.key.to_lowercase();
+            if self.entries.contains_key(&name) {
+                trace!("Soundscript entry {} redefined in {}", name, path.display());
+                continue;
+            }
+
+            self.entries.insert(
+                name,
+                SoundEntry {
+                    file: path.to_path_buf(),
+                    waves: entry_waves(entry),
+                    definition: entry.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Processes soundscript entries referenced by maps.
+///
+/// Adds the waves of every resolved entry to the sound set and drops names that aren't
+/// defined anywhere. Returns the resolved entries, sorted by name.
+pub fn process(u_assets: &mut UniqueAssets, roots: &[PathBuf]) -> Vec<SoundEntry> {
+    let scripts = Soundscripts::load(roots);
+    let mut entries: Vec<SoundEntry> = Vec::new();
+
+    let mut names: Vec<String> = u_assets.soundscripts_name.drain().collect();
+    names.sort();
+    for name in names {
+        let Some(entry) = scripts.get(&name) else {
+            trace!("Not a soundscript entry: {}", name);
+            continue;
+        };
+
+        debug!(
+            "Found soundscript entry {} in {}",
+            name,
+            entry.file.display()
+        );
+        let key = named_key(NamedAsset::Soundscript, &name);
+        if let Some(file) = utils::root_relative_path(&entry.file, roots) {
+            u_assets.add_parent(file, &key);
+        }
+        u_assets.add_children(&key, |found| {
+            found.sounds_name.extend(entry.waves.iter().cloned())
+        });
+        u_assets.soundscripts_name.insert(name);
+        entries.push(entry.clone());
+    }
+
+    info!(
+        "Soundscript processing finished. Resolved {} entries from {} files.",
+        entries.len(),
+        files(&entries).len()
+    );
+    entries
+}
+
+/// Returns the distinct soundscript files defining the entries.
+pub fn files(entries: &[SoundEntry]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for entry in entries {
+        if !files.contains(&entry.file) {
+            files.push(entry.file.clone());
+        }
+    }
+    files
+}
+
+/// Writes `maps/<map>_level_sounds.txt` with the entries used by each parsed map.
+///
+/// Entries of a level sounds file already in the output (copied with the map's companion
+/// files) are kept and win over the used ones. Returns the number of written files.
+pub fn write_level_sounds(
+    u_assets: &UniqueAssets,
+    entries: &[SoundEntry],
+    output_dir: &Path,
+) -> io::Result<usize> {
+    let mut map_entries: HashMap<String, Vec<&KeyValue>> = HashMap::new();
+    for entry in entries {
+        let key = named_key(NamedAsset::Soundscript, &entry.definition.key);
+        for map_path in u_assets.source_maps(key) {
+            let used = map_entries.entry(map_name(map_path)).or_default();
+            if !used.iter().any(|kv| std::ptr::eq(*kv, &entry.definition)) {
+                used.push(&entry.definition);
+            }
+        }
+    }
+
+    let mut written = 0;
+    for (map, used) in map_entries {
+        let destination = output_dir
+            .join("maps")
+            .join(format!("{}_level_sounds.txt", map));
+        let mut level_sounds: Vec<KeyValue> = if destination.is_file() {
+            keyvalues::parse_file(&destination).unwrap_or_else(|e| {
+                warn!("Failed to read {}: {}", destination.display(), e);
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        let mut names: HashSet<String> = level_sounds
+            .iter()
+            .map(|kv| kv.key.to_lowercase())
+            .collect();
+        for entry in used {
+            if names.insert(entry.key.to_lowercase()) {
+                level_sounds.push(entry.clone());
+            }
+        }
+
+        fs::create_dir_all(output_dir.join("maps"))?;
+        fs::write(&destination, keyvalues::to_string(&level_sounds))?;
+        debug!("Wrote level sounds {}", destination.display());
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Writes `scripts/game_sounds_manifest.txt` to the output directory.
+///
+/// The manifest keeps the entries of the game's own manifest and adds a `precache_file`
+/// entry for every copied soundscript that isn't listed yet. Nothing is written when no
+/// soundscripts were copied.
+pub fn write_manifest(
+    soundscript_paths: &[PathBuf],
+    roots: &[PathBuf],
+    output_dir: &Path,
+) -> io::Result<()> {
+    if soundscript_paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut entries: Vec<(String, String)> = roots
+        .iter()
+        .find_map(|root| utils::find_file(root, Path::new(MANIFEST)))
+        .map(|manifest| manifest_entries(&manifest))
+        .unwrap_or_default();
+
+    for path in soundscript_paths {
+        let Some(relative) = utils::root_relative_path(path, roots) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        if !entries
+            .iter()
+            .any(|(_, file)| file.eq_ignore_ascii_case(&relative))
+        {
+            entries.push(("precache_file".to_string(), relative));
+        }
+    }
+
+    let mut content = String::from("\"game_sounds_manifest\"\n{\n");
+    for (key, file) in &entries {
+        content.push_str(&format!("\t\"{}\"\t\"{}\"\n", key, file));
+    }
+    content.push_str("}\n");
+
+    let destination = output_dir.join(MANIFEST);
+    if let Some(parent_dir) = destination.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+    fs::write(destination, content)
+}
+
+/// Returns the soundscript files of the content roots: manifest entries first, in order,
+/// then any other `scripts/game_sounds_*.txt`.
+fn soundscript_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for manifest in roots
+        .iter()
+        .filter_map(|root| utils::find_file(root, Path::new(MANIFEST)))
+    {
+        for (_, file) in manifest_entries(&manifest) {
+            let found = roots
+                .iter()
+                .find_map(|root| utils::find_file(root, Path::new(&file)));
+            match found {
+                Some(path) if !files.contains(&path) => files.push(path),
+                Some(_) => {}
+                None => trace!("Soundscript {} listed in manifest not found.", file),
+            }
+        }
+    }
+
+    for path in utils::find_scripts(roots, "game_sounds_") {
+        let is_manifest = path
+            .file_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case("game_sounds_manifest.txt"));
+        if !is_manifest && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// Reads the `precache_file` / `preload_file` entries of a manifest.
+fn manifest_entries(manifest: &Path) -> Vec<(String, String)> {
+    let kv = match keyvalues::parse_file(manifest) {
+        Ok(kv) => kv,
+        Err(e) => {
+            warn!("Failed to read {}: {}", manifest.display(), e);
+            return Vec::new();
+        }
+    };
+
+    kv.iter()
+        .flat_map(KeyValue::children)
+        .filter(|kv| kv.is_enabled())
+        .filter_map(|kv| {
+            let key = kv.key.to_ascii_lowercase();
+            let file = kv.as_str()?.trim().replace('\\', "/");
+            (key.ends_with("_file") && !file.is_empty()).then_some((key, file))
+        })
+        .collect()
+}
+
+/// Collects the `wave` values of an entry, including those in `rndwave` blocks.
+fn entry_waves(entry: &KeyValue) -> Vec<PathBuf> {
+    let mut waves = Vec::new();
+    entry.visit(&mut |kv| {
+        if kv.key.eq_ignore_ascii_case("wave")
+            && kv.is_enabled()
+            && let Some(wave) = kv.as_str().filter(|w| !w.trim().is_empty())
+        {
+            waves.push(sound_name(wave));
+        }
+    });
+    waves
+}
@@ -0,0 +1,267 @@
+//! Resolution of surface properties (`$surfaceprop`) used by materials and models.
+//!
+//! Surface properties are loaded from the files listed in
+//! `scripts/surfaceproperties_manifest.txt` and from every `scripts/surfaceproperties*.txt`.
+//! The stock definitions live in the game's VPKs, which aren't searched, so every definition
+//! found here is a custom one. The used definitions and their `base` chain are written to
+//! `scripts/surfaceproperties_custom.txt`, and their step, impact and break sounds are added
+//! to the soundscript entries.
+
+use super::{HashSet, NamedAsset, Path, PathBuf, UniqueAssets, named_key, utils};
+use crate::keyvalues::{self, KeyValue, Value};
+use log::{debug, info, trace, warn};
+use std::collections::HashMap;
+use std::{fs, io};
+
+/// Path of the surface property manifest, relative to a content root.
+const MANIFEST: &str = "scripts/surfaceproperties_manifest.txt";
+/// File the used custom definitions are written to, relative to the output directory.
+const OUTPUT_FILE: &str = "scripts/surfaceproperties_custom.txt";
+/// The stock definitions, always listed first in the written manifest.
+const DEFAULT_FILE: &str = "scripts/surfaceproperties.txt";
+
+/// Keys holding soundscript entries.
+const SOUND_KEYS: [&str; 10] = [
+    "stepleft",
+    "stepright",
+    "impactsoft",
+    "impacthard",
+    "scrapesmooth",
+    "scraperough",
+    "bulletimpact",
+    "rolling",
+    "break",
+    "strain",
+];
+
+/// Surface property definitions available in the content roots, keyed by lowercase name.
+#[derive(Debug, Default)]
+pub struct SurfaceProperties {
+    entries: HashMap<String, KeyValue>,
+}
+
+impl SurfaceProperties {
+    /// Loads every surface property file of the content roots. The first definition wins.
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let mut properties = Self::default();
+        for path in surfaceprop_files(roots) {
+            properties.add_file(&path);
+        }
+        properties
+    }
+
+    /// Returns the definition with the given name (case-insensitive).
+    pub fn get(&self, name: &str) -> Option<&KeyValue> {
+        self.entries.get(&name.to_lowercase())
+    }
+
+    /// Resolves `names` and their `base` chains. Bases come before the definitions deriving
+    /// from them, as the engine requires. Names without a definition are skipped.
+    pub fn resolve<'a>(&'a self, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a KeyValue> {
+        let mut used: Vec<&KeyValue> = Vec::new();
+        let mut used_names: HashSet<String> = HashSet::new();
+
+        for name in names {
+            let mut chain: Vec<&KeyValue> = Vec::new();
+            let mut current = name.to_lowercase();
+            while !used_names.contains(&current) {
+                let Some(property) = self.get(&current) else {
+                    trace!(
+                        "Surface property \"{}\" not found, assuming a stock one.",
+                        current
+                    );
+                    break;
+                };
+                if chain.iter().any(|p| std::ptr::eq(*p, property)) {
+                    warn!("Surface property \"{}\" has a cyclic base chain.", name);
+                    break;
+                }
+                chain.push(property);
+                match property.get_str("base") {
+                    Some(base) if !base.trim().is_empty() => current = base.trim().to_lowercase(),
+                    _ => break,
+                }
+            }
+
+            for property in chain.into_iter().rev() {
+                if used_names.insert(property.key.to_lowercase()) {
+                    used.push(property);
+                }
+            }
+        }
+        used
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        let kv = match keyvalues::parse_file(path) {
+            Ok(kv) => kv,
+            Err(e) => {
+                warn!(
+                    "Failed to read surface properties {}: {}",
+                    path.display(),
+                    e
+                );
+                return;
+            }
+        };
+
+        for entry in kv
+            .into_iter()
+            .filter(|kv| matches!(kv.value, Value::Block(_)))
+        {
+            let name = entry.key.to_lowercase();
+            if self.entries.contains_key(&name) {
+                trace!("Surface property {} redefined in {}", name, path.display());
+                continue;
+            }
+            self.entries.insert(name, entry);
+        }
+    }
+}
+
+/// Processes the surface properties used by the collected materials and models.
+///
+/// Adds the sounds of every resolved definition to the soundscript entries.
+/// Returns the definitions to write, bases first.
+pub fn process(u_assets: &mut UniqueAssets, roots: &[PathBuf]) -> Vec<KeyValue> {
+    if u_assets.surfaceprops_name.is_empty() {
+        return Vec::new();
+    }
+
+    let properties = SurfaceProperties::load(roots);
+    let mut names: Vec<&str> = u_assets
+        .surfaceprops_name
+        .iter()
+        .map(String::as_str)
+        .collect();
+    names.sort();
+    let resolved: Vec<KeyValue> = properties.resolve(names).into_iter().cloned().collect();
+
+    for property in &resolved {
+        debug!("Found custom surface property {}", property.key);
+        let key = named_key(NamedAsset::SurfaceProp, &property.key);
+        if let Some(base) = property.get_str("base").filter(|b| !b.trim().is_empty()) {
+            u_assets.add_parent(named_key(NamedAsset::SurfaceProp, base), &key);
+        }
+        u_assets.add_children(&key, |found| {
+            for key in SOUND_KEYS {
+                if let Some(sound) = property.get_str(key).filter(|s| !s.trim().is_empty()) {
+                    found.soundscripts_name.insert(sound.trim().to_lowercase());
+                }
+            }
+        });
+    }
+
+    info!(
+        "Surface property processing finished. Found {} custom definitions.",
+        resolved.len()
+    );
+    resolved
+}
+
+/// Writes the used custom definitions to `scripts/surfaceproperties_custom.txt` and a
+/// `scripts/surfaceproperties_manifest.txt` listing it after the stock files.
+///
+/// Files of the game's manifest that exist as loose files are left out, their used
+/// definitions are part of the written file. Nothing is written without definitions.
+pub fn write_files(
+    surfaceprops: &[KeyValue],
+    roots: &[PathBuf],
+    output_dir: &Path,
+) -> io::Result<()> {
+    if surfaceprops.is_empty() {
+        return Ok(());
+    }
+
+    let mut files: Vec<String> = match roots
+        .iter()
+        .find_map(|root| utils::find_file(root, Path::new(MANIFEST)))
+    {
+        Some(manifest) => manifest_files(&manifest)
+            .into_iter()
+            .filter(|file| {
+                !roots
+                    .iter()
+                    .any(|root| utils::find_file(root, Path::new(file)).is_some())
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    // The stock definitions have to stay first, the custom ones derive from them.
+    if !files
+        .iter()
+        .any(|file| file.eq_ignore_ascii_case(DEFAULT_FILE))
+    {
+        files.insert(0, DEFAULT_FILE.to_string());
+    }
+    if !files
+        .iter()
+        .any(|file| file.eq_ignore_ascii_case(OUTPUT_FILE))
+    {
+        files.push(OUTPUT_FILE.to_string());
+    }
+
+    let mut manifest = String::from("\"surfaceprops_manifest\"\n{\n");
+    for file in &files {
+        manifest.push_str(&format!("\t\"file\"\t\"{}\"\n", file));
+    }
+    manifest.push_str("}\n");
+
+    fs::create_dir_all(output_dir.join("scripts"))?;
+    fs::write(
+        output_dir.join(OUTPUT_FILE),
+        keyvalues::to_string(surfaceprops),
+    )?;
+    fs::write(output_dir.join(MANIFEST), manifest)
+}
+
+/// Returns the surface property files of the content roots: manifest entries first, in
+/// order, then any other `scripts/surfaceproperties*.txt`.
+fn surfaceprop_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for manifest in roots
+        .iter()
+        .filter_map(|root| utils::find_file(root, Path::new(MANIFEST)))
+    {
+        for file in manifest_files(&manifest) {
+            let found = roots
+                .iter()
+                .find_map(|root| utils::find_file(root, Path::new(&file)));
+            match found {
+                Some(path) if !files.contains(&path) => files.push(path),
+                Some(_) => {}
+                None => trace!("Surface properties {} listed in manifest not found.", file),
+            }
+        }
+    }
+
+    for path in utils::find_scripts(roots, "surfaceproperties") {
+        let is_manifest = path
+            .file_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case("surfaceproperties_manifest.txt"));
+        if !is_manifest && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// Reads the `file` entries of a manifest.
+fn manifest_files(manifest: &Path) -> Vec<String> {
+    let kv = match keyvalues::parse_file(manifest) {
+        Ok(kv) => kv,
+        Err(e) => {
+            warn!("Failed to read {}: {}", manifest.display(), e);
+            return Vec::new();
+        }
+    };
+
+    kv.iter()
+        .flat_map(KeyValue::children)
+        .filter(|kv| kv.is_enabled() && kv.key.eq_ignore_ascii_case("file"))
+        .filter_map(|kv| Some(kv.as_str()?.trim().replace('\\', "/")))
+        .filter(|file| !file.is_empty())
+        .collect()
+}
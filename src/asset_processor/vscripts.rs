@@ -0,0 +1,137 @@
+//! Resolution of VScript files (`scripts/vscripts/*.nut`) and the assets they reference.
+//!
+//! Scripts are scanned for string literals passed to a few well-known functions: included
+//! scripts are followed, precached or assigned models and soundscript entries are added
+//! to the unique assets.
+
+use super::{Path, PathBuf, UniqueAssets, utils};
+use crate::parsers::common::{has_sound_suffix, material_name, sound_name, vscript_name};
+use log::{debug, info, trace, warn};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::OnceLock;
+
+/// Directory holding VScripts, relative to a content root.
+pub(crate) const VSCRIPTS_DIR: &str = "scripts/vscripts";
+
+// Static regex for finding function calls with a string literal argument.
+static RE: OnceLock<Regex> = OnceLock::new();
+
+/// Returns a static Regex instance matching `Function("literal"`.
+fn get_regex() -> &'static Regex {
+    RE.get_or_init(|| {
+        Regex::new(
+            r#"\b(IncludeScript|DoIncludeScript|PrecacheModel|SetModel|PrecacheSoundScript|PrecacheScriptSound)\s*\(\s*"([^"\r\n]*)""#,
+        )
+        .expect("Invalid regex pattern")
+    })
+}
+
+/// Processes VScripts referenced by maps, following `IncludeScript` dependencies.
+///
+/// Models and soundscript entries used by the scripts are added to the unique assets,
+/// so this has to run before the model, soundscript and sound passes.
+/// Returns the paths of the found script files.
+pub fn process(u_assets: &mut UniqueAssets, roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut script_paths: Vec<PathBuf> = Vec::new();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    let mut queue: Vec<PathBuf> = u_assets.vscripts_name.iter().cloned().collect();
+
+    while let Some(name) = queue.pop() {
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+        let Some(path) = find_script(&name, roots) else {
+            debug!("VScript not found: {}", name.display());
+            continue;
+        };
+
+        let code = match fs::read(&path) {
+            Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+            Err(e) => {
+                warn!("Failed to read VScript {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        // Included scripts and scanned assets belong to the found file, which belongs to
+        // the referenced name (they differ when `.nut` was appended).
+        let script = utils::root_relative_path(&path, roots)
+            .map_or_else(|| Path::new(VSCRIPTS_DIR).join(&name), Path::to_path_buf);
+        u_assets.add_parent(&script, Path::new(VSCRIPTS_DIR).join(&name));
+        let includes = u_assets.add_children(&script, |found| scan_code(&code, found));
+        for include in includes {
+            u_assets.add_parent(Path::new(VSCRIPTS_DIR).join(&include), &script);
+            queue.push(include);
+        }
+        if !script_paths.contains(&path) {
+            script_paths.push(path);
+        }
+    }
+
+    u_assets.vscripts_name.extend(visited);
+    info!(
+        "VScript processing finished. Found {} script files.",
+        script_paths.len()
+    );
+    script_paths
+}
+
+/// Scans Squirrel code for asset references and adds them to the unique assets.
+/// Returns the scripts included by the code.
+pub fn scan_code(code: &str, u_assets: &mut UniqueAssets) -> Vec<PathBuf> {
+    let mut includes = Vec::new();
+
+    for cap in get_regex().captures_iter(code) {
+        let value = cap[2].trim();
+        if value.is_empty() {
+            continue;
+        }
+
+        match &cap[1] {
+            "IncludeScript" | "DoIncludeScript" => includes.push(vscript_name(value)),
+            "PrecacheModel" | "SetModel" => {
+                let lower = value.to_ascii_lowercase();
+                if lower.ends_with(".mdl") {
+                    trace!("Found model in VScript: {}", value);
+                    u_assets.models_name.insert(value.replace('\\', "/").into());
+                } else if lower.ends_with(".vmt") || lower.ends_with(".spr") {
+                    trace!("Found sprite in VScript: {}", value);
+                    u_assets.materials_name.insert(material_name(value));
+                }
+            }
+            _ if has_sound_suffix(value) => {
+                trace!("Found sound in VScript: {}", value);
+                u_assets.sounds_name.insert(sound_name(value));
+            }
+            _ => {
+                trace!("Found soundscript entry in VScript: {}", value);
+                u_assets.soundscripts_name.insert(value.to_lowercase());
+            }
+        }
+    }
+
+    includes
+}
+
+/// Finds a script in the content roots. Names without an extension get `.nut` appended,
+/// like the engine does, and are also tried as is.
+fn find_script(name: &Path, roots: &[PathBuf]) -> Option<PathBuf> {
+    let has_nut = name
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("nut"));
+    let mut candidates = Vec::new();
+    if !has_nut {
+        let mut with_ext = name.as_os_str().to_owned();
+        with_ext.push(".nut");
+        candidates.push(PathBuf::from(with_ext));
+    }
+    candidates.push(name.to_path_buf());
+
+    candidates.iter().find_map(|candidate| {
+        let relative = Path::new(VSCRIPTS_DIR).join(candidate);
+        roots
+            .iter()
+            .find_map(|root| utils::find_file(root, &relative))
+    })
+}
@@ -2,6 +2,7 @@ use clap::Parser;
 use log::LevelFilter;
 
 use crate::utils;
+use sourcemods_builder::asset_processor::materials::DEFAULT_MAX_TEXTURE_SIZE;
 
 /// Command-line arguments structure.
 #[derive(Parser, Debug)]
@@ -27,6 +28,40 @@ pub struct Args {
     /// Process only BSP files, skip VMF.
     #[arg(long, default_value_t = false)]
     pub ignore_bsp: bool,
+    /// FGD file used to classify entity keys (can be repeated). Without it, keys are guessed by name.
+    #[arg(long = "fgd", value_name = "FGD")]
+    pub fgd: Vec<String>,
+    /// Skip VMF entities and brushes in the visgroup with this name (can be repeated).
+    #[arg(long = "exclude-visgroup", value_name = "NAME")]
+    pub exclude_visgroups: Vec<String>,
+    /// Skip VMF entities and brushes hidden through visgroups.
+    #[arg(long, default_value_t = false)]
+    pub exclude_hidden: bool,
+    /// Write a `maps/<map>_particles.txt` manifest listing the particle files each map uses.
+    #[arg(long, default_value_t = false)]
+    pub particle_manifests: bool,
+    /// Only collect the model materials used by the skins placed in the maps
+    /// (entity and static prop `skin` keys).
+    #[arg(long, default_value_t = false)]
+    pub minimal_skins: bool,
+    /// Warn about textures with a side larger than this many pixels (0 disables the check).
+    #[arg(long, value_name = "PIXELS", default_value_t = DEFAULT_MAX_TEXTURE_SIZE)]
+    pub max_texture_size: u32,
+    /// Remove up to this many of the largest mip levels from copied textures (0 keeps all).
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub strip_mips: u8,
+    /// Only strip textures with a side above this many pixels, and never below it.
+    #[arg(long, value_name = "PIXELS", default_value_t = 0)]
+    pub strip_mips_above: u32,
+    /// Extract the custom content packed into BSPs (pakfile) into the output directory.
+    #[arg(long, default_value_t = false)]
+    pub extract_embedded: bool,
+    /// Write copies of the BSPs to `<output>/maps/` with the collected assets packed into them.
+    #[arg(long, default_value_t = false)]
+    pub pack_bsp: bool,
+    /// Write a bspzip addlist (`<map>_addlist.txt`) for each BSP to the output directory.
+    #[arg(long, default_value_t = false)]
+    pub bspzip_addlist: bool,
 }
 
 /// Parses command-line arguments.
@@ -0,0 +1,499 @@
+//! A reader for binary DMX files, the format of particle system files (`.pcf`).
+//!
+//! Only element names, classes, strings and element references are kept; numeric
+//! attributes are skipped. Encodings 1 through 5 are supported. Their layouts differ in
+//! how strings are stored, so each plausible layout for the file's version is tried in turn.
+
+use crate::parsers::lump::{read_i32, read_u16};
+
+/// Attribute type ids of binary DMX encodings 1 to 5.
+const AT_ELEMENT: u8 = 1;
+const AT_STRING: u8 = 5;
+const AT_BINARY: u8 = 6;
+const AT_FIRST_ARRAY: u8 = 15;
+const AT_LAST_ARRAY: u8 = 28;
+
+/// A DMX element.
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    /// Element type, e.g. `DmeParticleSystemDefinition`.
+    pub class: String,
+    pub name: String,
+    pub attributes: Vec<(String, Attribute)>,
+}
+
+/// Attribute values that can reference assets or other elements.
+#[derive(Debug, Clone)]
+pub enum Attribute {
+    /// Index of the referenced element, `None` for null or external references.
+    Element(Option<usize>),
+    Elements(Vec<Option<usize>>),
+    String(String),
+    Strings(Vec<String>),
+    /// Any other value (numbers, vectors, colors, binary blobs).
+    Other,
+}
+
+impl Element {
+    /// Returns the first attribute with the given name (case-insensitive).
+    pub fn get(&self, name: &str) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, a)| a)
+    }
+
+    /// Indices of every element referenced by this element's attributes.
+    pub fn references(&self) -> impl Iterator<Item = usize> + '_ {
+        self.attributes.iter().flat_map(|(_, attr)| {
+            let refs: Vec<usize> = match attr {
+                Attribute::Element(index) => index.iter().copied().collect(),
+                Attribute::Elements(indices) => indices.iter().flatten().copied().collect(),
+                _ => Vec::new(),
+            };
+            refs
+        })
+    }
+}
+
+/// A parsed DMX file. The root element is the first one.
+#[derive(Debug, Clone, Default)]
+pub struct Dmx {
+    /// The format declared in the header, e.g. `pcf`.
+    pub format: String,
+    pub elements: Vec<Element>,
+}
+
+/// How strings are laid out in a given encoding version.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    /// Whether the file has a string table at all.
+    table: bool,
+    /// String table count is an `i32` (otherwise `i16`).
+    table_count_i32: bool,
+    /// String table indices are `i32` (otherwise `i16`).
+    index_i32: bool,
+    /// Element names are string table indices (otherwise inline).
+    names_in_table: bool,
+    /// String attribute values are string table indices (otherwise inline).
+    strings_in_table: bool,
+}
+
+impl Layout {
+    fn candidates(version: u32) -> Vec<Layout> {
+        let layout = |table_count_i32, index_i32, names_in_table, strings_in_table| Layout {
+            table: true,
+            table_count_i32,
+            index_i32,
+            names_in_table,
+            strings_in_table,
+        };
+
+        match version {
+            1 => vec![Layout {
+                table: false,
+                ..layout(true, false, false, false)
+            }],
+            2 | 3 => vec![
+                layout(true, false, false, false),
+                layout(false, false, false, false),
+            ],
+            4 => vec![
+                layout(true, false, true, false),
+                layout(true, false, true, true),
+                layout(true, true, true, true),
+            ],
+            _ => vec![
+                layout(true, true, true, true),
+                layout(true, false, true, true),
+            ],
+        }
+    }
+}
+
+/// Reads a binary DMX file. Returns `None` for other encodings or malformed data.
+pub fn read(data: &[u8]) -> Option<Dmx> {
+    let header_end = data.iter().position(|&b| b == 0)?;
+    let header = String::from_utf8_lossy(&data[..header_end]);
+    let (version, format) = parse_header(&header)?;
+
+    Layout::candidates(version).into_iter().find_map(|layout| {
+        let mut reader = Reader {
+            data,
+            pos: header_end + 1,
+            layout,
+            table: Vec::new(),
+        };
+        let elements = reader.read_elements()?;
+        Some(Dmx {
+            format: format.clone(),
+            elements,
+        })
+    })
+}
+
+/// Parses `<!-- dmx encoding binary 2 format pcf 1 -->` into the encoding version and format.
+fn parse_header(header: &str) -> Option<(u32, String)> {
+    let mut words = header.split_whitespace();
+    words.find(|w| *w == "encoding")?;
+    if words.next()? != "binary" {
+        return None;
+    }
+    let version = words.next()?.parse().ok()?;
+    words.find(|w| *w == "format")?;
+    let format = words.next().unwrap_or_default().to_string();
+    Some((version, format))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    layout: Layout,
+    table: Vec<String>,
+}
+
+impl Reader<'_> {
+    fn read_elements(&mut self) -> Option<Vec<Element>> {
+        if self.layout.table {
+            let count = if self.layout.table_count_i32 {
+                self.i32()?
+            } else {
+                self.i16()? as i32
+            };
+            for _ in 0..count.max(0) {
+                let s = self.inline_string()?;
+                self.table.push(s);
+            }
+        }
+
+        let count = usize::try_from(self.i32()?).ok()?;
+        // Each element header takes at least 18 bytes; reject obviously wrong counts early.
+        if count > self.remaining() / 18 + 1 {
+            return None;
+        }
+
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            let class = self.table_string()?;
+            let name = if self.layout.names_in_table {
+                self.table_string()?
+            } else {
+                self.inline_string()?
+            };
+            self.skip(16)?; // GUID
+            elements.push(Element {
+                class,
+                name,
+                attributes: Vec::new(),
+            });
+        }
+
+        for element in elements.iter_mut() {
+            let count = usize::try_from(self.i32()?).ok()?;
+            for _ in 0..count {
+                let name = self.table_string()?;
+                let kind = self.u8()?;
+                let value = self.attribute(kind)?;
+                element.attributes.push((name, value));
+            }
+        }
+
+        // Element references must point inside the file.
+        let valid = elements
+            .iter()
+            .all(|e| e.references().all(|index| index < elements.len()));
+        valid.then_some(elements)
+    }
+
+    fn attribute(&mut self, kind: u8) -> Option<Attribute> {
+        match kind {
+            AT_ELEMENT => Some(Attribute::Element(self.element_ref()?)),
+            AT_STRING => {
+                let value = if self.layout.strings_in_table {
+                    self.table_string()?
+                } else {
+                    self.inline_string()?
+                };
+                Some(Attribute::String(value))
+            }
+            AT_BINARY => {
+                let len = usize::try_from(self.i32()?).ok()?;
+                self.skip(len)?;
+                Some(Attribute::Other)
+            }
+            AT_FIRST_ARRAY..=AT_LAST_ARRAY => {
+                let count = usize::try_from(self.i32()?).ok()?;
+                match kind - AT_FIRST_ARRAY + 1 {
+                    AT_ELEMENT => {
+                        let refs = (0..count)
+                            .map(|_| self.element_ref())
+                            .collect::<Option<Vec<_>>>()?;
+                        Some(Attribute::Elements(refs))
+                    }
+                    // String arrays are always stored inline.
+                    AT_STRING => {
+                        let values = (0..count)
+                            .map(|_| self.inline_string())
+                            .collect::<Option<Vec<_>>>()?;
+                        Some(Attribute::Strings(values))
+                    }
+                    AT_BINARY => {
+                        for _ in 0..count {
+                            let len = usize::try_from(self.i32()?).ok()?;
+                            self.skip(len)?;
+                        }
+                        Some(Attribute::Other)
+                    }
+                    item => {
+                        self.skip(value_size(item)?.checked_mul(count)?)?;
+                        Some(Attribute::Other)
+                    }
+                }
+            }
+            _ => {
+                self.skip(value_size(kind)?)?;
+                Some(Attribute::Other)
+            }
+        }
+    }
+
+    /// Reads an element reference: an index, `-1` for null, or `-2` and an external GUID.
+    fn element_ref(&mut self) -> Option<Option<usize>> {
+        match self.i32()? {
+            -2 => {
+                self.inline_string()?;
+                Some(None)
+            }
+            index if index < 0 => Some(None),
+            index => Some(Some(index as usize)),
+        }
+    }
+
+    /// Reads a string stored in the string table, or inline when the file has none.
+    fn table_string(&mut self) -> Option<String> {
+        if !self.layout.table {
+            return self.inline_string();
+        }
+        let index = if self.layout.index_i32 {
+            self.i32()?
+        } else {
+            self.i16()? as i32
+        };
+        self.table.get(usize::try_from(index).ok()?).cloned()
+    }
+
+    fn inline_string(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        self.pos += len + 1;
+        Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        let end = self.pos.checked_add(len)?;
+        (end <= self.data.len()).then(|| self.pos = end)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(value)
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        let value = read_u16(self.data, self.pos)? as i16;
+        self.pos += 2;
+        Some(value)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        let value = read_i32(self.data, self.pos)?;
+        self.pos += 4;
+        Some(value)
+    }
+}
+
+/// Size in bytes of a fixed-size attribute value.
+fn value_size(kind: u8) -> Option<usize> {
+    match kind {
+        2 | 3 | 7 | 8 => Some(4), // int, float, time, color
+        4 => Some(1),             // bool
+        9 => Some(8),             // vector2
+        10 | 12 => Some(12),      // vector3, qangle
+        11 | 13 => Some(16),      // vector4, quaternion
+        14 => Some(64),           // matrix
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: [&str; 12] = [
+        "DmElement",
+        "root",
+        "particleSystemDefinitions",
+        "DmeParticleSystemDefinition",
+        "fire",
+        "material",
+        "particle/fire.vmt",
+        "radius",
+        "children",
+        "tags",
+        "blob",
+        "child",
+    ];
+
+    /// Writes binary DMX data in the given layout.
+    struct Writer {
+        out: Vec<u8>,
+        layout: Layout,
+    }
+
+    impl Writer {
+        fn i32(&mut self, value: i32) {
+            self.out.extend_from_slice(&value.to_le_bytes());
+        }
+
+        fn i16(&mut self, value: i16) {
+            self.out.extend_from_slice(&value.to_le_bytes());
+        }
+
+        fn inline(&mut self, s: &str) {
+            self.out.extend_from_slice(s.as_bytes());
+            self.out.push(0);
+        }
+
+        fn string(&mut self, s: &str, in_table: bool) {
+            if !self.layout.table || !in_table {
+                return self.inline(s);
+            }
+            let index = TABLE.iter().position(|t| *t == s).unwrap();
+            if self.layout.index_i32 {
+                self.i32(index as i32);
+            } else {
+                self.i16(index as i16);
+            }
+        }
+
+        fn attribute(&mut self, name: &str, kind: u8) {
+            self.string(name, true);
+            self.out.push(kind);
+        }
+    }
+
+    /// Builds a particle file with a root element referencing one particle system.
+    fn build_pcf(version: u32, layout: Layout) -> Vec<u8> {
+        let mut w = Writer {
+            out: format!("<!-- dmx encoding binary {} format pcf 1 -->\n\0", version).into_bytes(),
+            layout,
+        };
+        if layout.table {
+            if layout.table_count_i32 {
+                w.i32(TABLE.len() as i32);
+            } else {
+                w.i16(TABLE.len() as i16);
+            }
+            for s in TABLE {
+                w.inline(s);
+            }
+        }
+
+        w.i32(2);
+        for (class, name) in [
+            ("DmElement", "root"),
+            ("DmeParticleSystemDefinition", "fire"),
+        ] {
+            w.string(class, true);
+            w.string(name, layout.names_in_table);
+            w.out.extend_from_slice(&[0; 16]);
+        }
+
+        w.i32(1);
+        w.attribute("particleSystemDefinitions", AT_FIRST_ARRAY);
+        w.i32(1);
+        w.i32(1);
+
+        w.i32(6);
+        w.attribute("material", AT_STRING);
+        w.string("particle/fire.vmt", layout.strings_in_table);
+        w.attribute("radius", 3);
+        w.out.extend_from_slice(&5f32.to_le_bytes());
+        w.attribute("children", AT_FIRST_ARRAY);
+        w.i32(1);
+        w.i32(-1);
+        w.attribute("tags", AT_FIRST_ARRAY + AT_STRING - 1);
+        w.i32(2);
+        w.inline("a");
+        w.inline("b");
+        w.attribute("blob", AT_BINARY);
+        w.i32(3);
+        w.out.extend_from_slice(b"xyz");
+        w.attribute("child", AT_ELEMENT);
+        w.i32(-2);
+        w.inline("external-guid");
+        w.out
+    }
+
+    #[test]
+    fn read_supports_encodings_1_to_5() {
+        for version in 1..=5 {
+            let layout = Layout::candidates(version)[0];
+            let dmx = read(&build_pcf(version, layout))
+                .unwrap_or_else(|| panic!("version {} {:?}", version, layout));
+            assert_eq!(dmx.format, "pcf");
+            assert_eq!(dmx.elements.len(), 2);
+
+            let root = &dmx.elements[0];
+            assert_eq!(
+                (root.class.as_str(), root.name.as_str()),
+                ("DmElement", "root")
+            );
+            assert_eq!(root.references().collect::<Vec<_>>(), [1]);
+
+            let system = &dmx.elements[1];
+            assert_eq!(system.class, "DmeParticleSystemDefinition");
+            assert_eq!(system.name, "fire");
+            assert!(
+                matches!(system.get("Material"), Some(Attribute::String(s)) if s == "particle/fire.vmt")
+            );
+            assert!(matches!(system.get("radius"), Some(Attribute::Other)));
+            assert!(
+                matches!(system.get("children"), Some(Attribute::Elements(refs)) if refs == &[None])
+            );
+            assert!(
+                matches!(system.get("tags"), Some(Attribute::Strings(tags)) if tags == &["a", "b"])
+            );
+            assert!(matches!(system.get("blob"), Some(Attribute::Other)));
+            assert!(matches!(
+                system.get("child"),
+                Some(Attribute::Element(None))
+            ));
+            assert_eq!(system.references().count(), 0);
+        }
+    }
+
+    #[test]
+    fn read_rejects_invalid_files() {
+        assert!(read(b"<!-- dmx encoding keyvalues2 1 format pcf 1 -->\n\0").is_none());
+        assert!(read(b"no header").is_none());
+
+        let layout = Layout::candidates(2)[0];
+        let mut data = build_pcf(2, layout);
+        data.truncate(data.len() - 4);
+        assert!(read(&data).is_none());
+
+        // A reference past the last element: the root's only array item, in a file without
+        // a string table.
+        let mut data = build_pcf(1, Layout::candidates(1)[0]);
+        let name = b"particleSystemDefinitions\0";
+        let pos = data.windows(name.len()).position(|w| w == name).unwrap() + name.len() + 5;
+        data[pos..pos + 4].copy_from_slice(&7i32.to_le_bytes());
+        assert!(read(&data).is_none());
+    }
+}
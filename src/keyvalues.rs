@@ -0,0 +1,357 @@
+//! A small, lenient parser for Valve's KeyValues text format.
+//!
+//! KeyValues are used all over the Source engine: VMTs, soundscripts, soundscapes,
+//! manifests, `detail.vbsp`, model keyvalues, etc. The parser never fails; malformed
+//! input simply produces whatever could be read up to that point.
+
+use std::iter::Peekable;
+use std::path::Path;
+use std::{fs, io};
+
+/// A single key with either a string value or a block of nested key values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: Value,
+    /// Platform conditional such as `[$WIN32]` or `[!$X360]`, without brackets.
+    pub condition: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Block(Vec<KeyValue>),
+}
+
+impl KeyValue {
+    /// Returns the string value, or `None` for blocks.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::String(s) => Some(s),
+            Value::Block(_) => None,
+        }
+    }
+
+    /// Returns the nested key values. Empty for string values.
+    pub fn children(&self) -> &[KeyValue] {
+        match &self.value {
+            Value::String(_) => &[],
+            Value::Block(children) => children,
+        }
+    }
+
+    /// Finds the first child with the given key (case-insensitive).
+    pub fn get(&self, key: &str) -> Option<&KeyValue> {
+        self.children()
+            .iter()
+            .find(|kv| kv.key.eq_ignore_ascii_case(key))
+    }
+
+    /// Finds the string value of the first child with the given key (case-insensitive).
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.children()
+            .iter()
+            .filter(|kv| kv.key.eq_ignore_ascii_case(key))
+            .find_map(KeyValue::as_str)
+    }
+
+    /// Whether the entry applies to the PC version of the game.
+    pub fn is_enabled(&self) -> bool {
+        self.condition.as_deref().is_none_or(evaluate_condition)
+    }
+
+    /// Calls `f` for this entry and all of its descendants, depth-first.
+    pub fn visit<'a>(&'a self, f: &mut impl FnMut(&'a KeyValue)) {
+        f(self);
+        for child in self.children() {
+            child.visit(f);
+        }
+    }
+}
+
+/// Evaluates a KeyValues platform conditional (e.g. `$WIN32 || $POSIX`, `!$X360`) for PC.
+pub fn evaluate_condition(condition: &str) -> bool {
+    condition.split("||").any(|any| {
+        any.split("&&").all(|term| {
+            let term = term.trim();
+            let (negated, symbol) = match term.strip_prefix('!') {
+                Some(symbol) => (true, symbol.trim()),
+                None => (false, term),
+            };
+            let value = matches!(
+                symbol.to_ascii_uppercase().as_str(),
+                "$WIN32" | "$WINDOWS" | "$WIN64" | "$POSIX" | "$LINUX" | "$OSX"
+            );
+            value != negated
+        })
+    })
+}
+
+/// Parses KeyValues text into the list of its top-level entries.
+pub fn parse(text: &str) -> Vec<KeyValue> {
+    let mut tokens = Tokenizer::new(text).peekable();
+    parse_block(&mut tokens)
+}
+
+/// Reads and parses a KeyValues file. Non-UTF8 bytes are replaced.
+pub fn parse_file(path: &Path) -> io::Result<Vec<KeyValue>> {
+    let data = fs::read(path)?;
+    let text = String::from_utf8_lossy(&data);
+    Ok(parse(text.trim_start_matches('\u{feff}')))
+}
+
+/// Serializes entries back to KeyValues text, indented with tabs.
+pub fn to_string(entries: &[KeyValue]) -> String {
+    let mut out = String::new();
+    write_block(entries, 0, &mut out);
+    out
+}
+
+fn write_block(entries: &[KeyValue], depth: usize, out: &mut String) {
+    let indent = "\t".repeat(depth);
+    for entry in entries {
+        let condition = entry
+            .condition
+            .as_ref()
+            .map(|c| format!(" [{}]", c))
+            .unwrap_or_default();
+        match &entry.value {
+            Value::String(value) => {
+                out.push_str(&format!(
+                    "{}\"{}\"\t\"{}\"{}\n",
+                    indent, entry.key, value, condition
+                ));
+            }
+            Value::Block(children) => {
+                out.push_str(&format!(
+                    "{}\"{}\"{}\n{}{{\n",
+                    indent, entry.key, condition, indent
+                ));
+                write_block(children, depth + 1, out);
+                out.push_str(&format!("{}}}\n", indent));
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    String(String),
+    Condition(String),
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    /// Skips whitespace and `//` comments.
+    fn skip_trivia(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Reads up to (and consumes) `end`, or to the end of input.
+    fn read_until(&mut self, end: char) -> &'a str {
+        let rest = self.rest();
+        match rest.find(end) {
+            Some(idx) => {
+                self.pos += idx + end.len_utf8();
+                &rest[..idx]
+            }
+            None => {
+                self.pos = self.text.len();
+                rest
+            }
+        }
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.skip_trivia();
+        let c = self.rest().chars().next()?;
+
+        match c {
+            '{' => {
+                self.pos += 1;
+                Some(Token::Open)
+            }
+            '}' => {
+                self.pos += 1;
+                Some(Token::Close)
+            }
+            '"' => {
+                self.pos += 1;
+                Some(Token::String(self.read_until('"').to_string()))
+            }
+            '[' => {
+                self.pos += 1;
+                Some(Token::Condition(self.read_until(']').trim().to_string()))
+            }
+            _ => {
+                let rest = self.rest();
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | '"'))
+                    .unwrap_or(rest.len());
+                self.pos += len;
+                Some(Token::String(rest[..len].to_string()))
+            }
+        }
+    }
+}
+
+fn take_condition(tokens: &mut Peekable<Tokenizer>) -> Option<String> {
+    match tokens.peek() {
+        Some(Token::Condition(_)) => match tokens.next() {
+            Some(Token::Condition(condition)) => Some(condition),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn parse_block(tokens: &mut Peekable<Tokenizer>) -> Vec<KeyValue> {
+    let mut entries = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let key = match token {
+            Token::Close => break,
+            Token::Condition(_) => continue,
+            // Anonymous block, seen in some hand-written files.
+            Token::Open => {
+                let children = parse_block(tokens);
+                entries.push(KeyValue {
+                    key: String::new(),
+                    value: Value::Block(children),
+                    condition: take_condition(tokens),
+                });
+                continue;
+            }
+            Token::String(key) => key,
+        };
+
+        let mut condition = take_condition(tokens);
+        // Only the first of several conditionals applies.
+        while take_condition(tokens).is_some() {}
+        let value = match tokens.next() {
+            Some(Token::String(value)) => Value::String(value),
+            Some(Token::Open) => Value::Block(parse_block(tokens)),
+            // Key without a value at the end of a block or input.
+            Some(Token::Close) | Some(Token::Condition(_)) | None => {
+                entries.push(KeyValue {
+                    key,
+                    value: Value::String(String::new()),
+                    condition,
+                });
+                break;
+            }
+        };
+        condition = condition.or_else(|| take_condition(tokens));
+
+        entries.push(KeyValue {
+            key,
+            value,
+            condition,
+        });
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluate_condition_for_pc() {
+        assert!(evaluate_condition("$WIN32"));
+        assert!(evaluate_condition("$posix"));
+        assert!(evaluate_condition("!$X360"));
+        assert!(!evaluate_condition("$X360"));
+        assert!(!evaluate_condition("! $WIN32"));
+        assert!(evaluate_condition("$X360 || $WIN32"));
+        assert!(!evaluate_condition("$WIN32 && $X360"));
+        assert!(evaluate_condition("$WIN32 && !$PS3 || $X360"));
+    }
+
+    #[test]
+    fn parse_conditions() {
+        let entries = parse(
+            r#"
+            "sound.test"
+            {
+                "wave"      "pc.wav"    [$WIN32]
+                "wave"      "console.wav" [$X360]
+                "volume" [!$X360] "0.5"
+                "rndwave" [$X360 || $PS3]
+                {
+                    "wave" "a.wav"
+                }
+                "pitch" "100"
+            }
+            "#,
+        );
+        assert_eq!(entries.len(), 1);
+        let children = entries[0].children();
+        let summary: Vec<_> = children
+            .iter()
+            .map(|kv| (kv.key.as_str(), kv.condition.as_deref(), kv.is_enabled()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("wave", Some("$WIN32"), true),
+                ("wave", Some("$X360"), false),
+                ("volume", Some("!$X360"), true),
+                ("rndwave", Some("$X360 || $PS3"), false),
+                ("pitch", None, true),
+            ]
+        );
+        assert_eq!(children[2].as_str(), Some("0.5"));
+        assert_eq!(children[3].get_str("wave"), Some("a.wav"));
+    }
+
+    #[test]
+    fn parse_repeated_conditions() {
+        let entries = parse("\"a\" [$WIN32] [$X360] \"b\" [$POSIX] [$PS3] \"c\" \"d\"");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].as_str(), Some("b"));
+        assert_eq!(entries[0].condition.as_deref(), Some("$WIN32"));
+        assert_eq!(entries[1].key, "c");
+        assert_eq!(entries[1].as_str(), Some("d"));
+        assert_eq!(entries[1].condition, None);
+
+        let entries = parse("\"a\" [$WIN32] [$X360]");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].as_str(), Some(""));
+    }
+
+    #[test]
+    fn to_string_keeps_conditions() {
+        let entries = parse("\"a\" { \"b\" \"c\" [$WIN32] \"d\" [!$X360] { \"e\" \"f\" } }");
+        assert_eq!(parse(&to_string(&entries)), entries);
+    }
+}
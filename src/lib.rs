@@ -1,14 +1,20 @@
 //! Core library for sourcemods-builder application.
 
-use log::error;
 use std::fs;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 use walkdir::WalkDir;
 
 pub mod asset_processor;
+pub mod dmx;
+pub mod keyvalues;
+pub mod mdl;
+pub mod pakfile;
 pub mod parsers;
+pub mod pipeline;
 pub mod utils;
+pub mod vmt;
+pub mod vtf;
 
 pub use asset_processor::UniqueAssets;
 
@@ -51,10 +57,10 @@ pub fn check_directories(game_dir: &Path, map_dir: &Path, output_dir: &Path) ->
     if output_dir.to_str().unwrap_or_default().is_empty() {
         return Err("Output is empty.".to_string());
     }
-    if let Some(parent) = output_dir.parent() {
-        if !parent.exists() {
-            return Err("Output parent doesn't exist.".to_string());
-        }
+    if let Some(parent) = output_dir.parent()
+        && !parent.exists()
+    {
+        return Err("Output parent doesn't exist.".to_string());
     }
     if !output_dir.exists() {
         fs::create_dir_all(output_dir).map_err(|e| format!("Wrong output_dir: {}", e))?;
@@ -63,11 +69,22 @@ pub fn check_directories(game_dir: &Path, map_dir: &Path, output_dir: &Path) ->
     Ok(())
 }
 
+/// Asset directories found within the game directory.
+#[derive(Debug, Default)]
+pub struct AssetDirectories {
+    /// Content roots (parents of asset directories, e.g. `hl2`, `custom/my_addon`).
+    pub roots: Vec<PathBuf>,
+    /// Parents of `models` directories, since model names already start with `models/`.
+    pub models: Vec<PathBuf>,
+    /// `materials` directories.
+    pub materials: Vec<PathBuf>,
+    /// `sound` directories.
+    pub sounds: Vec<PathBuf>,
+}
+
 /// Finds asset directories within the game directory.
-pub fn find_asset_directories(game_dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>) {
-    let mut models_dirs: Vec<PathBuf> = Vec::new();
-    let mut materials_dirs: Vec<PathBuf> = Vec::new();
-    let mut sounds_dirs: Vec<PathBuf> = Vec::new();
+pub fn find_asset_directories(game_dir: &Path) -> AssetDirectories {
+    let mut dirs = AssetDirectories::default();
 
     for entry in WalkDir::new(game_dir)
         .into_iter()
@@ -75,12 +92,28 @@ pub fn find_asset_directories(game_dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>, V
         .filter(|e| e.file_type().is_dir())
     {
         match entry.file_name().to_str().unwrap_or_default() {
-            "models" => models_dirs.push(entry.path().parent().unwrap().to_path_buf()), // Parent of "models" dir
-            "materials" => materials_dirs.push(entry.into_path()),
-            "sound" => sounds_dirs.push(entry.into_path()),
+            "models" => dirs
+                .models
+                .push(entry.path().parent().unwrap().to_path_buf()), // Parent of "models" dir
+            "materials" => dirs.materials.push(entry.into_path()),
+            "sound" => dirs.sounds.push(entry.into_path()),
             _ => {}
         }
     }
 
-    (models_dirs, materials_dirs, sounds_dirs)
+    // The game dir itself is usually a content root, even without any asset directories.
+    dirs.roots.push(game_dir.to_path_buf());
+    let parents = dirs.models.iter().cloned().chain(
+        dirs.materials
+            .iter()
+            .chain(&dirs.sounds)
+            .filter_map(|d| d.parent().map(Path::to_path_buf)),
+    );
+    for root in parents {
+        if !dirs.roots.contains(&root) {
+            dirs.roots.push(root);
+        }
+    }
+
+    dirs
 }
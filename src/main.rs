@@ -6,10 +6,11 @@ use std::process::exit;
 use log::{error, info, warn};
 
 mod config;
+use sourcemods_builder::parsers::{ParseOptions, fgd::Fgd};
+use sourcemods_builder::pipeline::{self, OutputOptions};
 use sourcemods_builder::utils;
-use sourcemods_builder::{
-    UniqueAssets, asset_processor, check_directories, find_asset_directories,
-};
+use sourcemods_builder::vtf::MipStrip;
+use sourcemods_builder::{UniqueAssets, check_directories, find_asset_directories};
 
 fn main() {
     let args = config::get_args();
@@ -24,7 +25,25 @@ fn main() {
         exit(1);
     }
 
-    let u_assets_result = UniqueAssets::parse_dir(&map_dir, !args.ignore_vmf, !args.ignore_bsp);
+    let mut options = ParseOptions {
+        exclude_visgroups: args.exclude_visgroups.clone(),
+        exclude_hidden: args.exclude_hidden,
+        minimal_skins: args.minimal_skins,
+        ..Default::default()
+    };
+    if !args.fgd.is_empty() {
+        let fgd_paths: Vec<PathBuf> = args.fgd.iter().map(PathBuf::from).collect();
+        match Fgd::load(&fgd_paths) {
+            Ok(fgd) => options.fgd = Some(fgd),
+            Err(err) => {
+                error!("Failed to load FGD: {}", err);
+                exit(1);
+            }
+        }
+    }
+
+    let u_assets_result =
+        UniqueAssets::parse_dir(&map_dir, !args.ignore_vmf, !args.ignore_bsp, &options);
     let mut u_assets = match u_assets_result {
         Ok(assets) => assets,
         Err(err) => {
@@ -38,36 +57,49 @@ fn main() {
         exit(0)
     }
 
-    let (models_dirs, materials_dirs, sounds_dirs) = find_asset_directories(&game_dir);
+    let dirs = find_asset_directories(&game_dir);
 
     // Processing assets
-    let models_paths = asset_processor::models::process(&mut u_assets, &models_dirs);
-    let materials_paths = asset_processor::materials::process(&u_assets, &materials_dirs);
-    let sounds_paths = asset_processor::sounds::process(&u_assets, &sounds_dirs);
+    let files = pipeline::find_files(&mut u_assets, &dirs, |_| {});
+    for (map, issues) in files.texture_issues(&u_assets, args.max_texture_size) {
+        warn!(
+            "{}: {} textures with problems.",
+            map.display(),
+            issues.len()
+        );
+    }
 
-    let copied = models_paths.len() + materials_paths.len() + sounds_paths.len();
+    let copied = files.len();
     if copied == 0 {
         error!("Nothing copied; no assets found.");
         exit(1);
     }
 
-    if models_paths.is_empty() {
+    if files.models.is_empty() {
         warn!("No models for copying.");
     }
-    if materials_paths.is_empty() {
+    if files.materials.is_empty() {
         warn!("No materials for copying.");
     }
-    if sounds_paths.is_empty() {
+    if files.sounds.is_empty() {
         warn!("No sounds for copying.");
     }
 
-    // Copying assets to output directory
-    utils::copy_files(&models_paths, &output_dir, "models").expect("Failed to copy models");
-    utils::copy_files(&materials_paths, &output_dir, "materials")
-        .expect("Failed to copy materials");
-    utils::copy_files(&sounds_paths, &output_dir, "sound").expect("Failed to copy sounds");
+    // Copying assets to output directory and packing them into the maps
+    let output_options = OutputOptions {
+        mip_strip: MipStrip {
+            mips: args.strip_mips,
+            min_size: args.strip_mips_above,
+        },
+        particle_manifests: args.particle_manifests,
+        extract_embedded: args.extract_embedded,
+        pack_bsp: args.pack_bsp,
+        bspzip_addlist: args.bspzip_addlist,
+    };
+    if let Err(err) = files.write(&u_assets, &dirs, &output_dir, &output_options) {
+        error!("{}", err);
+        exit(1);
+    }
 
     info!("Success! {} assets copied.", copied);
 }
-
-// TODO: particles
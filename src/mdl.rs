@@ -0,0 +1,508 @@
+//! A minimal, panic-free reader for the header of studio models (`.mdl`, versions 44 to 53).
+//!
+//! Only the tables that reference other assets are read: textures and their search paths,
+//! included models, the skin table, the model keyvalues and sequence events. Animation data is never
+//! touched, unlike `vmdl`, which can panic on animation blocks. Offsets and counts are
+//! checked against the file, so malformed models just yield fewer entries.
+
+use crate::parsers::lump::{read_fixed_string, read_i32, read_u16};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// `IDST`, the identifier of studio model files.
+const MDL_ID: &[u8; 4] = b"IDST";
+/// Size of a `mstudiotexture_t`.
+const TEXTURE_SIZE: usize = 64;
+/// Size of a `mstudiomodelgroup_t`: label and name string offsets.
+const MODEL_GROUP_SIZE: usize = 8;
+/// Size of a `mstudioseqdesc_t`.
+const SEQUENCE_SIZE: usize = 212;
+/// Offset of `numevents` in a `mstudioseqdesc_t`.
+const SEQUENCE_EVENT_COUNT: usize = 24;
+/// Size of a `mstudioevent_t`: cycle, event, type, options and name offset.
+const EVENT_SIZE: usize = 80;
+/// Size of the `options` buffer of an event.
+const EVENT_OPTIONS_LEN: usize = 64;
+/// Events using the named event system have this bit in their type.
+const EVENT_TYPE_NEWEVENTSYSTEM: i32 = 1 << 10;
+/// Ids of the old-style events that play their options as a sound:
+/// `SCRIPT_EVENT_SOUND`, `SCRIPT_EVENT_SOUND_VOICE` and `CL_EVENT_SOUND` (`AE_CL_PLAYSOUND`).
+const SOUND_EVENTS: [i32; 3] = [1004, 1008, 5004];
+/// Size of the `.phy` file header: header size, id, solid count and checksum.
+const PHY_HEADER_SIZE: usize = 16;
+/// Upper bound for table counts, to reject garbage early.
+const MAX_COUNT: usize = 1 << 16;
+
+/// Offsets of the used `studiohdr_t` fields in versions 44 to 52.
+mod offsets {
+    pub const LOCAL_SEQUENCE_COUNT: usize = 188;
+    pub const TEXTURE_COUNT: usize = 204;
+    pub const TEXTURE_DIR_COUNT: usize = 212;
+    pub const SKIN_REFERENCE_COUNT: usize = 220;
+    pub const SURFACE_PROP_INDEX: usize = 308;
+    pub const KEY_VALUE_INDEX: usize = 312;
+    pub const INCLUDE_MODEL_COUNT: usize = 336;
+}
+
+/// The asset references of a studio model header.
+#[derive(Debug, Clone, Default)]
+pub struct MdlHeader {
+    pub version: i32,
+    /// Texture (material) names, relative to one of the `texture_dirs`.
+    pub textures: Vec<String>,
+    /// `$cdmaterials` search paths, relative to `materials/`.
+    pub texture_dirs: Vec<String>,
+    /// Models included with `$includemodel`, e.g. `models/humans/male_shared.mdl`.
+    pub include_models: Vec<String>,
+    /// Skin families, each mapping texture references to texture indices.
+    pub skin_families: Vec<Vec<u16>>,
+    pub surface_prop: String,
+    /// The `$keyvalues` text, if any.
+    pub key_values: Option<String>,
+    /// Events of the model's own sequences.
+    pub events: Vec<AnimationEvent>,
+}
+
+/// An animation event of a sequence.
+#[derive(Debug, Clone, Default)]
+pub struct AnimationEvent {
+    /// Numeric event id, for the old event system.
+    pub event: i32,
+    /// Event name (e.g. `AE_CL_PLAYSOUND`), for the named event system.
+    pub name: String,
+    pub options: String,
+}
+
+impl AnimationEvent {
+    /// Whether the event plays its options (a soundscript entry or a wave) as a sound.
+    pub fn plays_sound(&self) -> bool {
+        if self.name.is_empty() {
+            SOUND_EVENTS.contains(&self.event)
+        } else {
+            self.name.to_ascii_uppercase().contains("SOUND")
+        }
+    }
+}
+
+impl MdlHeader {
+    /// Reads the header of a model. Returns `None` for files that aren't studio models.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != MDL_ID {
+            return None;
+        }
+        let version = read_i32(data, 4)?;
+        if !(44..=53).contains(&version) {
+            return None;
+        }
+        // Version 53 stores a name string offset right after the checksum.
+        let shift = if version >= 53 { 4 } else { 0 };
+        let field = |offset: usize| read_i32(data, offset + shift);
+        let pair = |offset: usize| Some((field(offset)?, field(offset + 4)?));
+
+        let mut header = MdlHeader {
+            version,
+            ..Default::default()
+        };
+
+        if let Some((count, offset)) = pair(offsets::TEXTURE_COUNT) {
+            header.textures = table(data, count, offset, TEXTURE_SIZE)
+                .filter_map(|entry| relative_string(data, entry, entry))
+                .collect();
+        }
+        if let Some((count, offset)) = pair(offsets::TEXTURE_DIR_COUNT) {
+            header.texture_dirs = table(data, count, offset, 4)
+                .filter_map(|entry| relative_string(data, 0, entry))
+                .collect();
+        }
+        if let Some((count, offset)) = pair(offsets::INCLUDE_MODEL_COUNT) {
+            header.include_models = table(data, count, offset, MODEL_GROUP_SIZE)
+                .filter_map(|entry| relative_string(data, entry, entry + 4))
+                .filter(|name| !name.is_empty())
+                .map(|name| model_name(&name))
+                .collect();
+        }
+        if let (Some((references, families)), Some(offset)) = (
+            pair(offsets::SKIN_REFERENCE_COUNT),
+            field(offsets::SKIN_REFERENCE_COUNT + 8),
+        ) {
+            header.skin_families = read_skin_families(data, references, families, offset);
+        }
+        header.surface_prop =
+            relative_string(data, 0, offsets::SURFACE_PROP_INDEX + shift).unwrap_or_default();
+        if let Some((offset, size)) = pair(offsets::KEY_VALUE_INDEX) {
+            header.key_values = read_key_values(data, offset, size);
+        }
+        if let Some((count, offset)) = pair(offsets::LOCAL_SEQUENCE_COUNT) {
+            header.events = table(data, count, offset, SEQUENCE_SIZE)
+                .flat_map(|sequence| read_events(data, sequence))
+                .collect();
+        }
+
+        Some(header)
+    }
+
+    /// Options of the events that play sounds.
+    pub fn sound_events(&self) -> impl Iterator<Item = &str> {
+        self.events
+            .iter()
+            .filter(|event| event.plays_sound())
+            .map(|event| event.options.trim())
+            .filter(|options| !options.is_empty())
+    }
+
+    /// Material paths (relative to `materials/`) of every texture in every search path.
+    pub fn materials(&self) -> Vec<PathBuf> {
+        self.texture_materials(&self.textures)
+    }
+
+    /// Material paths of the textures used by the given skins. Skins missing from the
+    /// skin table fall back to the default one, like the engine does.
+    pub fn skin_materials(&self, skins: &HashSet<i32>) -> Vec<PathBuf> {
+        let Some(default) = self.skin_families.first() else {
+            return self.materials();
+        };
+
+        let mut indices: Vec<u16> = skins
+            .iter()
+            .flat_map(|&skin| {
+                usize::try_from(skin)
+                    .ok()
+                    .and_then(|skin| self.skin_families.get(skin))
+                    .unwrap_or(default)
+            })
+            .copied()
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let textures: Vec<String> = indices
+            .into_iter()
+            .filter_map(|index| self.textures.get(usize::from(index)).cloned())
+            .collect();
+        self.texture_materials(&textures)
+    }
+
+    fn texture_materials(&self, textures: &[String]) -> Vec<PathBuf> {
+        self.texture_dirs
+            .iter()
+            .flat_map(|dir| {
+                let dir = dir.replace('\\', "/");
+                textures
+                    .iter()
+                    .map(move |texture| PathBuf::from(&dir).join(texture.replace('\\', "/")))
+            })
+            .collect()
+    }
+}
+
+/// Reads the text section of a collision model (`.phy`), which follows the solids and holds
+/// the `solid`, `ragdollconstraint` and `$collisiontext` (`break`) blocks.
+pub fn read_phy_text(data: &[u8]) -> Option<String> {
+    let header_size = usize::try_from(read_i32(data, 0)?).ok()?;
+    let solids = usize::try_from(read_i32(data, 8)?).ok()?;
+    if header_size != PHY_HEADER_SIZE || solids > MAX_COUNT {
+        return None;
+    }
+
+    let mut offset = PHY_HEADER_SIZE;
+    for _ in 0..solids {
+        let size = usize::try_from(read_i32(data, offset)?).ok()?;
+        offset = offset.checked_add(4 + size)?;
+    }
+    let text = read_fixed_string(data.get(offset..)?);
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Positions of the entries of a table, or nothing if it doesn't fit in the file.
+fn table(data: &[u8], count: i32, offset: i32, size: usize) -> impl Iterator<Item = usize> {
+    let (count, offset) = match (usize::try_from(count), usize::try_from(offset)) {
+        (Ok(count), Ok(offset))
+            if count <= MAX_COUNT && offset.saturating_add(count * size) <= data.len() =>
+        {
+            (count, offset)
+        }
+        _ => (0, 0),
+    };
+    (0..count).map(move |i| offset + i * size)
+}
+
+/// Reads a null-terminated string whose offset, stored at `field`, is relative to `base`.
+fn relative_string(data: &[u8], base: usize, field: usize) -> Option<String> {
+    let relative = read_i32(data, field)?;
+    let start = base.checked_add_signed(relative as isize)?;
+    Some(read_fixed_string(data.get(start..)?))
+}
+
+fn read_skin_families(data: &[u8], references: i32, families: i32, offset: i32) -> Vec<Vec<u16>> {
+    let (Ok(references), Ok(families)) = (usize::try_from(references), usize::try_from(families))
+    else {
+        return Vec::new();
+    };
+    if references == 0 || references > MAX_COUNT || families > MAX_COUNT {
+        return Vec::new();
+    }
+    let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+
+    (0..families)
+        .map_while(|family| {
+            (0..references)
+                .map(|reference| {
+                    read_u16(
+                        data,
+                        offset.checked_add((family * references + reference) * 2)?,
+                    )
+                })
+                .collect::<Option<Vec<u16>>>()
+        })
+        .collect()
+}
+
+/// Reads the events of the sequence description at `sequence`.
+fn read_events(data: &[u8], sequence: usize) -> Vec<AnimationEvent> {
+    let (Some(count), Some(offset)) = (
+        read_i32(data, sequence + SEQUENCE_EVENT_COUNT),
+        read_i32(data, sequence + SEQUENCE_EVENT_COUNT + 4),
+    ) else {
+        return Vec::new();
+    };
+    let Some(offset) = sequence
+        .checked_add_signed(offset as isize)
+        .and_then(|offset| i32::try_from(offset).ok())
+    else {
+        return Vec::new();
+    };
+
+    table(data, count, offset, EVENT_SIZE)
+        .filter_map(|entry| {
+            let event = read_i32(data, entry + 4)?;
+            let kind = read_i32(data, entry + 8)?;
+            let options = read_fixed_string(data.get(entry + 12..entry + 12 + EVENT_OPTIONS_LEN)?);
+            let name = if kind & EVENT_TYPE_NEWEVENTSYSTEM != 0 {
+                relative_string(data, entry, entry + 12 + EVENT_OPTIONS_LEN).unwrap_or_default()
+            } else {
+                String::new()
+            };
+            Some(AnimationEvent {
+                event,
+                name,
+                options,
+            })
+        })
+        .collect()
+}
+
+fn read_key_values(data: &[u8], offset: i32, size: i32) -> Option<String> {
+    let offset = usize::try_from(offset).ok().filter(|&o| o > 0)?;
+    let size = usize::try_from(size).ok().filter(|&s| s > 0)?;
+    let text = data.get(offset..offset.checked_add(size)?)?;
+    let text = read_fixed_string(text);
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Normalizes a model reference (included models, gibs) to a path starting with `models/`
+/// and ending with an extension, `.mdl` if it has none.
+pub(crate) fn model_name(name: &str) -> String {
+    let mut name = name.trim().replace('\\', "/");
+    // Names are usually stored with the `models/` prefix, but not always.
+    if !name.to_ascii_lowercase().starts_with("models/") {
+        name = format!("models/{}", name);
+    }
+    if Path::new(&name).extension().is_none() {
+        name.push_str(".mdl");
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a studio model header, with the tables appended after it.
+    struct TestMdl {
+        data: Vec<u8>,
+        shift: usize,
+    }
+
+    impl TestMdl {
+        fn new(version: i32) -> Self {
+            let shift = if version >= 53 { 4 } else { 0 };
+            let mut mdl = Self {
+                data: vec![0; offsets::INCLUDE_MODEL_COUNT + 8 + shift],
+                shift,
+            };
+            mdl.data[..4].copy_from_slice(MDL_ID);
+            mdl.write(4, version);
+            mdl
+        }
+
+        fn write(&mut self, pos: usize, value: i32) {
+            self.data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        /// Writes a header field, accounting for the version 53 shift.
+        fn field(&mut self, offset: usize, value: usize) {
+            self.write(offset + self.shift, value as i32);
+        }
+
+        /// Writes a string offset relative to `base` at `pos`.
+        fn relative(&mut self, pos: usize, base: usize, target: usize) {
+            self.write(pos, (target - base) as i32);
+        }
+
+        /// Appends 4-byte aligned data, returning its offset.
+        fn append(&mut self, bytes: &[u8]) -> usize {
+            self.data.resize(self.data.len().next_multiple_of(4), 0);
+            let offset = self.data.len();
+            self.data.extend_from_slice(bytes);
+            offset
+        }
+
+        fn append_str(&mut self, s: &str) -> usize {
+            self.append(format!("{}\0", s).as_bytes())
+        }
+    }
+
+    fn build_mdl(version: i32) -> Vec<u8> {
+        let mut mdl = TestMdl::new(version);
+
+        let textures = mdl.append(&[0; 2 * TEXTURE_SIZE]);
+        mdl.field(offsets::TEXTURE_COUNT, 2);
+        mdl.field(offsets::TEXTURE_COUNT + 4, textures);
+        for (i, name) in ["wood", "Metal\\rust"].into_iter().enumerate() {
+            let entry = textures + i * TEXTURE_SIZE;
+            let name = mdl.append_str(name);
+            mdl.relative(entry, entry, name);
+        }
+
+        let dirs = [
+            mdl.append_str("models\\props\\") as i32,
+            mdl.append_str("shared/") as i32,
+        ];
+        let dirs = mdl.append(&[dirs[0].to_le_bytes(), dirs[1].to_le_bytes()].concat());
+        mdl.field(offsets::TEXTURE_DIR_COUNT, 2);
+        mdl.field(offsets::TEXTURE_DIR_COUNT + 4, dirs);
+
+        let skins: Vec<u8> = [0u16, 1, 1, 1]
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect();
+        let skins = mdl.append(&skins);
+        mdl.field(offsets::SKIN_REFERENCE_COUNT, 2);
+        mdl.field(offsets::SKIN_REFERENCE_COUNT + 4, 2);
+        mdl.field(offsets::SKIN_REFERENCE_COUNT + 8, skins);
+
+        let surface_prop = mdl.append_str("metal");
+        mdl.field(offsets::SURFACE_PROP_INDEX, surface_prop);
+
+        let key_values = "prop_data { \"base\" \"Wooden.Small\" }";
+        let offset = mdl.append_str(key_values);
+        mdl.field(offsets::KEY_VALUE_INDEX, offset);
+        mdl.field(offsets::KEY_VALUE_INDEX + 4, key_values.len() + 1);
+
+        let includes = mdl.append(&[0; MODEL_GROUP_SIZE]);
+        let name = mdl.append_str("humans\\male_shared.mdl");
+        mdl.relative(includes + 4, includes, name);
+        mdl.field(offsets::INCLUDE_MODEL_COUNT, 1);
+        mdl.field(offsets::INCLUDE_MODEL_COUNT + 4, includes);
+
+        let sequence = mdl.append(&[0; SEQUENCE_SIZE]);
+        let events = mdl.append(&[0; 3 * EVENT_SIZE]);
+        mdl.write(sequence + SEQUENCE_EVENT_COUNT, 3);
+        mdl.relative(sequence + SEQUENCE_EVENT_COUNT + 4, sequence, events);
+        let options = |event: usize| event + 12..event + 12 + EVENT_OPTIONS_LEN;
+        // An old-style sound event, a named one and a footstep that isn't a sound event.
+        mdl.write(events + 4, 1004);
+        mdl.data[options(events)][..12].copy_from_slice(b"ambient.drip");
+        let named = events + EVENT_SIZE;
+        mdl.write(named + 8, EVENT_TYPE_NEWEVENTSYSTEM);
+        mdl.data[options(named)][..14].copy_from_slice(b" npc/step.wav ");
+        let name = mdl.append_str("AE_CL_PLAYSOUND");
+        mdl.relative(named + 12 + EVENT_OPTIONS_LEN, named, name);
+        mdl.write(events + 2 * EVENT_SIZE + 4, 6004);
+        mdl.data[options(events + 2 * EVENT_SIZE)][..4].copy_from_slice(b"left");
+        mdl.field(offsets::LOCAL_SEQUENCE_COUNT, 1);
+        mdl.field(offsets::LOCAL_SEQUENCE_COUNT + 4, sequence);
+
+        mdl.data
+    }
+
+    #[test]
+    fn read_header_tables() {
+        for version in [44, 48, 49, 53] {
+            let header = MdlHeader::read(&build_mdl(version)).unwrap();
+            assert_eq!(header.version, version);
+            assert_eq!(header.textures, ["wood", "Metal\\rust"]);
+            assert_eq!(header.texture_dirs, ["models\\props\\", "shared/"]);
+            assert_eq!(header.include_models, ["models/humans/male_shared.mdl"]);
+            assert_eq!(header.skin_families, [[0, 1], [1, 1]]);
+            assert_eq!(header.surface_prop, "metal");
+            assert_eq!(
+                header.key_values.as_deref(),
+                Some("prop_data { \"base\" \"Wooden.Small\" }")
+            );
+            assert_eq!(header.events.len(), 3);
+            assert_eq!(header.events[1].name, "AE_CL_PLAYSOUND");
+            assert_eq!(
+                header.sound_events().collect::<Vec<_>>(),
+                ["ambient.drip", "npc/step.wav"]
+            );
+        }
+    }
+
+    #[test]
+    fn header_materials() {
+        let header = MdlHeader::read(&build_mdl(48)).unwrap();
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            header.materials(),
+            paths(&[
+                "models/props/wood",
+                "models/props/Metal/rust",
+                "shared/wood",
+                "shared/Metal/rust",
+            ])
+        );
+        assert_eq!(
+            header.skin_materials(&HashSet::from([1])),
+            paths(&["models/props/Metal/rust", "shared/Metal/rust"])
+        );
+        // Unknown skins use the default skin family.
+        assert_eq!(
+            header.skin_materials(&HashSet::from([7])),
+            header.materials()
+        );
+    }
+
+    #[test]
+    fn read_rejects_other_files() {
+        assert!(MdlHeader::read(b"IDST").is_none());
+        let mut data = build_mdl(48);
+        data[..4].copy_from_slice(b"IDSQ");
+        assert!(MdlHeader::read(&data).is_none());
+        for version in [43, 54] {
+            let mut data = build_mdl(48);
+            data[4..8].copy_from_slice(&i32::to_le_bytes(version));
+            assert!(MdlHeader::read(&data).is_none());
+        }
+    }
+
+    #[test]
+    fn read_skips_tables_outside_the_file() {
+        let mut data = build_mdl(48);
+        data[offsets::TEXTURE_COUNT..offsets::TEXTURE_COUNT + 4]
+            .copy_from_slice(&1000i32.to_le_bytes());
+        data[offsets::INCLUDE_MODEL_COUNT + 4..offsets::INCLUDE_MODEL_COUNT + 8]
+            .copy_from_slice(&(-8i32).to_le_bytes());
+        let header = MdlHeader::read(&data).unwrap();
+        assert!(header.textures.is_empty());
+        assert!(header.include_models.is_empty());
+        assert_eq!(header.texture_dirs.len(), 2);
+    }
+
+    #[test]
+    fn model_name_normalizes_references() {
+        assert_eq!(model_name(" props\\Crate "), "models/props/Crate.mdl");
+        assert_eq!(model_name("Models/gibs/wood.mdl"), "Models/gibs/wood.mdl");
+        assert_eq!(model_name("gibs/wood.MDL"), "models/gibs/wood.MDL");
+    }
+}
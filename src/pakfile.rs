@@ -0,0 +1,299 @@
+//! Access to the pakfile lump of a BSP: a zip archive of content embedded into the map,
+//! such as cubemaps, patched materials and custom assets packed by the mapper.
+
+use crate::parsers::lump::{self, BspHeader};
+use log::{trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// The embedded zip archive of a BSP.
+pub struct Pakfile {
+    archive: ZipArchive<Cursor<Vec<u8>>>,
+    /// Normalized entry names mapped to their index in the archive.
+    entries: HashMap<String, usize>,
+}
+
+impl Pakfile {
+    /// Reads the pakfile of a BSP. Returns `None` if the map has no (valid) pakfile.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let header = BspHeader::read(data)?;
+        let lump = header.lump_data(data, lump::LUMP_PAKFILE)?;
+        if lump.is_empty() {
+            return None;
+        }
+        Self::from_lump(lump)
+    }
+
+    /// Reads a pakfile from the raw lump data.
+    pub fn from_lump(lump: &[u8]) -> Option<Self> {
+        let mut archive = match ZipArchive::new(Cursor::new(lump.to_vec())) {
+            Ok(archive) => archive,
+            Err(e) => {
+                warn!("Failed to read pakfile: {}", e);
+                return None;
+            }
+        };
+
+        let mut entries = HashMap::with_capacity(archive.len());
+        for index in 0..archive.len() {
+            if let Ok(file) = archive.by_index_raw(index)
+                && file.is_file()
+            {
+                entries.insert(normalize(file.name()), index);
+            }
+        }
+        Some(Self { archive, entries })
+    }
+
+    /// Normalized names (lowercase, `/`-separated) of the embedded files.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Returns whether the pakfile contains `name` (case-insensitive).
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(&normalize(name))
+    }
+
+    /// Reads the embedded file `name` (case-insensitive).
+    pub fn get(&mut self, name: &str) -> Option<Vec<u8>> {
+        let index = *self.entries.get(&normalize(name))?;
+        let mut file = self.archive.by_index(index).ok()?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        match file.read_to_end(&mut data) {
+            Ok(_) => Some(data),
+            Err(e) => {
+                warn!("Failed to read embedded file {}: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+/// Returns a copy of the BSP `data` with `files` (entry name, source file) added to its pakfile.
+///
+/// Entries already in the pakfile are kept and win over added files with the same name.
+/// Added files are stored uncompressed. The new pakfile replaces the old one when it is
+/// the last lump of the file, as written by vbsp, and is appended to the file otherwise.
+/// Other lumps are never moved, so their offsets (including the absolute offsets in the
+/// game lump directory) stay valid.
+pub fn write_bsp(data: &[u8], files: &[(String, PathBuf)]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let header = BspHeader::read(data).ok_or_else(|| invalid("not a VBSP file"))?;
+    let entry = header.lumps[lump::LUMP_PAKFILE];
+    let old_lump = header
+        .lump_data(data, lump::LUMP_PAKFILE)
+        .ok_or_else(|| invalid("pakfile lump out of bounds"))?;
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut names: HashSet<String> = HashSet::new();
+
+    if !old_lump.is_empty() {
+        let mut archive = ZipArchive::new(Cursor::new(old_lump)).map_err(io::Error::other)?;
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index).map_err(io::Error::other)?;
+            names.insert(normalize(file.name()));
+            writer.raw_copy_file(file).map_err(io::Error::other)?;
+        }
+    }
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, path) in files {
+        let name = name.replace('\\', "/");
+        if !names.insert(normalize(&name)) {
+            trace!("{} is already in the pakfile, skipping.", name);
+            continue;
+        }
+        writer
+            .start_file(name.as_str(), options)
+            .map_err(io::Error::other)?;
+        writer.write_all(&fs::read(path)?)?;
+    }
+    let pakfile = writer.finish().map_err(io::Error::other)?.into_inner();
+
+    // Drop the old pakfile if nothing follows it, then append the new one (4-byte aligned).
+    let old_end = entry.offset as usize + entry.length as usize;
+    let mut bsp = if !old_lump.is_empty() && old_end == data.len() {
+        data[..entry.offset as usize].to_vec()
+    } else {
+        data.to_vec()
+    };
+    bsp.resize(bsp.len().next_multiple_of(4), 0);
+
+    let offset = u32::try_from(bsp.len()).map_err(|_| invalid("BSP too large"))?;
+    let length = u32::try_from(pakfile.len()).map_err(|_| invalid("pakfile too large"))?;
+    let (offset_pos, length_pos) = header.entry_fields(lump::LUMP_PAKFILE);
+    bsp[offset_pos..offset_pos + 4].copy_from_slice(&offset.to_le_bytes());
+    bsp[length_pos..length_pos + 4].copy_from_slice(&length.to_le_bytes());
+    bsp.extend_from_slice(&pakfile);
+    Ok(bsp)
+}
+
+/// Normalizes an entry name for lookups: lowercase, with forward slashes.
+pub fn normalize(name: &str) -> String {
+    name.replace('\\', "/").to_lowercase()
+}
+
+/// Returns the entry name as a relative path, or `None` if it would escape the output
+/// directory (absolute paths, `..` components).
+pub fn enclosed_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a VBSP whose lumps are stored in the given order after the header.
+    fn build_bsp(lumps: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0; 8 + lump::HEADER_LUMPS * 16];
+        data[..4].copy_from_slice(b"VBSP");
+        data[4..8].copy_from_slice(&20i32.to_le_bytes());
+        for (index, lump) in lumps {
+            let base = 8 + index * 16;
+            let offset = data.len() as u32;
+            data[base..base + 4].copy_from_slice(&offset.to_le_bytes());
+            data[base + 4..base + 8].copy_from_slice(&(lump.len() as u32).to_le_bytes());
+            data.extend_from_slice(lump);
+        }
+        data
+    }
+
+    fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Writes a file to pack into the temporary directory.
+    fn source_file(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pakfile-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    const ENTITIES: &[u8] = b"{\n\"classname\" \"worldspawn\"\n}\n\0";
+
+    #[test]
+    fn write_bsp_replaces_trailing_pakfile() {
+        let old_pakfile = build_zip(&[("materials/maps/test/c0_0_0.vtf", b"cubemap")]);
+        let data = build_bsp(&[
+            (lump::LUMP_ENTITIES, ENTITIES),
+            (lump::LUMP_PAKFILE, &old_pakfile),
+        ]);
+        let old_header = BspHeader::read(&data).unwrap();
+        let files = [
+            (
+                "materials/custom/wall.vmt".to_string(),
+                source_file("wall.vmt", b"\"LightmappedGeneric\" {}"),
+            ),
+            (
+                "Materials\\Maps\\Test\\c0_0_0.vtf".to_string(),
+                source_file("c0.vtf", b"replacement"),
+            ),
+        ];
+
+        let bsp = write_bsp(&data, &files).unwrap();
+        let header = BspHeader::read(&bsp).unwrap();
+        let pakfile_entry = header.lumps[lump::LUMP_PAKFILE];
+        let old_offset = old_header.lumps[lump::LUMP_PAKFILE].offset;
+        assert_eq!(pakfile_entry.offset, old_offset.next_multiple_of(4));
+        assert_eq!(
+            pakfile_entry.offset as usize + pakfile_entry.length as usize,
+            bsp.len()
+        );
+        assert_eq!(header.lump_data(&bsp, lump::LUMP_ENTITIES), Some(ENTITIES));
+
+        let mut pakfile = Pakfile::read(&bsp).unwrap();
+        assert_eq!(pakfile.names().count(), 2);
+        assert_eq!(
+            pakfile.get("materials/maps/test/c0_0_0.vtf").as_deref(),
+            Some(&b"cubemap"[..])
+        );
+        assert_eq!(
+            pakfile.get("MATERIALS/custom/wall.vmt").as_deref(),
+            Some(&b"\"LightmappedGeneric\" {}"[..])
+        );
+        for (_, path) in files {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn write_bsp_appends_pakfile_before_other_lumps() {
+        let old_pakfile = build_zip(&[("scripts/test.txt", b"embedded")]);
+        let names: &[u8] = b"tools/toolsnodraw\0";
+        let data = build_bsp(&[
+            (lump::LUMP_ENTITIES, ENTITIES),
+            (lump::LUMP_PAKFILE, &old_pakfile),
+            (lump::LUMP_TEXDATA_STRING_DATA, names),
+        ]);
+        let old_header = BspHeader::read(&data).unwrap();
+        let files = [(
+            "sound/test.wav".to_string(),
+            source_file("test.wav", b"RIFF"),
+        )];
+
+        let bsp = write_bsp(&data, &files).unwrap();
+        let header = BspHeader::read(&bsp).unwrap();
+        let pakfile_entry = header.lumps[lump::LUMP_PAKFILE];
+        assert!(pakfile_entry.offset as usize >= data.len());
+        assert_eq!(pakfile_entry.offset % 4, 0);
+        // Everything after the header is kept in place.
+        assert_eq!(
+            &bsp[8 + lump::HEADER_LUMPS * 16..data.len()],
+            &data[8 + lump::HEADER_LUMPS * 16..]
+        );
+        for index in [lump::LUMP_ENTITIES, lump::LUMP_TEXDATA_STRING_DATA] {
+            assert_eq!(header.lumps[index].offset, old_header.lumps[index].offset);
+            assert_eq!(
+                header.lump_data(&bsp, index),
+                old_header.lump_data(&data, index)
+            );
+        }
+
+        let mut pakfile = Pakfile::read(&bsp).unwrap();
+        assert_eq!(
+            pakfile.get("scripts/test.txt").as_deref(),
+            Some(&b"embedded"[..])
+        );
+        assert_eq!(pakfile.get("sound/test.wav").as_deref(), Some(&b"RIFF"[..]));
+        for (_, path) in files {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn write_bsp_creates_missing_pakfile() {
+        let data = build_bsp(&[(lump::LUMP_ENTITIES, ENTITIES)]);
+        let files = [(
+            "models/test.mdl".to_string(),
+            source_file("test.mdl", b"IDST"),
+        )];
+
+        let bsp = write_bsp(&data, &files).unwrap();
+        let mut pakfile = Pakfile::read(&bsp).unwrap();
+        assert_eq!(
+            pakfile.get("models/test.mdl").as_deref(),
+            Some(&b"IDST"[..])
+        );
+        fs::remove_file(&files[0].1).unwrap();
+    }
+
+    #[test]
+    fn write_bsp_rejects_other_files() {
+        assert!(write_bsp(b"IBSP", &[]).is_err());
+    }
+}
@@ -1,20 +1,271 @@
+use super::lump::{self, BspHeader};
+use super::{ParseOptions, common};
 use crate::asset_processor::UniqueAssets;
-use log::trace;
-use std::path::Path;
+use crate::keyvalues;
+use crate::pakfile::Pakfile;
+use log::{debug, trace, warn};
+use std::path::{Path, PathBuf};
 use vbsp::{Bsp, BspResult};
 
 /// Extracts unique assets from a BSP map file.
-/// Currently, BSP parsing is not implemented.
-pub fn get_uniques(path: &Path, uasset: &mut UniqueAssets) -> BspResult<()> {
+///
+/// Maps `vbsp` can't read are scanned lump by lump instead, see [`scan_lumps`].
+pub fn get_uniques(
+    path: &Path,
+    uasset: &mut UniqueAssets,
+    options: &ParseOptions,
+) -> BspResult<()> {
     trace!("Attempting to read and parse BSP file: {}", path.display());
     let data = std::fs::read(path)?;
-    let bsp = Bsp::read(&data)?;
+    let map = common::map_name(path);
+    let mut pakfile = Pakfile::read(&data);
 
-    add_unique_models(&bsp, uasset);
-    add_texture(&bsp, uasset);
+    match Bsp::read(&data) {
+        Ok(bsp) => {
+            add_unique_models(&bsp, &map, uasset, options);
+            add_texture(bsp.textures().map(|t| t.name()), pakfile.as_mut(), uasset);
+        }
+        Err(err) => {
+            warn!(
+                "vbsp failed to read {}: {}. Falling back to the lump scanner.",
+                path.display(),
+                err
+            );
+            if !scan_lumps(&data, &map, pakfile.as_mut(), uasset, options) {
+                return Err(err);
+            }
+        }
+    }
+    add_detail_models(&data, uasset);
+    if let Some(pakfile) = &pakfile {
+        add_embedded_files(pakfile, path, uasset);
+    }
     Ok(())
 }
 
+/// Extracts references from the lumps of a map directly: entities, texture names and
+/// static prop models. Handles the Left 4 Dead 2 header and LZMA-compressed lumps.
+/// Returns `false` if the file isn't a VBSP.
+fn scan_lumps(
+    data: &[u8],
+    map: &str,
+    pakfile: Option<&mut Pakfile>,
+    uassets: &mut UniqueAssets,
+    options: &ParseOptions,
+) -> bool {
+    let Some(header) = BspHeader::read(data) else {
+        return false;
+    };
+    debug!(
+        "Scanning BSP lumps (version {}, {} layout).",
+        header.version,
+        if header.l4d2_layout {
+            "L4D2"
+        } else {
+            "standard"
+        }
+    );
+
+    if let Some(entities) = header.lump_bytes(data, lump::LUMP_ENTITIES) {
+        let text = String::from_utf8_lossy(&entities);
+        let mut skins = common::SkinInputs::new(options);
+        for properties in parse_entities(&text) {
+            add_entity(&properties, map, uassets, &mut skins, options);
+        }
+        skins.resolve(uassets);
+    }
+
+    add_texture(
+        texture_names(data, &header).iter().map(String::as_str),
+        pakfile,
+        uassets,
+    );
+
+    if let Some((entry, sprp)) = lump::find_game_lump(data, &header, b"sprp") {
+        let names = read_name_dictionary(&sprp);
+        for (prop_type, skin) in read_static_prop_skins(&sprp, entry.version, names.len()) {
+            if let Some(name) = names.get(usize::from(prop_type)) {
+                common::add_model_skin(name, Some(&skin.to_string()), options, uassets);
+            }
+        }
+        for name in names {
+            trace!("Found static prop model: {}", name);
+            uassets.models_name.insert(name.into());
+        }
+    }
+    true
+}
+
+/// Parses the entity lump into the key values of each entity.
+fn parse_entities(text: &str) -> Vec<Vec<(&str, &str)>> {
+    let mut entities = Vec::new();
+    let mut current: Option<Vec<(&str, &str)>> = None;
+    let mut key: Option<&str> = None;
+
+    let mut rest = text;
+    while let Some(pos) = rest.find(['{', '}', '"']) {
+        let token = rest.as_bytes()[pos];
+        rest = &rest[pos + 1..];
+        match token {
+            b'{' => current = Some(Vec::new()),
+            b'}' => {
+                entities.extend(current.take());
+                key = None;
+            }
+            _ => {
+                let end = rest.find('"').unwrap_or(rest.len());
+                let string = &rest[..end];
+                rest = rest.get(end + 1..).unwrap_or_default();
+                if let Some(properties) = current.as_mut() {
+                    match key.take() {
+                        Some(k) => properties.push((k, string)),
+                        None => key = Some(string),
+                    }
+                }
+            }
+        }
+    }
+    entities
+}
+
+/// Reads the texture names from the texdata string table.
+fn texture_names(data: &[u8], header: &BspHeader) -> Vec<String> {
+    let (Some(table), Some(strings)) = (
+        header.lump_bytes(data, lump::LUMP_TEXDATA_STRING_TABLE),
+        header.lump_bytes(data, lump::LUMP_TEXDATA_STRING_DATA),
+    ) else {
+        return Vec::new();
+    };
+
+    table
+        .chunks_exact(4)
+        .filter_map(|offset| {
+            let offset = usize::try_from(lump::read_i32(offset, 0)?).ok()?;
+            Some(lump::read_fixed_string(strings.get(offset..)?))
+        })
+        .collect()
+}
+
+/// Reads a game lump dictionary of model names: a count followed by 128-byte names.
+fn read_name_dictionary(lump: &[u8]) -> Vec<String> {
+    const NAME_LEN: usize = 128;
+
+    let count = lump::read_i32(lump, 0).unwrap_or(0).max(0) as usize;
+    let mut names = Vec::new();
+    for i in 0..count {
+        let start = 4 + i * NAME_LEN;
+        let Some(name) = lump.get(start..start + NAME_LEN) else {
+            break;
+        };
+        let name = lump::read_fixed_string(name);
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Sizes of the static prop records of each `sprp` version. Engine branches disagree on
+/// versions 7, 10 and 11: the size filling the lump exactly wins, the first one otherwise.
+fn static_prop_sizes(version: u16) -> Option<&'static [usize]> {
+    match version {
+        4 => Some(&[56]),
+        5 => Some(&[60]),
+        6 => Some(&[64]),
+        7 => Some(&[68, 72]),
+        8 => Some(&[68]),
+        9 => Some(&[72]),
+        10 => Some(&[76, 72]),
+        11 => Some(&[80, 76]),
+        _ => None,
+    }
+}
+
+/// Reads the model index and skin of every static prop from the `sprp` game lump: the
+/// name dictionary, the leaf list, then the props. Prop records grow with the lump version,
+/// but the skin is at the same offset in all of them.
+fn read_static_prop_skins(sprp: &[u8], version: u16, name_count: usize) -> Vec<(u16, i32)> {
+    const NAME_LEN: usize = 128;
+    const PROP_TYPE: usize = 24;
+    const SKIN: usize = 32;
+
+    let Some(sizes) = static_prop_sizes(version) else {
+        warn!(
+            "Unknown static prop lump version {}, skipping prop skins.",
+            version
+        );
+        return Vec::new();
+    };
+
+    let leaves_start = 4 + name_count * NAME_LEN;
+    let Some(leaf_count) = lump::read_i32(sprp, leaves_start).and_then(|c| usize::try_from(c).ok())
+    else {
+        return Vec::new();
+    };
+    let props_start = leaves_start + 4 + leaf_count * 2;
+    let Some(prop_count) = lump::read_i32(sprp, props_start)
+        .and_then(|c| usize::try_from(c).ok())
+        .filter(|&c| c > 0)
+    else {
+        return Vec::new();
+    };
+    let records = sprp.get(props_start + 4..).unwrap_or_default();
+    // Lumps may be padded after the props.
+    let Some(&prop_size) = sizes
+        .iter()
+        .find(|&&size| size * prop_count == records.len())
+        .or_else(|| sizes.first())
+        .filter(|&&size| size * prop_count <= records.len())
+    else {
+        warn!(
+            "Static prop lump too short for {} version {} props, skipping prop skins.",
+            prop_count, version
+        );
+        return Vec::new();
+    };
+
+    records
+        .chunks_exact(prop_size)
+        .take(prop_count)
+        .filter_map(|prop| {
+            Some((
+                lump::read_u16(prop, PROP_TYPE)?,
+                lump::read_i32(prop, SKIN)?,
+            ))
+        })
+        .collect()
+}
+
+/// Records the files packed into the map, so they aren't searched for and copied again.
+fn add_embedded_files(pakfile: &Pakfile, map_path: &Path, uassets: &mut UniqueAssets) {
+    let files = uassets
+        .embedded_files
+        .entry(map_path.to_path_buf())
+        .or_default();
+    let mut count = 0;
+    for name in pakfile.names() {
+        trace!("Found embedded file: {}", name);
+        files.insert(name.to_string());
+        count += 1;
+    }
+    debug!("Found {} files embedded in the pakfile.", count);
+}
+
+/// Extracts detail prop models from the `dprp` game lump.
+fn add_detail_models(data: &[u8], uassets: &mut UniqueAssets) {
+    let Some(header) = BspHeader::read(data) else {
+        return;
+    };
+    let Some((_, dprp)) = lump::find_game_lump(data, &header, b"dprp") else {
+        return;
+    };
+
+    for name in read_name_dictionary(&dprp) {
+        trace!("Found detail model: {}", name);
+        uassets.models_name.insert(name.into());
+    }
+}
+
 fn is_coord_segment(segment: &str) -> bool {
     !segment.is_empty()
         && segment
@@ -22,36 +273,90 @@ fn is_coord_segment(segment: &str) -> bool {
             .all(|c| c.is_ascii_digit() || c == '-' || c == '_')
 }
 
-fn add_texture(bsp: &Bsp, uassets: &mut UniqueAssets) {
-    for tex_info_handle in bsp.textures() {
-        let name = tex_info_handle.name();
+/// Maximum depth of patch VMTs including other patch VMTs.
+const MAX_PATCH_DEPTH: usize = 8;
+
+/// Resolves a material generated by the compile tools (`maps/<map>/...`) through its VMT
+/// in the pakfile. Patch VMTs are followed to the material they `include`; any other
+/// VMT is returned as is. Returns `None` if the VMT isn't embedded.
+fn resolve_patch_material(name: &str, pakfile: &mut Pakfile) -> Option<PathBuf> {
+    let mut current = PathBuf::from(name);
+
+    for _ in 0..MAX_PATCH_DEPTH {
+        let vmt_name = format!("materials/{}.vmt", current.display());
+        let Some(data) = pakfile.get(&vmt_name) else {
+            // Only the first VMT has to be embedded, included materials may be stock ones.
+            return (current != Path::new(name)).then_some(current);
+        };
+
+        let vmt = keyvalues::parse(&String::from_utf8_lossy(&data));
+        let Some(include) = vmt
+            .first()
+            .filter(|shader| shader.key.eq_ignore_ascii_case("patch"))
+            .and_then(|shader| shader.get_str("include"))
+        else {
+            return Some(current);
+        };
+
+        let included = common::material_name(include);
+        trace!(
+            "Patch material {} includes {}",
+            current.display(),
+            included.display()
+        );
+        let is_patch = included
+            .to_str()
+            .is_some_and(|n| n.to_ascii_lowercase().starts_with("maps/"));
+        if !is_patch {
+            return Some(included);
+        }
+        current = included;
+    }
+
+    debug!("Patch material {} includes too many patches.", name);
+    Some(current)
+}
+
+/// Strips the `maps/<map>/` prefix and `_x_y_z` cubemap coordinates from a patch material
+/// name. Used when the patch VMT isn't available.
+fn guess_patched_material(name: &str) -> Option<&str> {
+    let mut name_part = name;
+    if let Some(second_slash_pos) = name_part[5..].find('/') {
+        name_part = &name_part[5 + second_slash_pos + 1..];
+    }
 
+    // remove suffix
+    let pos3 = name_part.rfind('_')?;
+    let pos2 = name_part[..pos3].rfind('_')?;
+    let pos1 = name_part[..pos2].rfind('_')?;
+    let segment1 = &name_part[pos1 + 1..pos2];
+    let segment2 = &name_part[pos2 + 1..pos3];
+    let segment3 = &name_part[pos3 + 1..];
+
+    (is_coord_segment(segment1) && is_coord_segment(segment2) && is_coord_segment(segment3))
+        .then(|| &name_part[..pos1])
+}
+
+fn add_texture<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    mut pakfile: Option<&mut Pakfile>,
+    uassets: &mut UniqueAssets,
+) {
+    for name in names {
         if !name.is_empty() && !name.starts_with("TOOLS/") {
             trace!("Processing texture name: '{}'", name);
             // oh no, this is patch-materials
-            if name.starts_with("maps/") {
-                let mut name_part = name;
-                if let Some(second_slash_pos) = name_part[5..].find('/') {
-                    name_part = &name_part[5 + second_slash_pos + 1..];
+            if name.to_ascii_lowercase().starts_with("maps/") {
+                if let Some(material) = pakfile
+                    .as_deref_mut()
+                    .and_then(|pakfile| resolve_patch_material(name, pakfile))
+                {
+                    uassets.materials_name.insert(material);
+                    continue;
                 }
-
-                // remove suffix
-                if let Some(pos3) = name_part.rfind('_') {
-                    if let Some(pos2) = name_part[..pos3].rfind('_') {
-                        if let Some(pos1) = name_part[..pos2].rfind('_') {
-                            let segment1 = &name_part[pos1 + 1..pos2];
-                            let segment2 = &name_part[pos2 + 1..pos3];
-                            let segment3 = &name_part[pos3 + 1..];
-
-                            if is_coord_segment(segment1)
-                                && is_coord_segment(segment2)
-                                && is_coord_segment(segment3)
-                            {
-                                uassets.materials_name.insert(name_part[..pos1].into());
-                                continue;
-                            }
-                        }
-                    }
+                if let Some(material) = guess_patched_material(name) {
+                    uassets.materials_name.insert(material.into());
+                    continue;
                 }
             }
 
@@ -60,48 +365,236 @@ fn add_texture(bsp: &Bsp, uassets: &mut UniqueAssets) {
     }
 }
 
-/// Extracts unique model and material names from BSP entities.
-fn add_unique_models(bsp: &Bsp, uassets: &mut UniqueAssets) {
+/// Extracts unique model, material and sound names from BSP static props and entities.
+fn add_unique_models(bsp: &Bsp, map: &str, uassets: &mut UniqueAssets, options: &ParseOptions) {
     for fixed_string in &bsp.static_props.dict.name {
         let model_path = fixed_string.as_str();
         uassets.models_name.insert(model_path.into());
     }
+    let names = &bsp.static_props.dict.name;
+    for prop in bsp.static_props() {
+        if let Some(name) = names.get(usize::from(prop.prop_type)) {
+            let skin = prop.skin.to_string();
+            common::add_model_skin(name.as_str(), Some(&skin), options, uassets);
+        }
+    }
 
+    let mut skins = common::SkinInputs::new(options);
     for entity in bsp.entities.iter() {
-        for (key, value) in entity.properties() {
-            // Process models
-            if key == "model" {
-                let modelname = value;
-                if modelname.ends_with(".vmt") {
-                    uassets
-                        .materials_name
-                        .insert(modelname.replace(".vmt", "").into()); // Add material name
-                    continue;
-                }
-                if modelname.starts_with("models") {
-                    uassets.models_name.insert(modelname.into());
-                }
-            }
+        let properties: Vec<(&str, &str)> = entity.properties().collect();
+        add_entity(&properties, map, uassets, &mut skins, options);
+    }
+    skins.resolve(uassets);
+}
 
-            if key == "texture" || key == "materials" {
-                trace!("Found material from '{}' key: {}", key, value);
-                uassets.materials_name.insert(value.into());
-            }
+/// Extracts assets referenced by the key values and outputs of an entity, and by
+/// worldspawn key values (skybox, detail sprites).
+fn add_entity(
+    properties: &[(&str, &str)],
+    map: &str,
+    uassets: &mut UniqueAssets,
+    skins: &mut common::SkinInputs,
+    options: &ParseOptions,
+) {
+    let get = |key: &str| {
+        properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| *v)
+    };
+    let classname = get("classname").unwrap_or_default();
+    skins.add_entity(get("targetname"), classname, get("model"));
 
-            // Process Sounds
-            let suffixes = [".wav", ".mp3", ".ogg", ".flac"]; // todo MOVE IT
-            let value_bytes = value.as_bytes();
-            let value_len = value_bytes.len();
-
-            if suffixes.iter().any(|suffix| {
-                let suffix_bytes = suffix.as_bytes();
-                let suffix_len = suffix_bytes.len();
-                value_len >= suffix_len
-                    && value_bytes[value_len - suffix_len..].eq_ignore_ascii_case(suffix_bytes)
-            }) {
-                trace!("Found sound: {}", value);
-                uassets.sounds_name.insert(value.into());
-            }
+    for &(key, value) in properties {
+        if let Some(connection) = common::parse_connection(value) {
+            common::add_connection(&connection, options, uassets);
+            skins.add_connection(&connection, get("model"));
+            continue;
+        }
+        common::add_entity_keyvalue(classname, key, value, options, uassets);
+        common::add_map_keyvalue(map, classname, key, value, options, uassets);
+    }
+
+    if let Some(model) = get("model") {
+        common::add_model_skin(model, get("skin"), options, uassets);
+    }
+    if classname == "worldspawn" {
+        common::add_worldspawn(get, uassets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Builds a `sprp` game lump: the name dictionary, one leaf, then the props
+    /// (model index, skin) as records of `size` bytes, followed by `padding` bytes.
+    fn build_sprp(names: &[&str], props: &[(u16, i32)], size: usize, padding: usize) -> Vec<u8> {
+        let mut lump = (names.len() as i32).to_le_bytes().to_vec();
+        for name in names {
+            let mut field = [0; 128];
+            field[..name.len()].copy_from_slice(name.as_bytes());
+            lump.extend_from_slice(&field);
+        }
+        lump.extend_from_slice(&1i32.to_le_bytes());
+        lump.extend_from_slice(&0u16.to_le_bytes());
+        lump.extend_from_slice(&(props.len() as i32).to_le_bytes());
+        for (prop_type, skin) in props {
+            let mut record = vec![0xAA; size];
+            record[24..26].copy_from_slice(&prop_type.to_le_bytes());
+            record[32..36].copy_from_slice(&skin.to_le_bytes());
+            lump.extend_from_slice(&record);
+        }
+        lump.extend(std::iter::repeat_n(0, padding));
+        lump
+    }
+
+    /// Builds a version 21 map in the Left 4 Dead 2 layout, which `vbsp` can't read, with
+    /// entities, texture names and a `sprp` game lump of the given version.
+    fn build_l4d2_bsp(
+        entities: &str,
+        textures: &[&str],
+        sprp_version: u16,
+        sprp: &[u8],
+    ) -> Vec<u8> {
+        let header_size = 8 + lump::HEADER_LUMPS * 16;
+        let mut data = vec![0; header_size];
+        data[..4].copy_from_slice(b"VBSP");
+        data[4..8].copy_from_slice(&21i32.to_le_bytes());
+        let add_lump = |data: &mut Vec<u8>, index: usize, lump: &[u8]| {
+            let base = 8 + index * 16;
+            let offset = data.len() as u32;
+            data[base + 4..base + 8].copy_from_slice(&offset.to_le_bytes());
+            data[base + 8..base + 12].copy_from_slice(&(lump.len() as u32).to_le_bytes());
+            data.extend_from_slice(lump);
+        };
+
+        add_lump(
+            &mut data,
+            lump::LUMP_ENTITIES,
+            format!("{}\0", entities).as_bytes(),
+        );
+        let mut strings = Vec::new();
+        let mut table = Vec::new();
+        for texture in textures {
+            table.extend_from_slice(&(strings.len() as i32).to_le_bytes());
+            strings.extend_from_slice(texture.as_bytes());
+            strings.push(0);
+        }
+        add_lump(&mut data, lump::LUMP_TEXDATA_STRING_DATA, &strings);
+        add_lump(&mut data, lump::LUMP_TEXDATA_STRING_TABLE, &table);
+
+        let sprp_offset = data.len() + 4 + 16;
+        let mut game_lump = 1i32.to_le_bytes().to_vec();
+        game_lump.extend_from_slice(&u32::from_be_bytes(*b"sprp").to_le_bytes());
+        game_lump.extend_from_slice(&0u16.to_le_bytes());
+        game_lump.extend_from_slice(&sprp_version.to_le_bytes());
+        game_lump.extend_from_slice(&(sprp_offset as i32).to_le_bytes());
+        game_lump.extend_from_slice(&(sprp.len() as i32).to_le_bytes());
+        game_lump.extend_from_slice(sprp);
+        add_lump(&mut data, lump::LUMP_GAME_LUMP, &game_lump);
+        data
+    }
+
+    const PROPS: [(u16, i32); 3] = [(0, 1), (1, 0), (0, 3)];
+
+    #[test]
+    fn static_prop_skins_use_the_version_record_size() {
+        for (version, size) in [
+            (4, 56),
+            (6, 64),
+            (7, 72),
+            (9, 72),
+            (10, 76),
+            (10, 72),
+            (11, 80),
+        ] {
+            let sprp = build_sprp(&["a.mdl", "b.mdl"], &PROPS, size, 0);
+            assert_eq!(
+                read_static_prop_skins(&sprp, version, 2),
+                PROPS,
+                "v{}",
+                version
+            );
         }
     }
+
+    #[test]
+    fn static_prop_skins_ignore_padding() {
+        // Dividing the lump by the prop count would give 68-byte records.
+        let sprp = build_sprp(&["a.mdl"], &PROPS[..2], 64, 8);
+        assert_eq!(read_static_prop_skins(&sprp, 6, 1), PROPS[..2]);
+        let sprp = build_sprp(&["a.mdl"], &PROPS, 76, 16);
+        assert_eq!(read_static_prop_skins(&sprp, 10, 1), PROPS);
+    }
+
+    #[test]
+    fn static_prop_skins_skip_unknown_versions_and_short_lumps() {
+        let sprp = build_sprp(&["a.mdl"], &PROPS, 76, 0);
+        assert!(read_static_prop_skins(&sprp, 3, 1).is_empty());
+        assert!(read_static_prop_skins(&sprp, 12, 1).is_empty());
+        assert!(read_static_prop_skins(&sprp[..sprp.len() - 4], 10, 1).is_empty());
+        let empty = build_sprp(&["a.mdl"], &[], 76, 0);
+        assert!(read_static_prop_skins(&empty, 10, 1).is_empty());
+    }
+
+    #[test]
+    fn scan_lumps_reads_l4d2_maps() {
+        let entities = r#"{
+"classname" "worldspawn"
+"skyname" "sky_day01_01"
+}
+{
+"classname" "prop_dynamic"
+"model" "models/props/door.mdl"
+"skin" "2"
+"OnUser1" "relay,Trigger,,0,-1"
+}"#;
+        let sprp = build_sprp(
+            &["models/props/crate.mdl", "models/props/barrel.mdl"],
+            &PROPS,
+            72,
+            4,
+        );
+        let data = build_l4d2_bsp(entities, &["Custom/Wall", "TOOLS/TOOLSNODRAW"], 9, &sprp);
+        assert!(Bsp::read(&data).is_err());
+
+        let options = ParseOptions {
+            minimal_skins: true,
+            ..Default::default()
+        };
+        let mut uassets = UniqueAssets::default();
+        assert!(scan_lumps(&data, "test", None, &mut uassets, &options));
+
+        let models: HashSet<PathBuf> = [
+            "models/props/door.mdl",
+            "models/props/crate.mdl",
+            "models/props/barrel.mdl",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(uassets.models_name, models);
+        assert!(uassets.materials_name.contains(Path::new("Custom/Wall")));
+        assert!(
+            uassets
+                .materials_name
+                .contains(Path::new("skybox/sky_day01_01up"))
+        );
+        assert_eq!(
+            uassets.model_skins[Path::new("models/props/crate.mdl")],
+            HashSet::from([1, 3])
+        );
+        assert_eq!(
+            uassets.model_skins[Path::new("models/props/barrel.mdl")],
+            HashSet::from([0])
+        );
+        assert_eq!(
+            uassets.model_skins[Path::new("models/props/door.mdl")],
+            HashSet::from([2])
+        );
+
+        assert!(!scan_lumps(b"IBSP", "test", None, &mut uassets, &options));
+    }
 }
@@ -0,0 +1,731 @@
+//! Helpers shared by the VMF and BSP parsers.
+
+use log::trace;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::ParseOptions;
+use super::fgd::AssetKind;
+use crate::asset_processor::{UniqueAssets, vscripts};
+
+/// Suffixes of the six skybox faces.
+const SKYBOX_FACES: [&str; 6] = ["up", "dn", "lf", "rt", "ft", "bk"];
+
+/// Sound file extensions recognized in entity values.
+const SOUND_SUFFIXES: [&str; 4] = [".wav", ".mp3", ".ogg", ".flac"];
+
+/// Extensions of files that may appear in entity values and are never soundscript names.
+const FILE_EXTENSIONS: [&str; 12] = [
+    "mdl", "vmt", "vtf", "spr", "txt", "nut", "pcf", "vmf", "bsp", "res", "cfg", "vcd",
+];
+
+/// Keys holding entity names, which may contain dots but never reference sounds.
+const NAME_KEYS: [&str; 6] = [
+    "targetname",
+    "parentname",
+    "target",
+    "classname",
+    "globalname",
+    "damagefilter",
+];
+
+/// Entity classes that select a soundscape by name.
+const SOUNDSCAPE_CLASSES: [&str; 2] = ["env_soundscape", "env_soundscape_triggerable"];
+
+/// Prefix characters the engine strips from sound names (streaming, spatialization, etc.).
+const SOUND_CHARS: &[char] = &[
+    '*', '#', '@', '>', '<', '^', ')', '}', '$', '!', '?', '&', '~',
+];
+
+/// Adds the asset referenced by a single entity key value.
+///
+/// When the entity class is defined in the configured FGD, the property type decides
+/// what the value refers to. Otherwise the key name and value suffix heuristics are used.
+pub(crate) fn add_entity_keyvalue(
+    classname: &str,
+    key: &str,
+    value: &str,
+    options: &ParseOptions,
+    uassets: &mut UniqueAssets,
+) {
+    let value = value.trim();
+    if value.is_empty() {
+        return;
+    }
+
+    if key.eq_ignore_ascii_case("vscripts") {
+        add_vscripts(value, uassets);
+        return;
+    }
+
+    match &options.fgd {
+        Some(fgd) if fgd.has_class(classname) => {
+            if let Some(kind) = fgd.asset_kind(classname, key) {
+                add_typed_asset(kind, value, uassets);
+            } else if has_sound_suffix(value) {
+                // Plenty of sound keys are declared as plain strings in stock FGDs.
+                trace!("Found sound: {}", value);
+                uassets.sounds_name.insert(sound_name(value));
+            }
+        }
+        _ => add_by_heuristics(key, value, uassets),
+    }
+}
+
+/// Adds the scripts of a `vscripts` key, a space-separated list of paths relative to
+/// `scripts/vscripts/`.
+fn add_vscripts(value: &str, uassets: &mut UniqueAssets) {
+    for script in value.split_whitespace() {
+        trace!("Found vscript: {}", script);
+        uassets.vscripts_name.insert(vscript_name(script));
+    }
+}
+
+/// Normalizes a script reference to a path relative to `scripts/vscripts/`.
+pub(crate) fn vscript_name(value: &str) -> PathBuf {
+    let value = value.trim().replace('\\', "/").to_lowercase();
+    let value = value.trim_start_matches("scripts/vscripts/");
+    value.into()
+}
+
+/// An entity I/O connection: `target, input, parameter, delay, times to fire`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Connection<'a> {
+    pub target: &'a str,
+    pub input: &'a str,
+    pub param: &'a str,
+    pub delay: f32,
+    pub refire: i32,
+}
+
+/// Parses the value of an entity output. Fields are separated by `\x1B` in newer maps
+/// and by commas in older ones. Returns `None` for values that aren't connections.
+pub(crate) fn parse_connection(value: &str) -> Option<Connection<'_>> {
+    let separator = if value.contains('\x1B') { '\x1B' } else { ',' };
+    parse_connection_fields(value, separator)
+}
+
+fn parse_connection_fields(value: &str, separator: char) -> Option<Connection<'_>> {
+    let fields: Vec<&str> = value.split(separator).collect();
+    let [target, input, param, delay, refire] = fields.as_slice() else {
+        return None;
+    };
+
+    Some(Connection {
+        target: target.trim(),
+        input: input.trim(),
+        param: param.trim(),
+        delay: delay.trim().parse().ok()?,
+        refire: refire.trim().parse().ok()?,
+    })
+}
+
+/// Adds the assets referenced by the parameter of an entity I/O connection.
+pub(crate) fn add_connection(
+    connection: &Connection,
+    options: &ParseOptions,
+    uassets: &mut UniqueAssets,
+) {
+    let (input, param) = (connection.input, connection.param);
+    if param.is_empty() {
+        return;
+    }
+    trace!(
+        "Scanning connection {} -> {}({})",
+        connection.target, input, param
+    );
+
+    if input.eq_ignore_ascii_case("RunScriptFile") {
+        trace!("Found vscript from RunScriptFile: {}", param);
+        uassets.vscripts_name.insert(vscript_name(param));
+    } else if input.eq_ignore_ascii_case("RunScriptCode") {
+        // Hammer doesn't allow double quotes in parameters, Portal 2 uses backticks instead.
+        let code = param.replace('`', "\"");
+        for include in vscripts::scan_code(&code, uassets) {
+            uassets.vscripts_name.insert(include);
+        }
+        add_parameter_paths(&code, uassets);
+    } else if input.eq_ignore_ascii_case("AddOutput") {
+        add_output_parameter(param, options, uassets);
+    } else if input.to_ascii_lowercase().ends_with("model") {
+        add_typed_asset(AssetKind::Model, param, uassets);
+    } else {
+        add_parameter_paths(param, uassets);
+        // Inputs like `PlaySound` or `SetSound` take soundscript entries.
+        if input.to_ascii_lowercase().contains("sound") && is_soundscript_name(param) {
+            trace!("Found possible soundscript entry: {}", param);
+            uassets.soundscripts_name.insert(param.to_lowercase());
+        }
+    }
+}
+
+/// Handles an `AddOutput` parameter: either `key value`, which sets a key value on the target,
+/// or `OnOutput target:input:param:delay:refire`, which adds a new connection.
+fn add_output_parameter(param: &str, options: &ParseOptions, uassets: &mut UniqueAssets) {
+    let Some((key, value)) = param.trim().split_once(char::is_whitespace) else {
+        return;
+    };
+
+    match parse_connection_fields(value.trim(), ':') {
+        Some(connection) => add_connection(&connection, options, uassets),
+        // The target's class isn't known here, so the key is classified by name.
+        None => add_entity_keyvalue("", key, value, options, uassets),
+    }
+}
+
+/// Adds file paths found in a parameter, such as `play ambient/alarm.wav` passed to a
+/// `point_clientcommand` or quoted paths in script code.
+fn add_parameter_paths(param: &str, uassets: &mut UniqueAssets) {
+    let tokens = param
+        .split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | ';' | ',' | '(' | ')'))
+        .filter(|token| !token.is_empty());
+
+    for token in tokens {
+        let lower = token.to_ascii_lowercase();
+        if lower.ends_with(".mdl") {
+            trace!("Found model in connection: {}", token);
+            uassets.models_name.insert(token.replace('\\', "/").into());
+        } else if is_sprite(token) {
+            trace!("Found material in connection: {}", token);
+            uassets.materials_name.insert(material_name(token));
+        } else if has_sound_suffix(token) {
+            trace!("Found sound in connection: {}", token);
+            uassets.sounds_name.insert(sound_name(token));
+        } else if lower.ends_with(".nut") {
+            trace!("Found vscript in connection: {}", token);
+            uassets.vscripts_name.insert(vscript_name(token));
+        }
+    }
+}
+
+/// Records map-specific references of a single entity key value: the soundscape selected
+/// by `env_soundscape` entities and particle systems.
+pub(crate) fn add_map_keyvalue(
+    map: &str,
+    classname: &str,
+    key: &str,
+    value: &str,
+    options: &ParseOptions,
+    uassets: &mut UniqueAssets,
+) {
+    let value = value.trim();
+    if value.is_empty() {
+        return;
+    }
+
+    if key.eq_ignore_ascii_case("soundscape")
+        && SOUNDSCAPE_CLASSES
+            .iter()
+            .any(|c| classname.eq_ignore_ascii_case(c))
+    {
+        trace!("Found soundscape: {}", value);
+        uassets
+            .soundscapes_name
+            .entry(map.to_string())
+            .or_default()
+            .insert(value.to_lowercase());
+    }
+
+    let is_particle = match &options.fgd {
+        Some(fgd) if fgd.has_class(classname) => {
+            fgd.asset_kind(classname, key) == Some(AssetKind::Particle)
+        }
+        _ => key.eq_ignore_ascii_case("effect_name"),
+    };
+    if is_particle {
+        trace!("Found particle system: {}", value);
+        uassets
+            .particles_name
+            .entry(map.to_string())
+            .or_default()
+            .insert(value.to_lowercase());
+    }
+}
+
+/// Records the skin a model is placed with, for `ParseOptions::minimal_skins`.
+/// A missing or invalid `skin` value means the default skin.
+pub(crate) fn add_model_skin(
+    model: &str,
+    skin: Option<&str>,
+    options: &ParseOptions,
+    uassets: &mut UniqueAssets,
+) {
+    let model = model.trim();
+    if !options.minimal_skins || !model.to_ascii_lowercase().ends_with(".mdl") {
+        return;
+    }
+    let skin = skin.and_then(|s| s.trim().parse().ok()).unwrap_or(0);
+    trace!("Found model {} with skin {}", model, skin);
+    uassets
+        .model_skins
+        .entry(model_key(model))
+        .or_default()
+        .insert(skin);
+}
+
+/// Skins set at runtime through entity I/O: the `Skin` input and `AddOutput "skin N"`.
+/// Their targets are entity names, so they are resolved once every entity of the map is
+/// known, see [`SkinInputs::resolve`]. Only collected with `ParseOptions::minimal_skins`.
+#[derive(Debug, Default)]
+pub(crate) struct SkinInputs {
+    enabled: bool,
+    /// Models of the entities with each name (lowercase). Entities without a model have
+    /// an empty set.
+    names: HashMap<String, HashSet<PathBuf>>,
+    /// Models of the entities of each class (lowercase), for targets naming a class.
+    classes: HashMap<String, HashSet<PathBuf>>,
+    /// Skins set on each target; `None` when the value isn't a number.
+    inputs: Vec<(SkinTarget, Option<i32>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SkinTarget {
+    /// An entity name or class (lowercase), possibly ending with a `*` wildcard.
+    Name(String),
+    /// The model of the entity firing the output (`!self`).
+    Model(PathBuf),
+    /// A target only known at runtime, like `!activator`.
+    Unknown,
+}
+
+impl SkinInputs {
+    pub fn new(options: &ParseOptions) -> Self {
+        Self {
+            enabled: options.minimal_skins,
+            ..Self::default()
+        }
+    }
+
+    /// Registers an entity skin inputs may target.
+    pub fn add_entity(&mut self, name: Option<&str>, classname: &str, model: Option<&str>) {
+        if !self.enabled {
+            return;
+        }
+        let model = model
+            .map(str::trim)
+            .filter(|model| model.to_ascii_lowercase().ends_with(".mdl"))
+            .map(model_key);
+        if let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) {
+            let models = self.names.entry(name.to_lowercase()).or_default();
+            models.extend(model.clone());
+        }
+        if let Some(model) = model {
+            let models = self.classes.entry(classname.to_lowercase()).or_default();
+            models.insert(model);
+        }
+    }
+
+    /// Records the skin set by a connection of an entity placed with `model`, if any.
+    pub fn add_connection(&mut self, connection: &Connection, model: Option<&str>) {
+        if !self.enabled {
+            return;
+        }
+        let this = model.map(|model| SkinTarget::Model(model_key(model.trim())));
+        self.add_input(connection, this.as_ref());
+    }
+
+    fn add_input(&mut self, connection: &Connection, this: Option<&SkinTarget>) {
+        let target = match connection.target.to_lowercase() {
+            name if name == "!self" => match this {
+                Some(this) => this.clone(),
+                None => return,
+            },
+            name if name.starts_with('!') => SkinTarget::Unknown,
+            name => SkinTarget::Name(name),
+        };
+        let (input, param) = (connection.input, connection.param);
+
+        if input.eq_ignore_ascii_case("Skin") {
+            trace!("Found skin input {:?} -> {}", target, param);
+            self.inputs.push((target, param.parse().ok()));
+        } else if input.eq_ignore_ascii_case("AddOutput") {
+            let Some((key, value)) = param.trim().split_once(char::is_whitespace) else {
+                return;
+            };
+            if key.eq_ignore_ascii_case("skin") {
+                trace!("Found skin key value {:?} -> {}", target, value);
+                self.inputs.push((target, value.trim().parse().ok()));
+            } else if let Some(output) = parse_connection_fields(value.trim(), ':') {
+                // The added output belongs to the target, which is its `!self`.
+                self.add_input(&output, Some(&target));
+            }
+        }
+    }
+
+    /// Adds the recorded skins to `uassets.model_skins`. When a target can't be resolved,
+    /// every entity model of the map uses all of its skins; when a value isn't a number,
+    /// the target's models do.
+    pub fn resolve(self, uassets: &mut UniqueAssets) {
+        for (target, skin) in &self.inputs {
+            let models = match target {
+                SkinTarget::Model(model) => Some(HashSet::from([model.clone()])),
+                SkinTarget::Name(name) => self.find(name),
+                SkinTarget::Unknown => None,
+            };
+            match (models, skin) {
+                (Some(models), Some(skin)) => {
+                    for model in models {
+                        uassets.model_skins.entry(model).or_default().insert(*skin);
+                    }
+                }
+                (Some(models), None) => uassets.all_skins_models.extend(models),
+                (None, _) => {
+                    trace!(
+                        "Skin input target {:?} not found, using every skin.",
+                        target
+                    );
+                    let models = self.classes.values().flatten().cloned();
+                    uassets.all_skins_models.extend(models);
+                }
+            }
+        }
+    }
+
+    /// Returns the models of the entities a target selects, by name or else by class.
+    /// `None` if it selects no entity.
+    fn find(&self, target: &str) -> Option<HashSet<PathBuf>> {
+        let matches = |name: &str| match target.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == target,
+        };
+        [&self.names, &self.classes]
+            .into_iter()
+            .find_map(|entities| {
+                let mut found = entities.iter().filter(|(name, _)| matches(name)).peekable();
+                found.peek()?;
+                Some(found.flat_map(|(_, models)| models).cloned().collect())
+            })
+    }
+}
+
+/// Normalizes a model path for lookups in `UniqueAssets::model_skins`.
+pub(crate) fn model_key(model: &str) -> PathBuf {
+    model.replace('\\', "/").to_lowercase().into()
+}
+
+/// Returns the name of a map as used by the engine: the lowercase file stem.
+pub(crate) fn map_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Adds a value whose asset kind is known from the FGD.
+fn add_typed_asset(kind: AssetKind, value: &str, uassets: &mut UniqueAssets) {
+    match kind {
+        AssetKind::Model if is_sprite(value) => {
+            uassets.materials_name.insert(material_name(value));
+        }
+        AssetKind::Model => {
+            if value.to_ascii_lowercase().ends_with(".mdl") {
+                trace!("Found model: {}", value);
+                uassets.models_name.insert(value.replace('\\', "/").into());
+            }
+        }
+        AssetKind::Sprite | AssetKind::Material | AssetKind::Decal => {
+            trace!("Found material: {}", value);
+            uassets.materials_name.insert(material_name(value));
+        }
+        AssetKind::Sound if has_sound_suffix(value) => {
+            trace!("Found sound: {}", value);
+            uassets.sounds_name.insert(sound_name(value));
+        }
+        AssetKind::Sound => {
+            trace!("Found soundscript entry: {}", value);
+            uassets.soundscripts_name.insert(value.to_lowercase());
+        }
+        AssetKind::Sky => add_skybox(value, uassets),
+        // Particle systems are tracked per map, see `add_map_keyvalue`.
+        AssetKind::Particle => {}
+    }
+}
+
+/// Classifies a key value by its key name and suffix, used when no FGD describes the entity.
+fn add_by_heuristics(key: &str, value: &str, uassets: &mut UniqueAssets) {
+    if key.eq_ignore_ascii_case("model") {
+        if is_sprite(value) {
+            uassets.materials_name.insert(material_name(value));
+        } else if value.to_ascii_lowercase().starts_with("models")
+            || value.to_ascii_lowercase().ends_with(".mdl")
+        {
+            uassets.models_name.insert(value.replace('\\', "/").into());
+        }
+    } else if ["material", "materials", "texture"]
+        .iter()
+        .any(|k| key.eq_ignore_ascii_case(k))
+    {
+        trace!("Found material from '{}' key: {}", key, value);
+        uassets.materials_name.insert(material_name(value));
+    }
+
+    if has_sound_suffix(value) {
+        trace!("Found sound: {}", value);
+        uassets.sounds_name.insert(sound_name(value));
+    } else if !NAME_KEYS.iter().any(|k| key.eq_ignore_ascii_case(k)) && is_soundscript_name(value) {
+        // Only a candidate: names that aren't defined in any soundscript are dropped later.
+        trace!("Found possible soundscript entry: {}", value);
+        uassets.soundscripts_name.insert(value.to_lowercase());
+    }
+}
+
+/// Whether a value looks like a soundscript entry name, e.g. `Weather.Rain`.
+fn is_soundscript_name(value: &str) -> bool {
+    let Some((group, ext)) = value.rsplit_once('.') else {
+        return false;
+    };
+    !group.is_empty()
+        && !ext.is_empty()
+        && value.starts_with(|c: char| c.is_ascii_alphabetic())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        && !FILE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e))
+}
+
+fn is_sprite(value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    value.ends_with(".vmt") || value.ends_with(".spr")
+}
+
+pub(crate) fn has_sound_suffix(value: &str) -> bool {
+    let value = value.as_bytes();
+    SOUND_SUFFIXES.iter().any(|suffix| {
+        let suffix = suffix.as_bytes();
+        value.len() >= suffix.len()
+            && value[value.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+    })
+}
+
+/// Normalizes a material reference to a path relative to `materials/`, without extension.
+pub(crate) fn material_name(value: &str) -> PathBuf {
+    let value = value.trim().replace('\\', "/");
+    let lower = value.to_ascii_lowercase();
+    let value = if lower.starts_with("materials/") {
+        &value["materials/".len()..]
+    } else {
+        &value
+    };
+    let value = if is_sprite(value) {
+        &value[..value.len() - 4]
+    } else {
+        value
+    };
+    value.into()
+}
+
+/// Normalizes a sound reference to a path relative to `sound/`, without prefix characters.
+pub(crate) fn sound_name(value: &str) -> PathBuf {
+    let value = value
+        .trim()
+        .trim_start_matches(SOUND_CHARS)
+        .replace('\\', "/");
+    let value = if value.to_ascii_lowercase().starts_with("sound/") {
+        &value["sound/".len()..]
+    } else {
+        &value
+    };
+    value.into()
+}
+
+/// Extracts assets referenced by worldspawn key values: skybox and detail sprites.
+///
+/// `get` looks up a worldspawn key, which lets both parsers share this logic.
+pub(crate) fn add_worldspawn<'a>(
+    get: impl Fn(&str) -> Option<&'a str>,
+    uassets: &mut UniqueAssets,
+) {
+    if let Some(skyname) = get("skyname") {
+        add_skybox(skyname, uassets);
+    }
+
+    if let Some(material) = get("detailmaterial").filter(|m| !m.trim().is_empty()) {
+        trace!("Found detail material: {}", material);
+        uassets
+            .materials_name
+            .insert(material.trim().replace('\\', "/").to_lowercase().into());
+    }
+    if let Some(vbsp) = get("detailvbsp").filter(|v| !v.trim().is_empty()) {
+        trace!("Found detail vbsp file: {}", vbsp);
+        uassets
+            .detail_vbsp_name
+            .insert(vbsp.trim().replace('\\', "/").to_lowercase().into());
+    }
+}
+
+/// Adds the six faces of a skybox (and their `_hdr` variants) to the material set.
+///
+/// `skyname` is the worldspawn `skyname` value, e.g. `sky_day01_01`, which maps to
+/// `materials/skybox/sky_day01_01up.vmt` and friends.
+fn add_skybox(skyname: &str, uassets: &mut UniqueAssets) {
+    let skyname = skyname.trim().to_lowercase();
+    if skyname.is_empty() {
+        return;
+    }
+    trace!("Found skybox: {}", skyname);
+
+    for variant in [skyname.clone(), format!("{skyname}_hdr")] {
+        for face in SKYBOX_FACES {
+            uassets
+                .materials_name
+                .insert(format!("skybox/{variant}{face}").into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_connection_escape_separated() {
+        let connection =
+            parse_connection("relay\x1BRunScriptCode\x1BFire(1, \"a,b\")\x1B0.5\x1B-1").unwrap();
+        assert_eq!(
+            connection,
+            Connection {
+                target: "relay",
+                input: "RunScriptCode",
+                param: "Fire(1, \"a,b\")",
+                delay: 0.5,
+                refire: -1,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_connection_comma_separated() {
+        let connection = parse_connection("speaker, PlaySound, ambient/alarm.wav , 2, 1").unwrap();
+        assert_eq!(
+            connection,
+            Connection {
+                target: "speaker",
+                input: "PlaySound",
+                param: "ambient/alarm.wav",
+                delay: 2.0,
+                refire: 1,
+            }
+        );
+        assert_eq!(parse_connection("door,Open,,0,-1").unwrap().param, "");
+    }
+
+    #[test]
+    fn parse_connection_rejects_other_values() {
+        assert_eq!(parse_connection("models/props/crate.mdl"), None);
+        assert_eq!(parse_connection("a,b,c,d"), None);
+        assert_eq!(parse_connection("a,b,c,d,e,f"), None);
+        assert_eq!(parse_connection("door,Open,,soon,-1"), None);
+        // Commas in the parameter only work with the escape separator.
+        assert_eq!(
+            parse_connection("relay,RunScriptCode,Fire(1, 2),0,-1"),
+            None
+        );
+    }
+
+    #[test]
+    fn add_connection_follows_add_output() {
+        let options = ParseOptions::default();
+        let mut uassets = UniqueAssets::default();
+        let connection = parse_connection(
+            "!self\x1BAddOutput\x1BOnTrigger prop:SetModel:models/props/crate.mdl:0:-1\x1B0\x1B-1",
+        )
+        .unwrap();
+        add_connection(&connection, &options, &mut uassets);
+        let connection = parse_connection("logic,RunScriptFile,Puzzles\\Door.nut,0,1").unwrap();
+        add_connection(&connection, &options, &mut uassets);
+
+        assert!(
+            uassets
+                .models_name
+                .contains(Path::new("models/props/crate.mdl"))
+        );
+        assert!(
+            uassets
+                .vscripts_name
+                .contains(Path::new("puzzles/door.nut"))
+        );
+    }
+
+    fn skin_inputs(entities: &[(&str, &str, &str, &[&str])]) -> UniqueAssets {
+        let options = ParseOptions {
+            minimal_skins: true,
+            ..Default::default()
+        };
+        let mut skins = SkinInputs::new(&options);
+        for (name, classname, model, connections) in entities {
+            let model = Some(*model).filter(|model| !model.is_empty());
+            skins.add_entity(Some(name), classname, model);
+            for value in connections.iter() {
+                skins.add_connection(&parse_connection(value).unwrap(), model);
+            }
+        }
+        let mut uassets = UniqueAssets::default();
+        skins.resolve(&mut uassets);
+        uassets
+    }
+
+    fn skins(uassets: &UniqueAssets, model: &str) -> Vec<i32> {
+        let mut skins: Vec<i32> = uassets.model_skins[Path::new(model)]
+            .iter()
+            .copied()
+            .collect();
+        skins.sort();
+        skins
+    }
+
+    #[test]
+    fn skin_inputs_resolve_names_classes_and_self() {
+        let uassets = skin_inputs(&[
+            ("Door_1", "prop_dynamic", "models/Door.mdl", &[]),
+            ("door_2", "prop_dynamic", "models/door.mdl", &[]),
+            ("", "prop_physics", "models/crate.mdl", &[]),
+            (
+                "lamp",
+                "prop_dynamic",
+                "models/lamp.mdl",
+                &[
+                    "!self,Skin,1,0,-1",
+                    "!self,AddOutput,OnUser1 !self:Skin:2:0:-1,0,-1",
+                ],
+            ),
+            (
+                "relay",
+                "logic_relay",
+                "",
+                &[
+                    "door_1\x1BSkin\x1B3\x1B0\x1B-1",
+                    "door_*,AddOutput,skin 4,0,-1",
+                    "prop_physics,Skin,5,0,-1",
+                    "relay,Skin,7,0,-1",
+                ],
+            ),
+        ]);
+
+        assert_eq!(skins(&uassets, "models/door.mdl"), [3, 4]);
+        assert_eq!(skins(&uassets, "models/crate.mdl"), [5]);
+        assert_eq!(skins(&uassets, "models/lamp.mdl"), [1, 2]);
+        assert!(uassets.all_skins_models.is_empty());
+    }
+
+    #[test]
+    fn skin_inputs_fall_back_to_every_skin() {
+        let uassets = skin_inputs(&[
+            ("door", "prop_dynamic", "models/door.mdl", &[]),
+            ("crate", "prop_physics", "models/crate.mdl", &[]),
+            ("relay", "logic_relay", "", &["door,Skin,$skin,0,-1"]),
+        ]);
+        let all: HashSet<PathBuf> = ["models/door.mdl".into()].into();
+        assert_eq!(uassets.all_skins_models, all);
+
+        for target in ["!activator", "nothing", "crates*"] {
+            let connection = format!("{},Skin,1,0,-1", target);
+            let uassets = skin_inputs(&[
+                ("door", "prop_dynamic", "models/door.mdl", &[]),
+                ("crate", "prop_physics", "models/crate.mdl", &[]),
+                ("relay", "logic_relay", "", &[&connection]),
+            ]);
+            let all: HashSet<PathBuf> =
+                ["models/door.mdl".into(), "models/crate.mdl".into()].into();
+            assert_eq!(uassets.all_skins_models, all);
+        }
+    }
+}
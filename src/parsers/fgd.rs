@@ -0,0 +1,571 @@
+//! Parser for Forge Game Data (FGD) files.
+//!
+//! Only the parts needed to classify entity keys are kept: class names, `base()` inheritance
+//! and property types. Inputs, outputs, choices and other helpers are parsed and discarded.
+
+use log::{debug, warn};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Kind of asset an entity key refers to, derived from the FGD property type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    /// `studio`: a model path.
+    Model,
+    /// `sprite`: a sprite material (`.vmt` / `.spr`).
+    Sprite,
+    /// `material`: a material path.
+    Material,
+    /// `decal`: a decal material.
+    Decal,
+    /// `sound`: a sound file or soundscript entry.
+    Sound,
+    /// `sky`: a skybox name.
+    Sky,
+    /// `particlesystem`: a particle system name.
+    Particle,
+}
+
+impl AssetKind {
+    fn from_type(value_type: &str) -> Option<Self> {
+        match value_type.to_ascii_lowercase().as_str() {
+            "studio" => Some(Self::Model),
+            "sprite" => Some(Self::Sprite),
+            "material" => Some(Self::Material),
+            "decal" => Some(Self::Decal),
+            "sound" => Some(Self::Sound),
+            "sky" => Some(Self::Sky),
+            "particlesystem" => Some(Self::Particle),
+            _ => None,
+        }
+    }
+}
+
+/// An entity class as declared in the FGD, before inheritance is resolved.
+#[derive(Debug, Default)]
+struct EntityClass {
+    bases: Vec<String>,
+    /// Lowercase property name -> asset kind, only for asset properties.
+    properties: Vec<(String, AssetKind)>,
+}
+
+/// Entity definitions loaded from one or more FGD files.
+#[derive(Debug, Default)]
+pub struct Fgd {
+    /// Lowercase class name -> (lowercase key -> asset kind), with inheritance resolved.
+    classes: HashMap<String, HashMap<String, AssetKind>>,
+}
+
+impl Fgd {
+    /// Loads and merges the given FGD files, following `@include` directives.
+    pub fn load(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut loader = Loader::default();
+        for path in paths {
+            loader.load_file(path)?;
+        }
+        Ok(loader.resolve())
+    }
+
+    /// Returns the kind of asset referenced by `key` on entities of class `classname`.
+    pub fn asset_kind(&self, classname: &str, key: &str) -> Option<AssetKind> {
+        self.class_keys(classname)?
+            .get(&key.to_ascii_lowercase())
+            .copied()
+    }
+
+    /// Returns `true` if the class is defined in the loaded FGDs.
+    pub fn has_class(&self, classname: &str) -> bool {
+        self.class_keys(classname).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    fn class_keys(&self, classname: &str) -> Option<&HashMap<String, AssetKind>> {
+        self.classes.get(&classname.to_ascii_lowercase())
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    classes: HashMap<String, EntityClass>,
+    loaded: HashSet<PathBuf>,
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path) -> io::Result<()> {
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if !self.loaded.insert(key) {
+            return Ok(());
+        }
+
+        debug!("Loading FGD: {}", path.display());
+        let data = fs::read(path)?;
+        let text = String::from_utf8_lossy(&data);
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        for include in self.parse(&text) {
+            let include_path = dir.join(&include);
+            if let Err(err) = self.load_file(&include_path) {
+                warn!(
+                    "Failed to load FGD include \"{}\" from {}: {}",
+                    include,
+                    path.display(),
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses FGD text, registering its classes. Returns the `@include`d file names.
+    fn parse(&mut self, text: &str) -> Vec<String> {
+        let mut includes = Vec::new();
+        let mut tokens = Tokens::new(text);
+
+        while let Some(token) = tokens.next() {
+            let Token::At(directive) = token else {
+                continue;
+            };
+            let directive = directive.to_ascii_lowercase();
+
+            if directive == "include" {
+                if let Some(Token::Str(file)) = tokens.next() {
+                    includes.push(file);
+                }
+            } else if directive.ends_with("class") {
+                self.parse_class(&directive, &mut tokens);
+            } else {
+                // @mapsize(...), @MaterialExclusion [...], @AutoVisGroup = "..." [...], etc.
+                skip_directive(&mut tokens);
+            }
+        }
+
+        includes
+    }
+
+    fn parse_class(&mut self, directive: &str, tokens: &mut Tokens) {
+        let mut bases = Vec::new();
+
+        // Helpers: `base(A, B) studio("...") color(0 0 0) ...` up to `=`.
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Punct('=') => break,
+                Token::Ident(helper) => {
+                    if tokens.peek() != Some(&Token::Punct('(')) {
+                        continue;
+                    }
+                    let args = read_parens(tokens);
+                    if helper.eq_ignore_ascii_case("base") {
+                        bases.extend(args.into_iter().filter_map(|t| match t {
+                            Token::Ident(name) => Some(name.to_ascii_lowercase()),
+                            _ => None,
+                        }));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let Some(Token::Ident(name)) = tokens.next() else {
+            return;
+        };
+        let name = name.to_ascii_lowercase();
+
+        // Optional description: `: "text" + "text"`
+        if tokens.peek() == Some(&Token::Punct(':')) {
+            tokens.next();
+            read_value(tokens);
+        }
+
+        let mut properties = Vec::new();
+        if tokens.peek() == Some(&Token::Punct('[')) {
+            tokens.next();
+            properties = parse_class_body(tokens);
+        }
+
+        let class = self.classes.entry(name).or_default();
+        // @ExtendClass / @OverrideClass add to an existing definition, others replace it.
+        if !matches!(directive, "extendclass" | "overrideclass") {
+            *class = EntityClass::default();
+        }
+        class.bases.extend(bases);
+        class.properties.extend(properties);
+    }
+
+    /// Flattens inheritance into per-class key tables.
+    fn resolve(self) -> Fgd {
+        let mut classes = HashMap::with_capacity(self.classes.len());
+        for name in self.classes.keys() {
+            let mut keys = HashMap::new();
+            let mut visited = HashSet::new();
+            self.collect_keys(name, &mut keys, &mut visited);
+            classes.insert(name.clone(), keys);
+        }
+        Fgd { classes }
+    }
+
+    fn collect_keys(
+        &self,
+        name: &str,
+        keys: &mut HashMap<String, AssetKind>,
+        visited: &mut HashSet<String>,
+    ) {
+        if !visited.insert(name.to_string()) {
+            return;
+        }
+        let Some(class) = self.classes.get(name) else {
+            return;
+        };
+        // Bases first, so the class's own properties override inherited ones.
+        for base in &class.bases {
+            self.collect_keys(base, keys, visited);
+        }
+        for (key, kind) in &class.properties {
+            keys.insert(key.clone(), *kind);
+        }
+    }
+}
+
+/// Parses the `[ ... ]` body of a class, returning the asset properties.
+fn parse_class_body(tokens: &mut Tokens) -> Vec<(String, AssetKind)> {
+    let mut properties = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let name = match token {
+            Token::Punct(']') => break,
+            Token::Ident(name) => name,
+            _ => continue,
+        };
+
+        // `input Name(type) : "desc"` / `output Name(type) : "desc"`
+        let is_io = name.eq_ignore_ascii_case("input") || name.eq_ignore_ascii_case("output");
+        if is_io && matches!(tokens.peek(), Some(Token::Ident(_))) {
+            tokens.next();
+        }
+
+        if tokens.peek() != Some(&Token::Punct('(')) {
+            continue;
+        }
+        let value_type = read_parens(tokens);
+
+        // Flags like `readonly` or `report` may follow the type.
+        while matches!(tokens.peek(), Some(Token::Ident(_))) && !starts_property(tokens) {
+            tokens.next();
+        }
+        // `: "Display name" : default : "Description"`, any part may be empty.
+        while tokens.peek() == Some(&Token::Punct(':')) {
+            tokens.next();
+            read_value(tokens);
+        }
+        // Choices / flags list.
+        if tokens.peek() == Some(&Token::Punct('=')) {
+            tokens.next();
+            if tokens.peek() == Some(&Token::Punct('[')) {
+                tokens.next();
+                skip_brackets(tokens);
+            }
+        }
+
+        if is_io {
+            continue;
+        }
+        if let [Token::Ident(value_type)] = value_type.as_slice()
+            && let Some(kind) = AssetKind::from_type(value_type)
+        {
+            properties.push((name.to_ascii_lowercase(), kind));
+        }
+    }
+
+    properties
+}
+
+/// Whether the upcoming tokens look like the start of a new property (`name(`).
+fn starts_property(tokens: &Tokens) -> bool {
+    matches!(tokens.peek_nth(1), Some(Token::Punct('(')))
+}
+
+/// Reads a single value after `:`, joining `"a" + "b"` string concatenations.
+fn read_value(tokens: &mut Tokens) {
+    if matches!(
+        tokens.peek(),
+        Some(Token::Str(_)) | Some(Token::Ident(_)) | Some(Token::Number(_))
+    ) && !(matches!(tokens.peek(), Some(Token::Ident(_))) && starts_property(tokens))
+    {
+        tokens.next();
+        while tokens.peek() == Some(&Token::Punct('+')) {
+            tokens.next();
+            tokens.next();
+        }
+    }
+}
+
+/// Reads a parenthesized argument list, returning the tokens inside.
+fn read_parens(tokens: &mut Tokens) -> Vec<Token> {
+    let mut args = Vec::new();
+    if tokens.peek() != Some(&Token::Punct('(')) {
+        return args;
+    }
+    tokens.next();
+
+    let mut depth = 1;
+    for token in tokens.by_ref() {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            Token::Punct(',') => continue,
+            _ => {}
+        }
+        args.push(token);
+    }
+    args
+}
+
+/// Skips tokens up to and including the `]` matching an already consumed `[`.
+fn skip_brackets(tokens: &mut Tokens) {
+    let mut depth = 1;
+    for token in tokens.by_ref() {
+        match token {
+            Token::Punct('[') => depth += 1,
+            Token::Punct(']') => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Skips an unsupported `@directive` and its arguments.
+fn skip_directive(tokens: &mut Tokens) {
+    read_parens(tokens);
+    if tokens.peek() == Some(&Token::Punct('=')) {
+        tokens.next();
+        read_value(tokens);
+    }
+    if tokens.peek() == Some(&Token::Punct('[')) {
+        tokens.next();
+        skip_brackets(tokens);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// `@Name` directive, without the `@`.
+    At(String),
+    Ident(String),
+    Str(String),
+    Number(String),
+    Punct(char),
+}
+
+struct Tokens {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Tokens {
+    fn new(text: &str) -> Self {
+        Self {
+            tokens: tokenize(text),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n)
+    }
+}
+
+impl Iterator for Tokens {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    let is_ident = |c: char| c.is_alphanumeric() || matches!(c, '_' | '$' | '.' | '-');
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if text[start..].starts_with("//") {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            for (_, c) in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                value.push(c);
+            }
+            tokens.push(Token::Str(value));
+        } else if c == '@' {
+            chars.next();
+            let mut name = String::new();
+            while let Some((_, c)) = chars.next_if(|&(_, c)| is_ident(c)) {
+                name.push(c);
+            }
+            tokens.push(Token::At(name));
+        } else if is_ident(c) {
+            let mut value = String::new();
+            while let Some((_, c)) = chars.next_if(|&(_, c)| is_ident(c)) {
+                value.push(c);
+            }
+            let is_number = value
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '-' | '.'));
+            tokens.push(if is_number {
+                Token::Number(value)
+            } else {
+                Token::Ident(value)
+            });
+        } else {
+            chars.next();
+            tokens.push(Token::Punct(c));
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes FGD files into a fresh temporary directory.
+    fn write_fgds(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fgd-test-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        dir
+    }
+
+    const BASE_FGD: &str = r#"
+        @mapsize(-16384, 16384)
+        @BaseClass = Targetname [ targetname(target_source) : "Name" ]
+        @BaseClass base(Targetname) = Studiomodel
+        [
+            model(studio) : "World Model" : : "The model to use."
+            skin(integer) : "Skin" : 0
+        ]
+        // Includes back to the main file are ignored.
+        @include "main.fgd"
+    "#;
+
+    const MAIN_FGD: &str = r#"
+        @include "base.fgd"
+        @PointClass base(Targetname, Studiomodel) studio() = prop_custom : "A prop"
+            + " with a long description."
+        [
+            gibsprite(sprite) readonly : "Gib Sprite" : "sprites/gib.vmt"
+            gibsound(sound) : "Gib Sound"
+            input SetModel(string) : "Set the model."
+            output OnBreak(void) : "Fired when broken."
+            model(material) : "Overridden"
+        ]
+        @SolidClass base(Targetname) = func_custom
+        [
+            spawnflags(flags) = [ 1 : "Start off" : 0 ]
+            effect(particlesystem) : "Effect"
+        ]
+        @ExtendClass = func_custom [ texture(decal) : "Decal" ]
+    "#;
+
+    #[test]
+    fn load_follows_includes_and_bases() {
+        let dir = write_fgds("bases", &[("base.fgd", BASE_FGD), ("main.fgd", MAIN_FGD)]);
+        let fgd = Fgd::load(&[dir.join("main.fgd")]).unwrap();
+
+        assert!(fgd.has_class("Studiomodel"));
+        assert!(fgd.has_class("PROP_CUSTOM"));
+        assert_eq!(
+            fgd.asset_kind("Studiomodel", "Model"),
+            Some(AssetKind::Model)
+        );
+        assert_eq!(fgd.asset_kind("Studiomodel", "skin"), None);
+        // Own properties override inherited ones.
+        assert_eq!(
+            fgd.asset_kind("prop_custom", "model"),
+            Some(AssetKind::Material)
+        );
+        assert_eq!(
+            fgd.asset_kind("prop_custom", "gibsprite"),
+            Some(AssetKind::Sprite)
+        );
+        assert_eq!(
+            fgd.asset_kind("prop_custom", "gibsound"),
+            Some(AssetKind::Sound)
+        );
+        assert_eq!(fgd.asset_kind("prop_custom", "setmodel"), None);
+        assert_eq!(fgd.asset_kind("prop_custom", "onbreak"), None);
+        assert_eq!(
+            fgd.asset_kind("func_custom", "effect"),
+            Some(AssetKind::Particle)
+        );
+        assert_eq!(
+            fgd.asset_kind("func_custom", "texture"),
+            Some(AssetKind::Decal)
+        );
+        assert_eq!(fgd.asset_kind("func_custom", "model"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_resolves_bases_across_files_and_cycles() {
+        let dir = write_fgds(
+            "cycles",
+            &[
+                ("a.fgd", "@BaseClass base(B) = A [ sound(sound) : \"\" ]"),
+                ("b.fgd", "@BaseClass base(A) = B [ sprite(sprite) : \"\" ]"),
+            ],
+        );
+        let fgd = Fgd::load(&[dir.join("a.fgd"), dir.join("b.fgd")]).unwrap();
+        for class in ["a", "b"] {
+            assert_eq!(fgd.asset_kind(class, "sound"), Some(AssetKind::Sound));
+            assert_eq!(fgd.asset_kind(class, "sprite"), Some(AssetKind::Sprite));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_skips_missing_includes() {
+        let dir = write_fgds(
+            "missing",
+            &[(
+                "main.fgd",
+                "@include \"missing.fgd\"\n@PointClass = info_custom [ model(studio) ]",
+            )],
+        );
+        let fgd = Fgd::load(&[dir.join("main.fgd")]).unwrap();
+        assert_eq!(
+            fgd.asset_kind("info_custom", "model"),
+            Some(AssetKind::Model)
+        );
+        assert!(Fgd::load(&[dir.join("missing.fgd")]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
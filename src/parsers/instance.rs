@@ -0,0 +1,643 @@
+//! Resolution of `func_instance` entities for the VMF parser.
+//!
+//! Instances are separate VMF files that get merged into the map by VBSP at compile time,
+//! so every asset placed inside them has to be collected as if it was part of the map itself.
+
+use log::{debug, trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use vmf_forge::VmfFile;
+use vmf_forge::vmf::entities::Entity;
+use vmf_forge::vmf::world::Solid;
+
+use super::{ParseOptions, common, vmf};
+use crate::asset_processor::UniqueAssets;
+#[cfg(unix)]
+use crate::utils;
+
+/// Keys holding entity names, fixed up by VBSP like `targetname`.
+const NAME_KEYS: [&str; 5] = [
+    "targetname",
+    "parentname",
+    "target",
+    "damagefilter",
+    "filtername",
+];
+
+/// Walks `func_instance` entities recursively and collects assets from the referenced VMFs.
+pub(super) struct InstanceResolver<'a> {
+    options: &'a ParseOptions,
+    /// Name of the top-level map, instances contribute to it.
+    map: String,
+    /// Directory of the top-level map; instances are also searched relative to it.
+    root_dir: PathBuf,
+    /// Additional instance roots (`sdk_content/maps` directories found above the map).
+    search_dirs: Vec<PathBuf>,
+    /// Instances currently being processed, used for cycle detection.
+    stack: Vec<PathBuf>,
+    /// Parsed instance files. `None` marks a file that failed to parse.
+    cache: HashMap<PathBuf, Option<VmfFile>>,
+    /// Instance files already collected with a given set of fixups.
+    processed: HashSet<(PathBuf, Vec<(String, String)>)>,
+}
+
+impl<'a> InstanceResolver<'a> {
+    pub fn new(map_path: &Path, options: &'a ParseOptions) -> Self {
+        let root_dir = map_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let search_dirs = root_dir
+            .ancestors()
+            .map(|dir| dir.join("sdk_content").join("maps"))
+            .filter(|dir| dir.is_dir())
+            .collect();
+
+        Self {
+            options,
+            map: common::map_name(map_path),
+            root_dir,
+            search_dirs,
+            stack: Vec::new(),
+            cache: HashMap::new(),
+            processed: HashSet::new(),
+        }
+    }
+
+    /// Collects assets from every `func_instance` placed in `vmf`, following nested instances.
+    /// Their entities and skin inputs are recorded in `skins`.
+    pub fn process(
+        &mut self,
+        vmf: &VmfFile,
+        vmf_path: &Path,
+        uassets: &mut UniqueAssets,
+        skins: &mut common::SkinInputs,
+    ) {
+        self.stack.push(canonical(vmf_path));
+        let vmf_dir = vmf_path.parent().unwrap_or(Path::new(""));
+        let filter = vmf::VisgroupFilter::new(vmf, self.options);
+
+        for ent in vmf.entities.iter() {
+            if !ent
+                .classname()
+                .is_some_and(|c| c.eq_ignore_ascii_case("func_instance"))
+                || filter.is_excluded(&ent.editor)
+            {
+                continue;
+            }
+            let Some(file) = ent.get("file").filter(|f| !f.is_empty()) else {
+                continue;
+            };
+
+            let Some(instance_path) = self.find_instance_file(file, vmf_dir) else {
+                warn!(
+                    "Instance \"{}\" referenced in {} not found.",
+                    file,
+                    vmf_path.display()
+                );
+                continue;
+            };
+
+            let instance_key = canonical(&instance_path);
+            if self.stack.contains(&instance_key) {
+                warn!(
+                    "Recursive instance \"{}\" in {}, skipping.",
+                    file,
+                    vmf_path.display()
+                );
+                continue;
+            }
+
+            let Some(mut instance) = self.load(&instance_key) else {
+                continue;
+            };
+
+            let mut fixups = Fixups::from_instance(ent);
+            fixups.add_defaults(&instance);
+            // Skin inputs target entities by name, so every named copy is recorded for them.
+            let key = fixups.key(self.options.minimal_skins);
+            if !self.processed.insert((instance_key, key)) {
+                trace!("Instance {} already processed.", instance_path.display());
+                continue;
+            }
+
+            debug!("Processing instance: {}", instance_path.display());
+            fixups.apply(&mut instance);
+            vmf::collect_assets(&instance, &self.map, uassets, skins, self.options);
+            self.process(&instance, &instance_path, uassets, skins);
+        }
+
+        self.stack.pop();
+    }
+
+    /// Looks for an instance file relative to the referencing VMF, the map and the instance roots.
+    fn find_instance_file(&self, file: &str, vmf_dir: &Path) -> Option<PathBuf> {
+        let mut relative = PathBuf::from(file.replace('\\', "/"));
+        if relative.extension().is_none() {
+            relative.set_extension("vmf");
+        }
+
+        let candidates = std::iter::once(vmf_dir)
+            .chain(std::iter::once(self.root_dir.as_path()))
+            .chain(self.search_dirs.iter().map(PathBuf::as_path));
+
+        for dir in candidates {
+            let path = dir.join(&relative);
+            if path.is_file() {
+                return Some(path);
+            }
+            // Source engine is not case-sensitive, unlike unix-like filesystems
+            #[cfg(unix)]
+            if let Ok(Some(path)) = utils::find_asset_case_insensitive(dir, &relative)
+                && path.is_file()
+            {
+                return Some(path);
+            }
+        }
+
+        None
+    }
+
+    /// Returns a fresh copy of the parsed instance, reading it from disk on first use.
+    fn load(&mut self, path: &Path) -> Option<VmfFile> {
+        self.cache
+            .entry(path.to_path_buf())
+            .or_insert_with(|| match VmfFile::open(path) {
+                Ok(vmf) => Some(vmf),
+                Err(err) => {
+                    warn!("Failed to parse instance {}: {}", path.display(), err);
+                    None
+                }
+            })
+            .clone()
+    }
+}
+
+/// How the names of the entities of an instance are made unique (`fixup_style`), using the
+/// name of the `func_instance`.
+#[derive(Debug, Clone, PartialEq)]
+enum NameFixup {
+    /// `instance-name`, the default.
+    Prefix(String),
+    /// `name-instance`.
+    Postfix(String),
+}
+
+impl NameFixup {
+    /// Returns the fixed up name, or `None` for names that are left as is: empty ones and
+    /// global names starting with `@` or `!`.
+    fn apply(&self, name: &str) -> Option<String> {
+        let name = name.trim();
+        if name.is_empty() || name.starts_with(['@', '!']) {
+            return None;
+        }
+        Some(match self {
+            Self::Prefix(fixup) => format!("{}-{}", fixup, name),
+            Self::Postfix(fixup) => format!("{}-{}", name, fixup),
+        })
+    }
+}
+
+/// Replacement parameters of a single `func_instance` (`replace01` .. `replaceNN`).
+#[derive(Debug, Default)]
+struct Fixups {
+    /// `$variable` -> value pairs applied to entity key values.
+    variables: Vec<(String, String)>,
+    /// `#material` -> material pairs applied to brush faces (lowercase).
+    materials: Vec<(String, String)>,
+    /// Entity name fixup. Unnamed instances get a generated name nothing outside of the
+    /// instance can refer to, so their entity names are kept.
+    name: Option<NameFixup>,
+}
+
+impl Fixups {
+    fn from_instance(ent: &Entity) -> Self {
+        let mut fixups = Self::default();
+
+        let name = ent.targetname().unwrap_or_default().trim();
+        let style = ent.get("fixup_style").and_then(|s| s.trim().parse().ok());
+        fixups.name = match style {
+            _ if name.is_empty() => None,
+            Some(1) => Some(NameFixup::Postfix(name.to_string())),
+            Some(2) => None,
+            _ => Some(NameFixup::Prefix(name.to_string())),
+        };
+
+        for (key, value) in ent.key_values.iter() {
+            if !key.to_ascii_lowercase().starts_with("replace") {
+                continue;
+            }
+            let Some((name, replacement)) = value.trim().split_once(char::is_whitespace) else {
+                continue;
+            };
+            let replacement = replacement.trim().to_string();
+
+            if let Some(material) = name.strip_prefix('#') {
+                fixups
+                    .materials
+                    .push((material.to_lowercase(), replacement.to_lowercase()));
+            } else {
+                fixups.variables.push((name.to_string(), replacement));
+            }
+        }
+
+        fixups
+    }
+
+    /// Adds default values declared by the instance's `func_instance_parms` entity
+    /// for variables that weren't set on the `func_instance` itself.
+    fn add_defaults(&mut self, instance: &VmfFile) {
+        let parms = instance.entities.iter().filter(|e| {
+            e.classname()
+                .is_some_and(|c| c.eq_ignore_ascii_case("func_instance_parms"))
+        });
+
+        for ent in parms {
+            for (key, value) in ent.key_values.iter() {
+                if !key.to_ascii_lowercase().starts_with("parm") {
+                    continue;
+                }
+                // Format: "$variable type [default value]"
+                let mut parts = value.trim().splitn(3, char::is_whitespace);
+                let (Some(name), Some(_), Some(default)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    continue;
+                };
+                if !self
+                    .variables
+                    .iter()
+                    .any(|(var, _)| var.eq_ignore_ascii_case(name))
+                {
+                    self.variables
+                        .push((name.to_string(), default.trim().to_string()));
+                }
+            }
+        }
+    }
+
+    /// Returns a normalized representation used to detect duplicate instance placements.
+    /// `with_name` tells placements with different name fixups apart.
+    fn key(&self, with_name: bool) -> Vec<(String, String)> {
+        let mut key: Vec<(String, String)> = self
+            .variables
+            .iter()
+            .chain(self.materials.iter())
+            .cloned()
+            .collect();
+        match &self.name {
+            Some(NameFixup::Prefix(fixup)) if with_name => {
+                key.push(("fixup_style 0".into(), fixup.clone()))
+            }
+            Some(NameFixup::Postfix(fixup)) if with_name => {
+                key.push(("fixup_style 1".into(), fixup.clone()))
+            }
+            _ => {}
+        }
+        key.sort();
+        key
+    }
+
+    /// Applies the replacements and the name fixup to every entity and brush face of the
+    /// instance.
+    fn apply(&mut self, instance: &mut VmfFile) {
+        // Longer names first, so `$model` doesn't clobber `$model2`.
+        self.variables
+            .sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+
+        for ent in instance.entities.iter_mut() {
+            if !self.variables.is_empty() {
+                for value in ent.key_values.values_mut() {
+                    self.substitute(value);
+                }
+                for (_, value) in ent.connections.iter_mut().flatten() {
+                    self.substitute(value);
+                }
+            }
+            if let Some(fixup) = &self.name {
+                fixup_names(ent, fixup);
+            }
+            if let Some(solids) = &mut ent.solids {
+                self.replace_materials(solids);
+            }
+        }
+        self.replace_materials(&mut instance.world.solids);
+    }
+
+    fn substitute(&self, value: &mut String) {
+        if !value.contains('$') {
+            return;
+        }
+        for (name, replacement) in &self.variables {
+            if let Some(replaced) = replace_ignore_ascii_case(value, name, replacement) {
+                *value = replaced;
+            }
+        }
+    }
+
+    fn replace_materials(&self, solids: &mut [Solid]) {
+        if self.materials.is_empty() {
+            return;
+        }
+        for side in solids.iter_mut().flat_map(|solid| solid.sides.iter_mut()) {
+            let material = side.material.to_lowercase();
+            if let Some((_, replacement)) = self.materials.iter().find(|(m, _)| *m == material) {
+                side.material = replacement.clone();
+            }
+        }
+    }
+}
+
+/// Fixes up the names an entity has and targets, in its key values and connections.
+fn fixup_names(ent: &mut Entity, fixup: &NameFixup) {
+    for (key, value) in ent.key_values.iter_mut() {
+        if NAME_KEYS.iter().any(|name| key.eq_ignore_ascii_case(name))
+            && let Some(fixed) = fixup.apply(value)
+        {
+            *value = fixed;
+        }
+    }
+    for (_, value) in ent.connections.iter_mut().flatten() {
+        let separator = if value.contains('\x1B') { '\x1B' } else { ',' };
+        if let Some((target, rest)) = value.split_once(separator)
+            && let Some(fixed) = fixup.apply(target)
+        {
+            *value = format!("{}{}{}", fixed, separator, rest);
+        }
+    }
+}
+
+/// Replaces every occurrence of `needle` in `haystack`, ignoring ASCII case.
+/// Returns `None` if there was nothing to replace.
+fn replace_ignore_ascii_case(haystack: &str, needle: &str, replacement: &str) -> Option<String> {
+    if needle.is_empty() {
+        return None;
+    }
+    let lower = haystack.to_ascii_lowercase();
+    let needle = needle.to_ascii_lowercase();
+    if !lower.contains(&needle) {
+        return None;
+    }
+
+    let mut result = String::with_capacity(haystack.len() + replacement.len());
+    let mut last = 0;
+    for (pos, _) in lower.match_indices(&needle) {
+        result.push_str(&haystack[last..pos]);
+        result.push_str(replacement);
+        last = pos + needle.len();
+    }
+    result.push_str(&haystack[last..]);
+    Some(result)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes VMF files into a fresh temporary directory.
+    fn write_vmfs(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("instance-test-{}-{}", std::process::id(), test));
+        for (name, text) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    /// A VMF with an empty world and the given entities, as `(key, value)` lists.
+    fn vmf_text(entities: &[&[(&str, &str)]]) -> String {
+        let mut text = String::from("world\n{\n\"id\" \"1\"\n\"classname\" \"worldspawn\"\n}\n");
+        for (id, key_values) in entities.iter().enumerate() {
+            text.push_str(&format!("entity\n{{\n\"id\" \"{}\"\n", id + 2));
+            for (key, value) in key_values.iter() {
+                text.push_str(&format!("\"{}\" \"{}\"\n", key, value));
+            }
+            text.push_str("}\n");
+        }
+        text
+    }
+
+    fn instance(key_values: &[(&str, &str)]) -> Entity {
+        let mut ent = Entity::new("func_instance", 1);
+        for (key, value) in key_values {
+            ent.set(key.to_string(), value.to_string());
+        }
+        ent
+    }
+
+    /// An instance with a named prop, a relay targeting it and a global relay.
+    fn instance_vmf() -> VmfFile {
+        let mut vmf = VmfFile::default();
+        let mut prop = Entity::new("prop_dynamic", 2);
+        prop.set("targetname".into(), "door".into());
+        prop.set("model".into(), "models/$Model.mdl".into());
+        prop.set("skin".into(), "$skin".into());
+        let mut relay = Entity::new("logic_relay", 3);
+        relay.set("targetname".into(), "relay".into());
+        relay.add_connection("OnTrigger", "door", "Skin", "$skin", 0.0, -1);
+        relay.add_connection("OnTrigger", "@global", "Trigger", "", 0.0, -1);
+        relay.add_connection("OnTrigger", "!activator", "Kill", "", 0.0, -1);
+        let mut global = Entity::new("logic_relay", 4);
+        global.set("targetname".into(), "@global".into());
+        let mut parms = Entity::new("func_instance_parms", 5);
+        parms.set("parm1".into(), "$skin integer 3".into());
+        parms.set("parm2".into(), "$model string props/crate".into());
+        vmf.entities.0 = vec![prop, relay, global, parms];
+        vmf
+    }
+
+    fn apply(key_values: &[(&str, &str)]) -> VmfFile {
+        let mut vmf = instance_vmf();
+        let mut fixups = Fixups::from_instance(&instance(key_values));
+        fixups.add_defaults(&vmf);
+        fixups.apply(&mut vmf);
+        vmf
+    }
+
+    fn value<'a>(vmf: &'a VmfFile, index: usize, key: &str) -> &'a str {
+        vmf.entities.0[index].get(key).unwrap()
+    }
+
+    fn targets(vmf: &VmfFile) -> Vec<&str> {
+        vmf.entities.0[1]
+            .connections
+            .iter()
+            .flatten()
+            .filter_map(|(_, value)| value.split('\x1B').next())
+            .collect()
+    }
+
+    #[test]
+    fn replace_ignore_ascii_case_replaces_every_occurrence() {
+        assert_eq!(
+            replace_ignore_ascii_case("models/$MODEL_$model.mdl", "$Model", "crate"),
+            Some("models/crate_crate.mdl".to_string())
+        );
+        assert_eq!(
+            replace_ignore_ascii_case("Bärlauch $x", "$X", "1"),
+            Some("Bärlauch 1".to_string())
+        );
+        assert_eq!(replace_ignore_ascii_case("$skin", "$model", "crate"), None);
+        assert_eq!(replace_ignore_ascii_case("$skin", "", "crate"), None);
+    }
+
+    #[test]
+    fn fixups_replace_variables_and_materials() {
+        let mut vmf = instance_vmf();
+        let mut fixups = Fixups::from_instance(&instance(&[
+            ("replace01", "$skin 2"),
+            ("replace02", "#DEV/Wall custom/wall"),
+        ]));
+        fixups.add_defaults(&vmf);
+        assert_eq!(
+            fixups.key(false),
+            [
+                ("$model".to_string(), "props/crate".to_string()),
+                ("$skin".to_string(), "2".to_string()),
+                ("dev/wall".to_string(), "custom/wall".to_string()),
+            ]
+        );
+        vmf.world.solids = vec![Solid::default()];
+        vmf.world.solids[0].sides = vec![Default::default()];
+        vmf.world.solids[0].sides[0].material = "dev/WALL".into();
+        fixups.apply(&mut vmf);
+
+        assert_eq!(value(&vmf, 0, "model"), "models/props/crate.mdl");
+        assert_eq!(value(&vmf, 0, "skin"), "2");
+        assert!(
+            vmf.entities.0[1].connections.as_ref().unwrap()[0]
+                .1
+                .contains("Skin\x1B2")
+        );
+        assert_eq!(vmf.world.solids[0].sides[0].material, "custom/wall");
+    }
+
+    #[test]
+    fn fixups_prefix_names() {
+        let vmf = apply(&[("targetname", "room1")]);
+        assert_eq!(value(&vmf, 0, "targetname"), "room1-door");
+        assert_eq!(value(&vmf, 1, "targetname"), "room1-relay");
+        assert_eq!(value(&vmf, 2, "targetname"), "@global");
+        assert_eq!(targets(&vmf), ["room1-door", "@global", "!activator"]);
+    }
+
+    #[test]
+    fn fixups_postfix_names() {
+        let vmf = apply(&[("targetname", "room1"), ("fixup_style", "1")]);
+        assert_eq!(value(&vmf, 0, "targetname"), "door-room1");
+        assert_eq!(targets(&vmf), ["door-room1", "@global", "!activator"]);
+    }
+
+    #[test]
+    fn fixups_keep_names() {
+        for key_values in [
+            &[("targetname", "room1"), ("fixup_style", "2")][..],
+            &[("fixup_style", "0")][..],
+        ] {
+            let vmf = apply(key_values);
+            assert_eq!(value(&vmf, 0, "targetname"), "door");
+            assert_eq!(targets(&vmf), ["door", "@global", "!activator"]);
+        }
+    }
+
+    #[test]
+    fn process_follows_instances_and_stops_at_cycles() {
+        let dir = write_vmfs(
+            "cycles",
+            &[
+                (
+                    "mapsrc/map.vmf",
+                    &vmf_text(&[
+                        &[("classname", "func_instance"), ("file", "a")],
+                        &[("classname", "func_instance"), ("file", "instances/B.vmf")],
+                    ]),
+                ),
+                (
+                    "mapsrc/a.vmf",
+                    &vmf_text(&[
+                        &[("classname", "func_instance"), ("file", "instances/b.vmf")],
+                        &[("classname", "prop_static"), ("model", "models/a.mdl")],
+                    ]),
+                ),
+                // Found in the `sdk_content/maps` directory above the map.
+                (
+                    "sdk_content/maps/instances/b.vmf",
+                    &vmf_text(&[
+                        &[("classname", "func_instance"), ("file", "../mapsrc/a.vmf")],
+                        &[("classname", "func_instance"), ("file", "instances/b.vmf")],
+                        &[("classname", "prop_static"), ("model", "models/b.mdl")],
+                    ]),
+                ),
+            ],
+        );
+        let map_path = dir.join("mapsrc/map.vmf");
+        let options = ParseOptions::default();
+        let map = VmfFile::open(&map_path).unwrap();
+        let mut uassets = UniqueAssets::default();
+        let mut skins = common::SkinInputs::new(&options);
+        InstanceResolver::new(&map_path, &options).process(
+            &map,
+            &map_path,
+            &mut uassets,
+            &mut skins,
+        );
+
+        let mut models: Vec<_> = uassets.models_name.into_iter().collect();
+        models.sort();
+        assert_eq!(
+            models,
+            [PathBuf::from("models/a.mdl"), PathBuf::from("models/b.mdl")]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn process_records_the_names_of_every_copy_for_skins() {
+        let dir = write_vmfs(
+            "skins",
+            &[(
+                "door.vmf",
+                &vmf_text(&[&[
+                    ("classname", "prop_dynamic"),
+                    ("targetname", "door"),
+                    ("model", "models/door.mdl"),
+                ]]),
+            )],
+        );
+        let map_path = dir.join("map.vmf");
+        let mut map = VmfFile::default();
+        let mut relay = Entity::new("logic_relay", 1);
+        relay.add_connection("OnTrigger", "second-door", "Skin", "2", 0.0, -1);
+        let first = instance(&[("targetname", "first"), ("file", "door.vmf")]);
+        let second = instance(&[("targetname", "second"), ("file", "door.vmf")]);
+        map.entities.0 = vec![relay.clone(), first, second];
+        let options = ParseOptions {
+            minimal_skins: true,
+            ..Default::default()
+        };
+        let mut uassets = UniqueAssets::default();
+        let mut skins = common::SkinInputs::new(&options);
+        skins.add_connection(
+            &common::parse_connection(&relay.connections.unwrap()[0].1).unwrap(),
+            None,
+        );
+        InstanceResolver::new(&map_path, &options).process(
+            &map,
+            &map_path,
+            &mut uassets,
+            &mut skins,
+        );
+        skins.resolve(&mut uassets);
+
+        assert!(uassets.all_skins_models.is_empty());
+        assert_eq!(
+            uassets.model_skins[Path::new("models/door.mdl")],
+            HashSet::from([0, 2])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,402 @@
+//! Minimal raw access to BSP lumps, used for data `vbsp` doesn't expose and as a fallback
+//! for maps `vbsp` can't read.
+//!
+//! Both lump directory layouts are supported: the standard one and the reordered one of
+//! Left 4 Dead 2. LZMA-compressed lumps are decompressed on access.
+
+use log::{trace, warn};
+use lzma_rs::decompress::{Options, UnpackedSize};
+use std::borrow::Cow;
+use std::io::Cursor;
+
+/// Number of lump entries in a Source BSP header.
+pub const HEADER_LUMPS: usize = 64;
+/// Index of the entity lump.
+pub const LUMP_ENTITIES: usize = 0;
+/// Index of the game lump directory.
+pub const LUMP_GAME_LUMP: usize = 35;
+/// Index of the pakfile lump, an uncompressed zip archive of embedded content.
+pub const LUMP_PAKFILE: usize = 40;
+/// Index of the texture name data (null-terminated strings).
+pub const LUMP_TEXDATA_STRING_DATA: usize = 43;
+/// Index of the texture name table (offsets into the string data).
+pub const LUMP_TEXDATA_STRING_TABLE: usize = 44;
+
+/// Size of `ident` + `version` + lump directory.
+const HEADER_SIZE: usize = 8 + HEADER_LUMPS * 16;
+/// Size of the header of an LZMA-compressed lump: id, sizes and LZMA properties.
+const LZMA_HEADER_SIZE: usize = 17;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LumpEntry {
+    pub offset: u32,
+    pub length: u32,
+    pub version: u32,
+    /// Uncompressed size of LZMA-compressed lumps, 0 otherwise.
+    pub ident: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BspHeader {
+    pub version: i32,
+    /// Lump entries are stored as `version, offset, length, ident` (Left 4 Dead 2).
+    pub l4d2_layout: bool,
+    pub lumps: Vec<LumpEntry>,
+}
+
+impl BspHeader {
+    /// Reads the BSP header. Returns `None` for files that aren't VBSP.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || &data[..4] != b"VBSP" {
+            return None;
+        }
+
+        let version = read_i32(data, 4)?;
+        let standard = read_lumps(data, false)?;
+        // L4D2 and CS:GO both use version 21, so the layout is picked by which one makes sense.
+        if version == 21 {
+            let l4d2 = read_lumps(data, true)?;
+            if lumps_score(&l4d2, data.len()) > lumps_score(&standard, data.len()) {
+                trace!("Using the Left 4 Dead 2 lump layout.");
+                return Some(Self {
+                    version,
+                    l4d2_layout: true,
+                    lumps: l4d2,
+                });
+            }
+        }
+
+        Some(Self {
+            version,
+            l4d2_layout: false,
+            lumps: standard,
+        })
+    }
+
+    /// Position of the offset and length fields of the lump at `index` in the header.
+    pub fn entry_fields(&self, index: usize) -> (usize, usize) {
+        let base = 8 + index * 16;
+        if self.l4d2_layout {
+            (base + 4, base + 8)
+        } else {
+            (base, base + 4)
+        }
+    }
+
+    /// Returns the raw data of the lump at `index`, if it lies within the file.
+    pub fn lump_data<'a>(&self, data: &'a [u8], index: usize) -> Option<&'a [u8]> {
+        let entry = self.lumps.get(index)?;
+        let start = entry.offset as usize;
+        data.get(start..start.checked_add(entry.length as usize)?)
+    }
+
+    /// Returns the data of the lump at `index`, decompressing LZMA lumps.
+    pub fn lump_bytes<'a>(&self, data: &'a [u8], index: usize) -> Option<Cow<'a, [u8]>> {
+        let lump = self.lump_data(data, index)?;
+        if !is_lzma(lump) {
+            return Some(Cow::Borrowed(lump));
+        }
+        match decompress(lump) {
+            Some(decompressed) => Some(Cow::Owned(decompressed)),
+            None => {
+                warn!("Failed to decompress lump {}.", index);
+                None
+            }
+        }
+    }
+}
+
+/// Reads the lump directory in the standard or Left 4 Dead 2 layout.
+fn read_lumps(data: &[u8], l4d2_layout: bool) -> Option<Vec<LumpEntry>> {
+    (0..HEADER_LUMPS)
+        .map(|i| {
+            let base = 8 + i * 16;
+            let fields = [
+                read_u32(data, base)?,
+                read_u32(data, base + 4)?,
+                read_u32(data, base + 8)?,
+                read_u32(data, base + 12)?,
+            ];
+            let [offset, length, version, ident] = if l4d2_layout {
+                [fields[1], fields[2], fields[0], fields[3]]
+            } else {
+                fields
+            };
+            Some(LumpEntry {
+                offset,
+                length,
+                version,
+                ident,
+            })
+        })
+        .collect()
+}
+
+/// Counts the non-empty lumps that lie after the header and within the file.
+fn lumps_score(lumps: &[LumpEntry], file_len: usize) -> usize {
+    lumps
+        .iter()
+        .filter(|lump| lump.length > 0)
+        .filter(|lump| {
+            let start = lump.offset as usize;
+            start >= HEADER_SIZE && start + lump.length as usize <= file_len
+        })
+        .count()
+}
+
+/// Whether the data starts with the header of Source's LZMA compression.
+pub fn is_lzma(data: &[u8]) -> bool {
+    data.len() >= LZMA_HEADER_SIZE && &data[..4] == b"LZMA"
+}
+
+/// Decompresses data with Source's LZMA header: `"LZMA"`, uncompressed size,
+/// compressed size and the 5 bytes of LZMA properties.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if !is_lzma(data) {
+        return None;
+    }
+    let actual_size = read_u32(data, 4)?;
+    let mut output = Vec::with_capacity((actual_size as usize).min(64 * 1024 * 1024));
+    let options = Options {
+        unpacked_size: UnpackedSize::UseProvided(Some(actual_size as u64)),
+        allow_incomplete: false,
+        memlimit: None,
+    };
+    lzma_rs::lzma_decompress_with_options(&mut Cursor::new(&data[12..]), &mut output, &options)
+        .ok()?;
+    Some(output)
+}
+
+/// An entry of the game lump directory (`sprp`, `dprp`, ...).
+#[derive(Debug, Clone, Copy)]
+pub struct GameLump {
+    pub id: [u8; 4],
+    pub flags: u16,
+    pub version: u16,
+    pub offset: i32,
+    pub length: i32,
+}
+
+/// Reads the game lump directory.
+pub fn read_game_lumps(data: &[u8], header: &BspHeader) -> Vec<GameLump> {
+    let Some(lump) = header.lump_bytes(data, LUMP_GAME_LUMP) else {
+        return Vec::new();
+    };
+    let lump = lump.as_ref();
+    let count = read_i32(lump, 0).unwrap_or(0).max(0) as usize;
+
+    (0..count)
+        .map_while(|i| {
+            let base = 4 + i * 16;
+            let id = read_u32(lump, base)?.to_be_bytes();
+            Some(GameLump {
+                id,
+                flags: read_u16(lump, base + 4)?,
+                version: read_u16(lump, base + 6)?,
+                offset: read_i32(lump, base + 8)?,
+                length: read_i32(lump, base + 12)?,
+            })
+        })
+        .collect()
+}
+
+/// Returns the directory entry and the data of the game lump with the given id
+/// (e.g. `b"dprp"`), decompressing it if needed.
+pub fn find_game_lump<'a>(
+    data: &'a [u8],
+    header: &BspHeader,
+    id: &[u8; 4],
+) -> Option<(GameLump, Cow<'a, [u8]>)> {
+    let lump = read_game_lumps(data, header)
+        .into_iter()
+        .find(|lump| &lump.id == id)?;
+
+    let start = usize::try_from(lump.offset).ok()?;
+    let length = usize::try_from(lump.length).ok()?;
+    let rest = data.get(start..)?;
+    // The length of compressed game lumps is the uncompressed one, the LZMA header knows
+    // how much to read.
+    if lump.flags & 1 != 0 || is_lzma(rest) {
+        trace!("Game lump {} is compressed.", String::from_utf8_lossy(id));
+        return Some((lump, Cow::Owned(decompress(rest)?)));
+    }
+    Some((lump, Cow::Borrowed(rest.get(..length)?)))
+}
+
+/// Reads a null-terminated string from a fixed-size buffer.
+pub fn read_fixed_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+pub fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+pub fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+pub fn read_i32(data: &[u8], pos: usize) -> Option<i32> {
+    Some(i32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a VBSP whose lumps (index, version, data) are stored in order after the header,
+    /// with the lump directory in the standard or Left 4 Dead 2 layout.
+    fn build_bsp(version: i32, l4d2_layout: bool, lumps: &[(usize, u32, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[..4].copy_from_slice(b"VBSP");
+        data[4..8].copy_from_slice(&version.to_le_bytes());
+        for (index, lump_version, lump) in lumps {
+            let offset = data.len() as u32;
+            let fields = if l4d2_layout {
+                [*lump_version, offset, lump.len() as u32, 0]
+            } else {
+                [offset, lump.len() as u32, *lump_version, 0]
+            };
+            for (i, field) in fields.into_iter().enumerate() {
+                let pos = 8 + index * 16 + i * 4;
+                data[pos..pos + 4].copy_from_slice(&field.to_le_bytes());
+            }
+            data.extend_from_slice(lump);
+        }
+        data
+    }
+
+    /// Compresses data with Source's LZMA header.
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut lzma = Vec::new();
+        lzma_rs::lzma_compress(&mut Cursor::new(data), &mut lzma).unwrap();
+        // `lzma_rs` writes the properties and the 8-byte uncompressed size before the stream.
+        let (properties, stream) = (&lzma[..5], &lzma[13..]);
+        let mut out = b"LZMA".to_vec();
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+        out.extend_from_slice(properties);
+        out.extend_from_slice(stream);
+        out
+    }
+
+    const ENTITIES: &[u8] = b"{\n\"classname\" \"worldspawn\"\n}\n\0";
+    const TEXTURES: &[u8] = b"DEV/DEV_MEASUREGENERIC01\0";
+
+    #[test]
+    fn read_standard_layout() {
+        let data = build_bsp(20, false, &[(LUMP_ENTITIES, 0, ENTITIES)]);
+        let header = BspHeader::read(&data).unwrap();
+        assert_eq!(header.version, 20);
+        assert!(!header.l4d2_layout);
+        assert_eq!(header.lump_data(&data, LUMP_ENTITIES), Some(ENTITIES));
+        assert_eq!(header.entry_fields(LUMP_ENTITIES), (8, 12));
+        assert_eq!(header.lump_data(&data, LUMP_PAKFILE), Some(&[][..]));
+        assert!(header.lump_data(&data, HEADER_LUMPS).is_none());
+    }
+
+    #[test]
+    fn read_picks_layout_of_version_21() {
+        let lumps: &[(usize, u32, &[u8])] = &[
+            (LUMP_ENTITIES, 1, ENTITIES),
+            (LUMP_TEXDATA_STRING_DATA, 1, TEXTURES),
+        ];
+
+        let data = build_bsp(21, true, lumps);
+        let header = BspHeader::read(&data).unwrap();
+        assert!(header.l4d2_layout);
+        assert_eq!(header.lumps[LUMP_ENTITIES].version, 1);
+        assert_eq!(header.entry_fields(LUMP_ENTITIES), (12, 16));
+        assert_eq!(header.lump_data(&data, LUMP_ENTITIES), Some(ENTITIES));
+        assert_eq!(
+            header.lump_data(&data, LUMP_TEXDATA_STRING_DATA),
+            Some(TEXTURES)
+        );
+
+        // CS:GO uses version 21 with the standard layout.
+        let data = build_bsp(21, false, lumps);
+        let header = BspHeader::read(&data).unwrap();
+        assert!(!header.l4d2_layout);
+        assert_eq!(header.lump_data(&data, LUMP_ENTITIES), Some(ENTITIES));
+
+        // Other versions never use the Left 4 Dead 2 layout.
+        let data = build_bsp(20, true, lumps);
+        assert!(!BspHeader::read(&data).unwrap().l4d2_layout);
+    }
+
+    #[test]
+    fn lumps_score_counts_lumps_within_the_file() {
+        let lump = |offset, length| LumpEntry {
+            offset,
+            length,
+            ..Default::default()
+        };
+        let header_size = HEADER_SIZE as u32;
+        let lumps = [
+            lump(header_size, 10),
+            lump(header_size + 10, 0),
+            lump(4, 10),
+            lump(header_size + 5, 100),
+            lump(header_size + 10, 10),
+        ];
+        assert_eq!(lumps_score(&lumps, HEADER_SIZE + 20), 2);
+    }
+
+    #[test]
+    fn read_rejects_other_files() {
+        assert!(BspHeader::read(b"VBSP").is_none());
+        let mut data = build_bsp(20, false, &[]);
+        data[..4].copy_from_slice(b"IBSP");
+        assert!(BspHeader::read(&data).is_none());
+    }
+
+    #[test]
+    fn lump_bytes_decompresses_lzma() {
+        let compressed = compress(ENTITIES);
+        assert!(is_lzma(&compressed));
+        let data = build_bsp(21, false, &[(LUMP_ENTITIES, 0, &compressed)]);
+        let header = BspHeader::read(&data).unwrap();
+        assert_eq!(
+            header.lump_bytes(&data, LUMP_ENTITIES).as_deref(),
+            Some(ENTITIES)
+        );
+
+        let mut broken = compressed.clone();
+        broken.truncate(LZMA_HEADER_SIZE + 2);
+        let data = build_bsp(21, false, &[(LUMP_ENTITIES, 0, &broken)]);
+        let header = BspHeader::read(&data).unwrap();
+        assert!(header.lump_bytes(&data, LUMP_ENTITIES).is_none());
+    }
+
+    #[test]
+    fn find_game_lump_by_id() {
+        // The directory is followed by the lumps, at offsets relative to the file.
+        let start = (HEADER_SIZE + 4 + 2 * 16) as i32;
+        let sprp = b"static props".as_slice();
+        let dprp = compress(b"detail props");
+        let mut game_lump = 2i32.to_le_bytes().to_vec();
+        for (id, flags, version, offset, length) in [
+            (b"sprp", 0u16, 10u16, start, sprp.len()),
+            (b"dprp", 1, 4, start + sprp.len() as i32, 12),
+        ] {
+            game_lump.extend_from_slice(&u32::from_be_bytes(*id).to_le_bytes());
+            game_lump.extend_from_slice(&flags.to_le_bytes());
+            game_lump.extend_from_slice(&version.to_le_bytes());
+            game_lump.extend_from_slice(&offset.to_le_bytes());
+            game_lump.extend_from_slice(&(length as i32).to_le_bytes());
+        }
+        game_lump.extend_from_slice(sprp);
+        game_lump.extend_from_slice(&dprp);
+        let data = build_bsp(20, false, &[(LUMP_GAME_LUMP, 0, &game_lump)]);
+        let header = BspHeader::read(&data).unwrap();
+
+        let (entry, lump) = find_game_lump(&data, &header, b"sprp").unwrap();
+        assert_eq!(entry.version, 10);
+        assert_eq!(lump.as_ref(), sprp);
+        let (entry, lump) = find_game_lump(&data, &header, b"dprp").unwrap();
+        assert_eq!(entry.version, 4);
+        assert_eq!(lump.as_ref(), b"detail props");
+        assert!(find_game_lump(&data, &header, b"tlpd").is_none());
+    }
+}
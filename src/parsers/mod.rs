@@ -1,4 +1,26 @@
 //! This module contains parsers for different map file formats.
 
 pub mod bsp;
+pub(crate) mod common; // Helpers shared by both parsers.
+pub mod fgd; // Entity definitions used to classify entity keys.
+mod instance; // Resolution of func_instance VMFs.
+pub mod lump; // Raw access to BSP lumps.
 pub mod vmf; // Module for parsing VMF files. // Module for parsing BSP files.
+
+use fgd::Fgd;
+
+/// Options that control how map files are scanned for assets.
+#[derive(Debug, Default)]
+pub struct ParseOptions {
+    /// Entity definitions used to classify entity keys.
+    /// When `None`, keys are classified by name and value suffix heuristics.
+    pub fgd: Option<Fgd>,
+    /// Names of VMF visgroups (case-insensitive) whose entities and solids are skipped,
+    /// including nested visgroups.
+    pub exclude_visgroups: Vec<String>,
+    /// Skip VMF entities and solids hidden through visgroups (`visgroupshown 0`)
+    /// or auto-visgroups (`visgroupautoshown 0`).
+    pub exclude_hidden: bool,
+    /// Record the `skin` of placed models, so only the materials of used skins are collected.
+    pub minimal_skins: bool,
+}
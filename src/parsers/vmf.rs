@@ -1,67 +1,152 @@
 use log::trace;
+use std::collections::HashSet;
 use std::path::Path;
 
+use super::instance::InstanceResolver;
+use super::{ParseOptions, common};
 use crate::asset_processor::UniqueAssets;
+use vmf_forge::vmf::common::Editor;
+use vmf_forge::vmf::metadata::VisGroup;
 use vmf_forge::{VmfFile, VmfResult, vmf::world::Solid};
 
-/// Extracts unique assets from a VMF file.
-pub fn get_uniques(path: &Path, uasset: &mut UniqueAssets) -> VmfResult<()> {
+/// Extracts unique assets from a VMF file, including the `func_instance` VMFs it references.
+pub fn get_uniques(
+    path: &Path,
+    uasset: &mut UniqueAssets,
+    options: &ParseOptions,
+) -> VmfResult<()> {
     trace!("Attempting to read and parse VMF file: {}", path.display());
     let vmf = VmfFile::open(path)?;
 
-    add_unique_models(&vmf, uasset);
-    _process_solids(&vmf.world.solids, uasset);
+    let mut skins = common::SkinInputs::new(options);
+    collect_assets(&vmf, &common::map_name(path), uasset, &mut skins, options);
+    // Instances have a world of their own, with Hammer's default skybox; only the map's applies.
+    add_world_assets(&vmf, uasset);
+    InstanceResolver::new(path, options).process(&vmf, path, uasset, &mut skins);
+    // Skin inputs may target entities of any instance.
+    skins.resolve(uasset);
     Ok(())
 }
 
-/// Extracts unique model and material names from VMF entities.
-fn add_unique_models(vmf: &VmfFile, uassets: &mut UniqueAssets) {
-    for ent in vmf.entities.iter() {
-        if let Some(modelname) = ent.get("model") {
-            if modelname.ends_with(".vmt") || modelname.ends_with(".spr") {
-                uassets
-                    .materials_name
-                    .insert(modelname.replace(".vmt", "").into()); // Add material name
-                continue;
-            }
-            uassets.models_name.insert(modelname.into());
+/// Extracts unique assets from the entities and solids of an already parsed VMF, without
+/// following instances or reading worldspawn keys. `map` is the name of the map the VMF
+/// belongs to. Entities and skin inputs are recorded in `skins`.
+pub(super) fn collect_assets(
+    vmf: &VmfFile,
+    map: &str,
+    uassets: &mut UniqueAssets,
+    skins: &mut common::SkinInputs,
+    options: &ParseOptions,
+) {
+    let filter = VisgroupFilter::new(vmf, options);
+    add_unique_models(vmf, map, uassets, skins, options, &filter);
+    _process_solids(&vmf.world.solids, uassets, &filter);
+}
+
+/// Decides which entities and solids of a VMF are skipped because of their visgroups.
+pub(super) struct VisgroupFilter {
+    /// Ids of the excluded visgroups and all of their children.
+    excluded_ids: HashSet<i32>,
+    exclude_hidden: bool,
+}
+
+impl VisgroupFilter {
+    pub fn new(vmf: &VmfFile, options: &ParseOptions) -> Self {
+        let mut excluded_ids = HashSet::new();
+        if !options.exclude_visgroups.is_empty() {
+            collect_excluded_ids(&vmf.visgroups.groups, options, false, &mut excluded_ids);
         }
 
-        // MATERIALS (if it is a brush entity)
-        if let Some(material_name) = ent.get("material") {
-            uassets.materials_name.insert(material_name.into());
-            trace!("Found material from 'material' key: {}", material_name);
+        Self {
+            excluded_ids,
+            exclude_hidden: options.exclude_hidden,
         }
-        if let Some(material_name) = ent.get("texture") {
-            uassets.materials_name.insert(material_name.into());
-            trace!("Found material from 'texture' key: {}", material_name);
+    }
+
+    /// Whether an object with the given editor data should be skipped.
+    pub fn is_excluded(&self, editor: &Editor) -> bool {
+        (self.exclude_hidden && (!editor.visgroup_shown || !editor.visgroup_auto_shown))
+            || editor
+                .visgroup_id
+                .is_some_and(|id| self.excluded_ids.contains(&id))
+    }
+}
+
+fn collect_excluded_ids(
+    groups: &[VisGroup],
+    options: &ParseOptions,
+    parent_excluded: bool,
+    ids: &mut HashSet<i32>,
+) {
+    for group in groups {
+        let excluded = parent_excluded
+            || options
+                .exclude_visgroups
+                .iter()
+                .any(|name| name.trim().eq_ignore_ascii_case(group.name.trim()));
+        if excluded {
+            ids.insert(group.id);
         }
-        if let Some(solids) = &ent.solids {
-            _process_solids(solids, uassets);
+        if let Some(children) = &group.children {
+            collect_excluded_ids(children, options, excluded, ids);
         }
+    }
+}
 
-        // SOUNDS
-        let suffixes = [".wav", ".mp3", ".ogg", ".flac"];
-        for value in ent.key_values.values() {
-            let value_bytes = value.as_bytes();
-            let value_len = value_bytes.len();
+/// Extracts assets referenced by worldspawn key values (skybox, detail sprites).
+fn add_world_assets(vmf: &VmfFile, uassets: &mut UniqueAssets) {
+    let key_values = &vmf.world.key_values;
+    common::add_worldspawn(|key| key_values.get(key).map(String::as_str), uassets);
+}
 
-            if suffixes.iter().any(|suffix| {
-                let suffix_bytes = suffix.as_bytes();
-                let suffix_len = suffix_bytes.len();
-                value_len >= suffix_len
-                    && value_bytes[value_len - suffix_len..].eq_ignore_ascii_case(suffix_bytes)
-            }) {
-                trace!("Found sound: {}", value);
-                uassets.sounds_name.insert(value.into());
+/// Extracts unique model, material and sound names from VMF entities.
+fn add_unique_models(
+    vmf: &VmfFile,
+    map: &str,
+    uassets: &mut UniqueAssets,
+    skins: &mut common::SkinInputs,
+    options: &ParseOptions,
+    filter: &VisgroupFilter,
+) {
+    for ent in vmf.entities.iter() {
+        if filter.is_excluded(&ent.editor) {
+            trace!(
+                "Skipping entity {} excluded by visgroup.",
+                ent.classname().unwrap_or_default()
+            );
+            continue;
+        }
+        let classname = ent.classname().unwrap_or_default();
+        for (key, value) in ent.key_values.iter() {
+            common::add_entity_keyvalue(classname, key, value, options, uassets);
+            common::add_map_keyvalue(map, classname, key, value, options, uassets);
+        }
+        let model = ent.key_values.get("model").map(String::as_str);
+        if let Some(model) = model {
+            let skin = ent.key_values.get("skin").map(String::as_str);
+            common::add_model_skin(model, skin, options, uassets);
+        }
+        skins.add_entity(ent.targetname(), classname, model);
+        for (_, value) in ent.connections.iter().flatten() {
+            if let Some(connection) = common::parse_connection(value) {
+                common::add_connection(&connection, options, uassets);
+                skins.add_connection(&connection, model);
             }
         }
+
+        // MATERIALS (if it is a brush entity)
+        if let Some(solids) = &ent.solids {
+            _process_solids(solids, uassets, filter);
+        }
     }
 }
 
 /// Processes a vector of VMF solids to extract unique material names.
-fn _process_solids(solids: &[Solid], uassets: &mut UniqueAssets) {
-    for solid in solids {
+fn _process_solids(solids: &[Solid], uassets: &mut UniqueAssets, filter: &VisgroupFilter) {
+    for solid in solids
+        .iter()
+        .filter(|solid| !filter.is_excluded(&solid.editor))
+    {
         for side in &solid.sides {
             let material_path = side.material.to_lowercase();
             if !material_path.contains("tools") {
@@ -0,0 +1,275 @@
+//! The asset pipeline shared by the CLI and the GUI: finds the files of the assets
+//! collected from the maps, then copies, writes and packs them into the output directory.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::asset_processor::companions::{self, CompanionFile};
+use crate::asset_processor::particles::{self, MapParticles};
+use crate::asset_processor::propdata::{self, PropData};
+use crate::asset_processor::soundscapes::{self, MapSoundscapes};
+use crate::asset_processor::soundscripts::{self, SoundEntry};
+use crate::asset_processor::{detail, embedded, materials, models, sounds, surfaceprops, vscripts};
+use crate::keyvalues::KeyValue;
+use crate::vtf::MipStrip;
+use crate::{AssetDirectories, UniqueAssets, utils};
+
+/// Settings of the output written by [`AssetFiles::write`].
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Largest mip levels removed from copied textures.
+    pub mip_strip: MipStrip,
+    /// Write a `maps/<map>_particles.txt` manifest for every map.
+    pub particle_manifests: bool,
+    /// Extract the custom content packed into BSPs into the output.
+    pub extract_embedded: bool,
+    /// Write copies of the BSPs with the collected assets packed into them.
+    pub pack_bsp: bool,
+    /// Write a bspzip addlist for each BSP.
+    pub bspzip_addlist: bool,
+}
+
+/// The files found for the assets collected from the maps.
+#[derive(Debug)]
+pub struct AssetFiles {
+    pub detail: Vec<PathBuf>,
+    pub companions: Vec<CompanionFile>,
+    pub vscripts: Vec<PathBuf>,
+    pub map_particles: Vec<MapParticles>,
+    pub particles: Vec<PathBuf>,
+    pub models: Vec<PathBuf>,
+    pub propdata: Vec<PathBuf>,
+    pub materials: Vec<PathBuf>,
+    pub surfaceprops: Vec<KeyValue>,
+    pub map_soundscapes: Vec<MapSoundscapes>,
+    pub sound_entries: Vec<SoundEntry>,
+    pub soundscripts: Vec<PathBuf>,
+    pub sounds: Vec<PathBuf>,
+}
+
+/// Finds the files of the collected assets. Each pass may add assets for the following
+/// ones, so they run in dependency order. `found` is called with the number of results
+/// of every pass, for progress reporting.
+pub fn find_files(
+    u_assets: &mut UniqueAssets,
+    dirs: &AssetDirectories,
+    mut found: impl FnMut(usize),
+) -> AssetFiles {
+    // detail.vbsp files first, they add detail models
+    let detail = detail::process(u_assets, &dirs.roots);
+    found(detail.len());
+    // Map companion files, overviews add materials
+    let companions = companions::process(u_assets, &dirs.roots);
+    found(companions.len());
+    // Vscripts add models and soundscript entries
+    let vscripts = vscripts::process(u_assets, &dirs.roots);
+    found(vscripts.len());
+    // Particles add models and materials
+    let map_particles = particles::process(u_assets, &dirs.roots);
+    found(map_particles.len());
+    // Models add gib models and prop data
+    let propdata_entries = PropData::load(&dirs.roots);
+    let models = models::process(u_assets, &dirs.models, &propdata_entries);
+    found(models.len());
+    let propdata = propdata::process(u_assets, &propdata_entries, &dirs.roots);
+    found(propdata.len());
+    // Materials add surface properties
+    let materials = materials::process(u_assets, &dirs.materials);
+    found(materials.len());
+    // Surface properties add soundscript entries
+    let surfaceprops = surfaceprops::process(u_assets, &dirs.roots);
+    found(surfaceprops.len());
+    // Soundscapes add sounds and soundscript entries
+    let map_soundscapes = soundscapes::process(u_assets, &dirs.roots);
+    found(map_soundscapes.len());
+    // Soundscript entries add sounds
+    let sound_entries = soundscripts::process(u_assets, &dirs.roots);
+    found(sound_entries.len());
+    let sounds = sounds::process(u_assets, &dirs.sounds);
+    found(sounds.len());
+
+    AssetFiles {
+        detail,
+        companions,
+        vscripts,
+        particles: particles::files(&map_particles),
+        map_particles,
+        models,
+        propdata,
+        materials,
+        surfaceprops,
+        map_soundscapes,
+        soundscripts: soundscripts::files(&sound_entries),
+        sound_entries,
+        sounds,
+    }
+}
+
+impl AssetFiles {
+    /// Number of files and written entries copied to the output.
+    pub fn len(&self) -> usize {
+        self.detail.len()
+            + self.companions.len()
+            + self.models.len()
+            + self.propdata.len()
+            + self.surfaceprops.len()
+            + self.materials.len()
+            + self.sounds.len()
+            + self.soundscripts.len()
+            + self.map_soundscapes.len()
+            + self.particles.len()
+            + self.vscripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Validates the found textures (see [`materials::validate_textures`]) and returns the
+    /// problems of the textures used by each map, one line per texture.
+    pub fn texture_issues(
+        &self,
+        u_assets: &UniqueAssets,
+        max_size: u32,
+    ) -> Vec<(PathBuf, Vec<String>)> {
+        let mut map_issues: Vec<(PathBuf, Vec<String>)> = Vec::new();
+        for report in materials::validate_textures(&self.materials, max_size) {
+            let texture = utils::folder_relative_path(&report.path, "materials");
+            let problems: Vec<String> = report.problems.iter().map(ToString::to_string).collect();
+            let issue = format!("{}: {}", texture.display(), problems.join(", "));
+            for map in u_assets.source_maps(&texture) {
+                match map_issues.iter_mut().find(|(path, _)| path == map) {
+                    Some((_, issues)) => issues.push(issue.clone()),
+                    None => map_issues.push((map.to_path_buf(), vec![issue.clone()])),
+                }
+            }
+        }
+        map_issues
+    }
+
+    /// Copies the found files to the output directory, writes the generated scripts and
+    /// manifests, and extracts or packs embedded content as set in `options`.
+    pub fn write(
+        &self,
+        u_assets: &UniqueAssets,
+        dirs: &AssetDirectories,
+        output_dir: &Path,
+        options: &OutputOptions,
+    ) -> Result<(), String> {
+        let roots = &dirs.roots;
+
+        utils::copy_files(&self.models, output_dir, "models", None)
+            .map_err(failed("copy models"))?;
+        utils::copy_files(
+            &self.materials,
+            output_dir,
+            "materials",
+            Some(&options.mip_strip),
+        )
+        .map_err(failed("copy materials"))?;
+        utils::copy_files(&self.sounds, output_dir, "sound", None)
+            .map_err(failed("copy sounds"))?;
+        utils::copy_root_files(&self.detail, roots, output_dir)
+            .map_err(failed("copy detail files"))?;
+        companions::copy_files(&self.companions, output_dir)
+            .map_err(failed("copy map companion files"))?;
+        utils::copy_root_files(&self.propdata, roots, output_dir)
+            .map_err(failed("copy prop data"))?;
+        utils::copy_root_files(&self.soundscripts, roots, output_dir)
+            .and_then(|_| soundscripts::write_manifest(&self.soundscripts, roots, output_dir))
+            .map_err(failed("copy soundscripts"))?;
+        surfaceprops::write_files(&self.surfaceprops, roots, output_dir)
+            .map_err(failed("write surface properties"))?;
+        soundscapes::write_map_files(&self.map_soundscapes, output_dir)
+            .map_err(failed("write soundscapes"))?;
+        utils::copy_root_files(&self.vscripts, roots, output_dir)
+            .map_err(failed("copy vscripts"))?;
+        utils::copy_root_files(&self.particles, roots, output_dir)
+            .map_err(failed("copy particles"))?;
+        if options.particle_manifests {
+            particles::write_map_manifests(&self.map_particles, roots, output_dir)
+                .map_err(failed("write particle manifests"))?;
+        }
+        if options.extract_embedded {
+            embedded::extract(u_assets, output_dir).map_err(failed("extract embedded files"))?;
+        }
+
+        if options.pack_bsp || options.bspzip_addlist {
+            // Soundscript entries go into each map's level sounds instead of their files.
+            soundscripts::write_level_sounds(u_assets, &self.sound_entries, output_dir)
+                .map_err(failed("write level sounds"))?;
+            let entries: Vec<_> = embedded::folder_entries(&self.models, "models")
+                .into_iter()
+                .chain(embedded::folder_entries(&self.materials, "materials"))
+                .chain(embedded::folder_entries(&self.sounds, "sound"))
+                .chain(embedded::root_entries(&self.detail, roots))
+                .chain(embedded::root_entries(&self.propdata, roots))
+                .chain(embedded::root_entries(&self.particles, roots))
+                .chain(embedded::root_entries(&self.vscripts, roots))
+                .collect();
+            if options.pack_bsp {
+                embedded::pack_maps(u_assets, &entries, output_dir).map_err(failed("pack maps"))?;
+            }
+            if options.bspzip_addlist {
+                embedded::write_addlists(u_assets, &entries, output_dir)
+                    .map_err(failed("write bspzip addlists"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turns an IO error into the message of a failed output step.
+fn failed(what: &'static str) -> impl FnOnce(io::Error) -> String {
+    move |err| format!("Failed to {}: {}", what, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn texture_issues_are_grouped_by_map() {
+        let dir = std::env::temp_dir().join(format!("pipeline-test-{}-issues", std::process::id()));
+        let texture = dir.join("materials/custom/wall.vtf");
+        fs::create_dir_all(texture.parent().unwrap()).unwrap();
+        fs::write(&texture, b"").unwrap();
+
+        let mut u_assets = UniqueAssets::default();
+        for map in ["maps/a.bsp", "maps/b.vmf"] {
+            u_assets.add_map_assets(Path::new(map), |found| {
+                found.materials_name.insert("custom/wall".into());
+            });
+        }
+        u_assets.add_map_assets(Path::new("maps/c.vmf"), |found| {
+            found.materials_name.insert("custom/floor".into());
+        });
+        u_assets.add_parent("materials/custom/wall.vtf", "materials/custom/wall.vmt");
+        let files = AssetFiles {
+            detail: Vec::new(),
+            companions: Vec::new(),
+            vscripts: Vec::new(),
+            map_particles: Vec::new(),
+            particles: Vec::new(),
+            models: Vec::new(),
+            propdata: Vec::new(),
+            materials: vec![texture],
+            surfaceprops: Vec::new(),
+            map_soundscapes: Vec::new(),
+            sound_entries: Vec::new(),
+            soundscripts: Vec::new(),
+            sounds: Vec::new(),
+        };
+
+        let mut issues = files.texture_issues(&u_assets, 0);
+        issues.sort();
+        let maps: Vec<&Path> = issues.iter().map(|(map, _)| map.as_path()).collect();
+        assert_eq!(maps, [Path::new("maps/a.bsp"), Path::new("maps/b.vmf")]);
+        for (_, lines) in &issues {
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].starts_with("materials/custom/wall.vtf: "));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,8 +1,9 @@
 //! Utility functions module for sourcemods-builder.
 
+use crate::vtf::MipStrip;
 use colored::*;
 use fern::Dispatch;
-use regex::Regex;
+use log::debug;
 use std::{
     fs, io,
     path::{Component, Path, PathBuf},
@@ -39,23 +40,16 @@ pub fn setup_logger(level: log::LevelFilter) -> Result<(), Box<dyn std::error::E
     Ok(())
 }
 
-/// Finds all captured groups from a regex in a file.
-pub fn find_all_groups_in_file(file_path: &Path, re: &Regex) -> io::Result<Vec<String>> {
-    let content = fs::read_to_string(file_path)?;
-    let matches: Vec<String> = re
-        .captures_iter(&content)
-        .filter_map(|cap| cap.get(1))
-        .map(|m| m.as_str().replace("\\", "/").to_lowercase())
-        .collect();
-    Ok(matches)
+/// Returns the part of `path` starting at the `base_folder` component (e.g. `models/...`).
+pub fn folder_relative_path(path: &Path, base_folder: &str) -> PathBuf {
+    path.iter()
+        .skip_while(|part| part.to_str() != Some(base_folder))
+        .collect::<PathBuf>()
 }
 
 /// Constructs a destination path for copied files.
 fn get_path(path: &Path, output_dir: &Path, base_folder: &str) -> io::Result<PathBuf> {
-    let relative_path = path
-        .iter()
-        .skip_while(|part| part.to_str() != Some(base_folder))
-        .collect::<PathBuf>();
+    let relative_path = folder_relative_path(path, base_folder);
 
     let destination = output_dir.join(&relative_path);
 
@@ -67,9 +61,56 @@ fn get_path(path: &Path, output_dir: &Path, base_folder: &str) -> io::Result<Pat
 }
 
 /// Copies multiple files to the output directory.
-pub fn copy_files(paths: &Vec<PathBuf>, output_dir: &Path, base_folder: &str) -> io::Result<()> {
+///
+/// With `mip_strip`, textures copied to `materials/` lose their largest mip levels.
+pub fn copy_files(
+    paths: &Vec<PathBuf>,
+    output_dir: &Path,
+    base_folder: &str,
+    mip_strip: Option<&MipStrip>,
+) -> io::Result<()> {
+    let mip_strip = mip_strip.filter(|strip| base_folder == "materials" && strip.mips > 0);
     for path in paths {
         let destination = get_path(path, output_dir, base_folder)?;
+        let is_vtf = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("vtf"));
+        if let Some(strip) = mip_strip
+            && is_vtf
+            && let Some(stripped) = strip.apply(&fs::read(path)?)
+        {
+            debug!("Stripped mip levels of {}", path.display());
+            fs::write(&destination, stripped)?;
+            continue;
+        }
+        fs::copy(path, &destination)?;
+    }
+    Ok(())
+}
+
+/// Returns `path` relative to the deepest content root that contains it.
+pub fn root_relative_path<'a>(path: &'a Path, roots: &[PathBuf]) -> Option<&'a Path> {
+    roots
+        .iter()
+        .filter_map(|root| path.strip_prefix(root).ok())
+        .min_by_key(|relative| relative.components().count())
+}
+
+/// Copies files that live directly in content roots (e.g. `detail.vbsp`),
+/// keeping their path relative to the deepest root that contains them.
+pub fn copy_root_files(
+    paths: &Vec<PathBuf>,
+    roots: &[PathBuf],
+    output_dir: &Path,
+) -> io::Result<()> {
+    for path in paths {
+        let relative_path = root_relative_path(path, roots)
+            .unwrap_or(path.file_name().map(Path::new).unwrap_or(path));
+
+        let destination = output_dir.join(relative_path);
+        if let Some(parent_dir) = destination.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
         fs::copy(path, &destination)?;
     }
     Ok(())
@@ -151,7 +192,7 @@ pub fn find_asset_case_insensitive(
 
         // Check if the found path is a directory before proceeding to the next component.
         // This prevents trying to `read_dir` on a file.
-        let is_last_component = component == relative_asset_path.components().last().unwrap();
+        let is_last_component = component == relative_asset_path.components().next_back().unwrap();
         if !is_last_component && !current_path.is_dir() {
             return Ok(None); // Cannot traverse further. :<
         }
@@ -160,6 +201,69 @@ pub fn find_asset_case_insensitive(
     Ok(Some(current_path))
 }
 
+/// Finds a file by its relative path within `base_dir`, ignoring case on unix.
+pub fn find_file(base_dir: &Path, relative_path: &Path) -> Option<PathBuf> {
+    #[cfg(not(unix))]
+    let path = base_dir.join(relative_path);
+    #[cfg(unix)] // Source engine is not case-sensitive, unlike unix-like filesystems
+    let path = match find_asset_case_insensitive(base_dir, relative_path) {
+        Ok(Some(correct_path)) => correct_path,
+        Ok(None) => return None,
+        Err(e) => {
+            log::warn!(
+                "Error searching for asset {} in {}: {}",
+                relative_path.display(),
+                base_dir.display(),
+                e
+            );
+            return None;
+        }
+    };
+
+    path.is_file().then_some(path)
+}
+
+/// Finds a direct subdirectory of `base_dir` by name, ignoring case.
+pub fn find_dir(base_dir: &Path, name: &str) -> Option<PathBuf> {
+    let dir = base_dir.join(name);
+    if dir.is_dir() {
+        return Some(dir);
+    }
+    fs::read_dir(base_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
+        })
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+}
+
+/// Lists `scripts/<prefix>*.txt` files of the content roots, in root order.
+pub fn find_scripts(roots: &[PathBuf], prefix: &str) -> Vec<PathBuf> {
+    let mut scripts = Vec::new();
+    for root in roots {
+        let Some(entries) = find_dir(root, "scripts").and_then(|dir| fs::read_dir(dir).ok()) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_lowercase();
+                name.starts_with(prefix) && name.ends_with(".txt")
+            })
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        found.sort();
+        scripts.extend(found);
+    }
+    scripts
+}
+
 pub fn iter_files<P: AsRef<Path>>(path: P) -> impl Iterator<Item = DirEntry> {
     WalkDir::new(path)
         .into_iter()
@@ -0,0 +1,290 @@
+//! Materials (`.vmt`), read with the KeyValues parser.
+//!
+//! Shader parameters are typed by name: texture parameters reference VTFs, material
+//! parameters reference other VMTs, everything else (`$surfaceprop`, colors, flags) is
+//! ignored. Conditional blocks (`">=dx90"`, `"GPU>=2"`), shader fallback blocks
+//! (`"LightmappedGeneric_DX80"`) and `srgb?`-style key prefixes are flattened: which branch
+//! applies depends on the player's hardware, so all of them are collected. Entries limited
+//! to consoles (`[$X360]`) are skipped.
+
+use crate::keyvalues::{self, KeyValue, Value};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Texture parameters whose names don't end with `texture`, `map` or `mask`.
+const TEXTURE_PARAMS: [&str; 9] = [
+    "$detail",
+    "$detail1",
+    "$detail2",
+    "$iris",
+    "$compress",
+    "$stretch",
+    "$bumpcompress",
+    "$bumpstretch",
+    "$fleshbordertexture1d",
+];
+
+/// Parameters referencing other materials.
+const MATERIAL_PARAMS: [&str; 4] = [
+    "$bottommaterial",
+    "$underwateroverlay",
+    "$crackmaterial",
+    "$fallbackmaterial",
+];
+
+/// Parameters holding surface property names.
+const SURFACEPROP_PARAMS: [&str; 2] = ["$surfaceprop", "$surfaceprop2"];
+
+/// Special texture values that aren't files.
+const BUILTIN_TEXTURES: [&str; 1] = ["env_cubemap"];
+
+/// A parsed material.
+#[derive(Debug, Clone, Default)]
+pub struct Vmt {
+    /// Shader name, e.g. `LightmappedGeneric` or `patch`.
+    pub shader: String,
+    /// Parameters of the shader and all its conditional blocks, with lowercase keys, in file order.
+    pub params: Vec<(String, String)>,
+}
+
+impl Vmt {
+    /// Parses the text of a material. Malformed input yields whatever could be read.
+    pub fn parse(text: &str) -> Self {
+        let entries = keyvalues::parse(text);
+        let Some(shader) = entries.into_iter().find(KeyValue::is_enabled) else {
+            return Self::default();
+        };
+
+        let mut params = Vec::new();
+        collect_params(shader.children(), &mut params);
+        Self {
+            shader: shader.key,
+            params,
+        }
+    }
+
+    /// Reads and parses a material file. Non-UTF8 bytes are replaced.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let text = String::from_utf8_lossy(&data);
+        Ok(Self::parse(text.trim_start_matches('\u{feff}')))
+    }
+
+    /// Textures (relative to `materials/`, without extension) used by texture parameters.
+    pub fn textures(&self) -> Vec<PathBuf> {
+        self.params
+            .iter()
+            .filter(|(key, value)| is_texture_param(key) && is_texture_value(value))
+            .map(|(_, value)| asset_name(value, "vtf"))
+            .collect()
+    }
+
+    /// Materials (relative to `materials/`, without extension) used by material parameters,
+    /// and the material a `patch` material includes.
+    pub fn materials(&self) -> Vec<PathBuf> {
+        let is_patch = self.shader.eq_ignore_ascii_case("patch");
+        self.params
+            .iter()
+            .filter(|(key, value)| {
+                let is_material =
+                    MATERIAL_PARAMS.contains(&key.as_str()) || (is_patch && key == "include");
+                is_material && !value.trim().is_empty()
+            })
+            .map(|(_, value)| asset_name(value, "vmt"))
+            .collect()
+    }
+
+    /// Surface property names (lowercase) of `$surfaceprop` and `$surfaceprop2`.
+    pub fn surface_props(&self) -> Vec<String> {
+        self.params
+            .iter()
+            .filter(|(key, value)| {
+                SURFACEPROP_PARAMS.contains(&key.as_str()) && !value.trim().is_empty()
+            })
+            .map(|(_, value)| value.trim().to_lowercase())
+            .collect()
+    }
+}
+
+/// Flattens the parameters of a shader block into `params`, descending into conditional,
+/// fallback and patch (`insert`, `replace`) blocks. Proxies only reference variables.
+fn collect_params(entries: &[KeyValue], params: &mut Vec<(String, String)>) {
+    for entry in entries.iter().filter(|entry| entry.is_enabled()) {
+        match &entry.value {
+            Value::String(value) => {
+                // `srgb?$basetexture`, `GPU>=2?$detail`: the condition doesn't matter here.
+                let key = entry.key.rsplit('?').next().unwrap_or_default();
+                params.push((key.trim().to_ascii_lowercase(), value.clone()));
+            }
+            Value::Block(children) if !entry.key.eq_ignore_ascii_case("proxies") => {
+                collect_params(children, params);
+            }
+            Value::Block(_) => {}
+        }
+    }
+}
+
+fn is_texture_param(key: &str) -> bool {
+    if TEXTURE_PARAMS.contains(&key) {
+        return true;
+    }
+    let name = key.trim_end_matches(|c: char| c.is_ascii_digit());
+    key.starts_with('$')
+        && (name.ends_with("texture") || name.ends_with("map") || name.ends_with("mask"))
+}
+
+/// Whether a texture parameter value names a file: not a number, vector, render target
+/// (`_rt_*`) or builtin texture.
+fn is_texture_value(value: &str) -> bool {
+    let value = value.trim();
+    let lower = value.to_ascii_lowercase();
+    value.chars().any(|c| c.is_ascii_alphabetic())
+        && !value.starts_with(['[', '{'])
+        && !lower.starts_with("_rt_")
+        && !BUILTIN_TEXTURES.contains(&lower.as_str())
+}
+
+/// Normalizes a texture or material reference to a lowercase path relative to `materials/`,
+/// without the `extension`.
+fn asset_name(value: &str, extension: &str) -> PathBuf {
+    let value = value.trim().replace('\\', "/").to_lowercase();
+    let value = value.strip_prefix("materials/").unwrap_or(&value);
+    let value = value
+        .strip_suffix(extension)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(value);
+    PathBuf::from(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn textures_skip_other_params() {
+        let vmt = Vmt::parse(
+            r#"
+            "VertexLitGeneric"
+            {
+                "$basetexture" "models/props/crate"
+                "$surfaceprop" "metal"
+                "$envmap" "env_cubemap"
+                "$envmaptint" "[0.5 0.5 0.5]"
+                "$color2" "{255 0 0}"
+                "$selfillummask" "_rt_FullFrameFB"
+                "$bumpmap" "0"
+                "$model" "1"
+                "$detail" "detail/noise"
+                "$detailscale" "4"
+                "%keywords" "wood"
+                "$bottommaterial" "nature/water_bottom"
+            }
+            "#,
+        );
+        assert_eq!(vmt.shader, "VertexLitGeneric");
+        assert_eq!(
+            vmt.textures(),
+            paths(&["models/props/crate", "detail/noise"])
+        );
+        assert_eq!(vmt.materials(), paths(&["nature/water_bottom"]));
+        assert_eq!(vmt.surface_props(), ["metal"]);
+    }
+
+    #[test]
+    fn textures_with_digits_and_dots() {
+        let vmt = Vmt::parse(
+            r#"
+            "LightmappedGeneric"
+            {
+                "$basetexture" "2fort/wall01"
+                "$basetexture2" "Materials\Custom\v1.2\Floor.VTF"
+                $bumpmap custom/floor.v2_normal
+                "$blendmodulatetexture" "3d/mask"
+            }
+            "#,
+        );
+        assert_eq!(
+            vmt.textures(),
+            paths(&[
+                "2fort/wall01",
+                "custom/v1.2/floor",
+                "custom/floor.v2_normal",
+                "3d/mask",
+            ])
+        );
+    }
+
+    #[test]
+    fn textures_of_every_fallback_block() {
+        let vmt = Vmt::parse(
+            r#"
+            "WorldVertexTransition"
+            {
+                "$basetexture" "nature/grass"
+                ">=dx90"
+                {
+                    "$basetexture2" "nature/dirt"
+                }
+                "<dx90"
+                {
+                    "$basetexture2" "nature/dirt_dx80"
+                }
+                "WorldVertexTransition_DX80"
+                {
+                    "srgb?$bumpmap" "nature/grass_normal"
+                }
+                "Proxies"
+                {
+                    "AnimatedTexture" { "animatedtexturevar" "$basetexture" }
+                }
+            }
+            "#,
+        );
+        assert_eq!(
+            vmt.textures(),
+            paths(&[
+                "nature/grass",
+                "nature/dirt",
+                "nature/dirt_dx80",
+                "nature/grass_normal",
+            ])
+        );
+    }
+
+    #[test]
+    fn textures_skip_console_keys() {
+        let vmt = Vmt::parse(
+            r#"
+            "UnlitGeneric"
+            {
+                "$basetexture" "custom/sign_console" [$X360]
+                "$basetexture" "custom/sign" [!$X360]
+                "$detail" [$X360 || $PS3] "custom/detail_console"
+                "$envmapmask" "custom/sign_mask" [$WIN32]
+                "360?$bumpmap" "custom/sign_normal"
+                "$X360"
+                {
+                    "$selfillummask" "custom/never"
+                } [$X360]
+            }
+            "#,
+        );
+        assert_eq!(
+            vmt.textures(),
+            paths(&["custom/sign", "custom/sign_mask", "custom/sign_normal"])
+        );
+    }
+
+    #[test]
+    fn parse_skips_console_shader() {
+        let vmt = Vmt::parse(
+            r#""UnlitGeneric" [$X360] { "$basetexture" "a" } "LightmappedGeneric" { "$basetexture" "b" }"#,
+        );
+        assert_eq!(vmt.shader, "LightmappedGeneric");
+        assert_eq!(vmt.textures(), paths(&["b"]));
+    }
+}
@@ -0,0 +1,571 @@
+//! A reader for the header of Valve textures (`.vtf`, versions 7.0 to 7.5).
+//!
+//! Besides the header fields, it computes the size the image data should have, so
+//! truncated files can be detected without decoding anything. The same layout
+//! knowledge lets [`MipStrip`] drop the largest mip levels of a texture by cutting
+//! its image data, without re-encoding.
+
+use crate::parsers::lump::{read_i32, read_u16};
+use std::fmt;
+
+/// `VTF\0`, the identifier of texture files.
+const VTF_ID: &[u8; 4] = b"VTF\0";
+/// Size of the 7.0 and 7.1 header; later versions are larger.
+const MIN_HEADER_SIZE: usize = 64;
+/// Size of a resource entry (7.3+): tag, flags and offset.
+const RESOURCE_SIZE: usize = 8;
+/// Resource tag of the low-res thumbnail.
+const RESOURCE_LOW_RES: [u8; 3] = [0x01, 0, 0];
+/// Resource tag of the high-res image data.
+const RESOURCE_HIGH_RES: [u8; 3] = [0x30, 0, 0];
+/// Resource flag of entries storing their value in the offset field instead of data.
+const RESOURCE_NO_DATA: u8 = 0x02;
+/// `TEXTUREFLAGS_NOMIP`: the texture is used without mip levels.
+const FLAG_NOMIP: u32 = 0x100;
+/// `TEXTUREFLAGS_ENVMAP`: the texture is a cubemap with six faces.
+const FLAG_ENVMAP: u32 = 0x4000;
+/// `first_frame` value of cubemaps stored without the spheremap face (before 7.5).
+const NO_SPHEREMAP: u16 = 0xFFFF;
+
+/// Pixel formats of the VTF format, indexed by their id: name, size of a pixel (or of a
+/// 4x4 block) and whether the format is block-compressed.
+const FORMATS: [(&str, usize, bool); 27] = [
+    ("RGBA8888", 4, false),
+    ("ABGR8888", 4, false),
+    ("RGB888", 3, false),
+    ("BGR888", 3, false),
+    ("RGB565", 2, false),
+    ("I8", 1, false),
+    ("IA88", 2, false),
+    ("P8", 1, false),
+    ("A8", 1, false),
+    ("RGB888_BLUESCREEN", 3, false),
+    ("BGR888_BLUESCREEN", 3, false),
+    ("ARGB8888", 4, false),
+    ("BGRA8888", 4, false),
+    ("DXT1", 8, true),
+    ("DXT3", 16, true),
+    ("DXT5", 16, true),
+    ("BGRX8888", 4, false),
+    ("BGR565", 2, false),
+    ("BGRX5551", 2, false),
+    ("BGRA4444", 2, false),
+    ("DXT1_ONEBITALPHA", 8, true),
+    ("BGRA5551", 2, false),
+    ("UV88", 2, false),
+    ("UVWQ8888", 4, false),
+    ("RGBA16161616F", 8, false),
+    ("RGBA16161616", 8, false),
+    ("UVLX8888", 4, false),
+];
+
+/// A pixel format id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageFormat(pub i32);
+
+impl ImageFormat {
+    /// No image, used for missing thumbnails.
+    pub const NONE: ImageFormat = ImageFormat(-1);
+
+    /// Bytes of an image of the given size, or `None` for unknown formats.
+    pub fn image_size(self, width: usize, height: usize) -> Option<usize> {
+        if self == Self::NONE {
+            return Some(0);
+        }
+        let &(_, size, compressed) = FORMATS.get(usize::try_from(self.0).ok()?)?;
+        Some(if compressed {
+            width.div_ceil(4) * height.div_ceil(4) * size
+        } else {
+            width * height * size
+        })
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = usize::try_from(self.0)
+            .ok()
+            .and_then(|id| FORMATS.get(id))
+            .map(|(name, _, _)| *name);
+        match name {
+            Some(name) => f.write_str(name),
+            None if *self == Self::NONE => f.write_str("NONE"),
+            None => write!(f, "unknown ({})", self.0),
+        }
+    }
+}
+
+/// The header of a texture.
+#[derive(Debug, Clone)]
+pub struct VtfHeader {
+    /// Major and minor version, e.g. `(7, 2)`.
+    pub version: (u32, u32),
+    pub header_size: usize,
+    pub width: u16,
+    pub height: u16,
+    /// Depth of volume textures (7.2+), 1 otherwise.
+    pub depth: u16,
+    /// `TEXTUREFLAGS_*` bits.
+    pub flags: u32,
+    pub frames: u16,
+    /// First animation frame. Before 7.5, `0xFFFF` marks cubemaps without a spheremap.
+    pub first_frame: u16,
+    pub format: ImageFormat,
+    pub mip_count: u8,
+    pub low_res_format: ImageFormat,
+    pub low_res_width: u8,
+    pub low_res_height: u8,
+    /// Offsets of the low-res and high-res data, from the resource table (7.3+).
+    low_res_offset: Option<usize>,
+    high_res_offset: Option<usize>,
+}
+
+impl VtfHeader {
+    /// Reads the header of a texture. Returns `None` if the data isn't a VTF or the
+    /// header is cut off.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != VTF_ID {
+            return None;
+        }
+        let u32_at = |pos| read_i32(data, pos).map(|v| v as u32);
+        let version = (u32_at(4)?, u32_at(8)?);
+        let header_size = usize::try_from(read_i32(data, 12)?).ok()?;
+        if header_size < MIN_HEADER_SIZE || data.len() < MIN_HEADER_SIZE {
+            return None;
+        }
+
+        let mut header = VtfHeader {
+            version,
+            header_size,
+            width: read_u16(data, 16)?,
+            height: read_u16(data, 18)?,
+            depth: 1,
+            flags: u32_at(20)?,
+            frames: read_u16(data, 24)?,
+            first_frame: read_u16(data, 26)?,
+            format: ImageFormat(read_i32(data, 52)?),
+            mip_count: *data.get(56)?,
+            low_res_format: ImageFormat(read_i32(data, 57)?),
+            low_res_width: *data.get(61)?,
+            low_res_height: *data.get(62)?,
+            low_res_offset: None,
+            high_res_offset: None,
+        };
+        if version >= (7, 2) {
+            header.depth = read_u16(data, 63)?.max(1);
+        }
+        if version >= (7, 3) {
+            let count = usize::try_from(read_i32(data, 68)?).ok()?;
+            for i in 0..count.min(32) {
+                let entry = data.get(80 + i * RESOURCE_SIZE..80 + (i + 1) * RESOURCE_SIZE)?;
+                let offset = usize::try_from(read_i32(entry, 4)?).ok();
+                if entry[..3] == RESOURCE_LOW_RES {
+                    header.low_res_offset = offset;
+                } else if entry[..3] == RESOURCE_HIGH_RES {
+                    header.high_res_offset = offset;
+                }
+            }
+        }
+        Some(header)
+    }
+
+    /// Number of faces: one, six for cubemaps, or seven for cubemaps older than 7.5 that
+    /// also store a spheremap.
+    pub fn faces(&self) -> usize {
+        if self.flags & FLAG_ENVMAP == 0 {
+            1
+        } else if self.version < (7, 5) && self.first_frame != NO_SPHEREMAP {
+            7
+        } else {
+            6
+        }
+    }
+
+    /// Size of the low-res thumbnail data.
+    pub fn low_res_size(&self) -> Option<usize> {
+        self.low_res_format.image_size(
+            usize::from(self.low_res_width),
+            usize::from(self.low_res_height),
+        )
+    }
+
+    /// Size of the high-res image data: every mip level of every frame, face and slice.
+    /// `None` for unknown formats.
+    pub fn high_res_size(&self) -> Option<usize> {
+        let per_image = self.frames.max(1) as usize * self.faces();
+        (0..u32::from(self.mip_count.max(1))).try_fold(0, |total, mip| {
+            let width = (usize::from(self.width) >> mip).max(1);
+            let height = (usize::from(self.height) >> mip).max(1);
+            let depth = (usize::from(self.depth) >> mip).max(1);
+            Some(total + self.format.image_size(width, height)? * depth * per_image)
+        })
+    }
+
+    /// The file size the header implies, or `None` when it can't be computed.
+    pub fn expected_file_size(&self) -> Option<usize> {
+        let high_res = self.high_res_size()?;
+        let low_res = self.low_res_size()?;
+        match (self.low_res_offset, self.high_res_offset) {
+            (low, Some(high)) => Some((high + high_res).max(low.map_or(0, |low| low + low_res))),
+            _ if self.version >= (7, 3) => None,
+            _ => Some(self.header_size + low_res + high_res),
+        }
+    }
+
+    /// Whether both dimensions are powers of two.
+    pub fn is_power_of_two(&self) -> bool {
+        self.width.is_power_of_two() && self.height.is_power_of_two()
+    }
+}
+
+impl fmt::Display for VtfHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "VTF {}.{}, {}x{}",
+            self.version.0, self.version.1, self.width, self.height
+        )?;
+        if self.depth > 1 {
+            write!(f, "x{}", self.depth)?;
+        }
+        write!(
+            f,
+            " {}, {} mips, {} frames, flags {:#x}, thumbnail {} {}x{}",
+            self.format,
+            self.mip_count,
+            self.frames,
+            self.flags,
+            self.low_res_format,
+            self.low_res_width,
+            self.low_res_height
+        )
+    }
+}
+
+/// Removes the largest mip levels of textures, for smaller builds.
+///
+/// Mip levels are stored smallest first, so the largest ones are cut from the end of
+/// the image data; the header dimensions and mip count and the offsets of the
+/// resources stored after the image data are rewritten. The low-res thumbnail is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MipStrip {
+    /// Largest number of mip levels to remove.
+    pub mips: u8,
+    /// Only textures with a side above this size are stripped, and never below it.
+    pub min_size: u32,
+}
+
+impl MipStrip {
+    /// Number of mip levels removed from a texture: at most `mips`, keeping the largest
+    /// side at `min_size` or above and at least one mip level.
+    pub fn strip_count(&self, header: &VtfHeader) -> u8 {
+        if header.flags & FLAG_NOMIP != 0 {
+            return 0;
+        }
+        let mut size = u32::from(header.width.max(header.height));
+        let mut count = 0;
+        while count < self.mips && count + 1 < header.mip_count && size / 2 >= self.min_size.max(1)
+        {
+            size /= 2;
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns the texture without its largest mip levels. `None` when nothing is
+    /// removed or the file can't be stripped safely (unknown format, truncated data).
+    pub fn apply(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let header = VtfHeader::read(data)?;
+        let count = self.strip_count(&header);
+        if count == 0 || data.len() < header.expected_file_size()? {
+            return None;
+        }
+
+        let mut stripped = header.clone();
+        stripped.width = (header.width >> count).max(1);
+        stripped.height = (header.height >> count).max(1);
+        stripped.depth = (header.depth >> count).max(1);
+        stripped.mip_count -= count;
+
+        let high_res_size = header.high_res_size()?;
+        let removed = high_res_size - stripped.high_res_size()?;
+        let high_res_end = match header.high_res_offset {
+            Some(offset) => offset + high_res_size,
+            None if header.version < (7, 3) => {
+                header.header_size + header.low_res_size()? + high_res_size
+            }
+            None => return None,
+        };
+
+        let mut out = Vec::with_capacity(data.len() - removed);
+        out.extend_from_slice(&data[..high_res_end - removed]);
+        out.extend_from_slice(&data[high_res_end..]);
+
+        out[16..18].copy_from_slice(&stripped.width.to_le_bytes());
+        out[18..20].copy_from_slice(&stripped.height.to_le_bytes());
+        out[56] = stripped.mip_count;
+        if header.version >= (7, 2) {
+            out[63..65].copy_from_slice(&stripped.depth.to_le_bytes());
+        }
+        if header.version >= (7, 3) {
+            let resources = usize::try_from(read_i32(data, 68)?).ok()?;
+            for i in 0..resources.min(32) {
+                let pos = 80 + i * RESOURCE_SIZE;
+                let entry = out.get(pos..pos + RESOURCE_SIZE)?;
+                if entry[3] & RESOURCE_NO_DATA != 0 {
+                    continue;
+                }
+                let offset = usize::try_from(read_i32(entry, 4)?).ok()?;
+                if offset >= high_res_end {
+                    let offset = u32::try_from(offset - removed).ok()?;
+                    out[pos + 4..pos + 8].copy_from_slice(&offset.to_le_bytes());
+                }
+            }
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGBA8888: i32 = 0;
+    const DXT1: i32 = 13;
+
+    /// Resource tag of key values data, stored after the image data in tests.
+    const RESOURCE_KEY_VALUES: [u8; 3] = *b"KVD";
+    /// Key values data appended to 7.3+ test files: a size, then the text.
+    const KEY_VALUES: &[u8] = b"\x04\0\0\0test";
+
+    /// A synthetic texture: header, DXT1 16x16 thumbnail, then the image data with every
+    /// byte set to the index of its mip level. 7.3+ files list both in their resources,
+    /// followed by a key values resource stored after the image data.
+    struct TestVtf {
+        version: u32,
+        width: u16,
+        height: u16,
+        flags: u32,
+        first_frame: u16,
+        format: i32,
+        mips: u8,
+    }
+
+    impl TestVtf {
+        fn new(version: u32, width: u16, height: u16, mips: u8) -> Self {
+            Self {
+                version,
+                width,
+                height,
+                flags: 0,
+                first_frame: 0,
+                format: RGBA8888,
+                mips,
+            }
+        }
+
+        fn header_size(&self) -> usize {
+            match self.version {
+                0 | 1 => 64,
+                2 => 80,
+                _ => 80 + 3 * RESOURCE_SIZE,
+            }
+        }
+
+        fn build(&self, faces: usize) -> Vec<u8> {
+            let header_size = self.header_size();
+            let mut data = vec![0; header_size];
+            data[..4].copy_from_slice(VTF_ID);
+            data[4..8].copy_from_slice(&7u32.to_le_bytes());
+            data[8..12].copy_from_slice(&self.version.to_le_bytes());
+            data[12..16].copy_from_slice(&(header_size as u32).to_le_bytes());
+            data[16..18].copy_from_slice(&self.width.to_le_bytes());
+            data[18..20].copy_from_slice(&self.height.to_le_bytes());
+            data[20..24].copy_from_slice(&self.flags.to_le_bytes());
+            data[24..26].copy_from_slice(&1u16.to_le_bytes());
+            data[26..28].copy_from_slice(&self.first_frame.to_le_bytes());
+            data[52..56].copy_from_slice(&self.format.to_le_bytes());
+            data[56] = self.mips;
+            data[57..61].copy_from_slice(&DXT1.to_le_bytes());
+            data[61] = 16;
+            data[62] = 16;
+            if self.version >= 2 {
+                data[63..65].copy_from_slice(&1u16.to_le_bytes());
+            }
+            let low_res_offset = header_size;
+            let high_res_offset = low_res_offset + 128;
+            if self.version >= 3 {
+                data[68..72].copy_from_slice(&3u32.to_le_bytes());
+                data[80..83].copy_from_slice(&RESOURCE_LOW_RES);
+                data[84..88].copy_from_slice(&(low_res_offset as u32).to_le_bytes());
+                data[88..91].copy_from_slice(&RESOURCE_HIGH_RES);
+                data[92..96].copy_from_slice(&(high_res_offset as u32).to_le_bytes());
+            }
+
+            data.resize(high_res_offset, 0xAA);
+            let format = ImageFormat(self.format);
+            for mip in (0..self.mips).rev() {
+                let width = (usize::from(self.width) >> mip).max(1);
+                let height = (usize::from(self.height) >> mip).max(1);
+                let size = format.image_size(width, height).unwrap() * faces;
+                data.resize(data.len() + size, mip);
+            }
+            if self.version >= 3 {
+                let offset = data.len() as u32;
+                data[96..99].copy_from_slice(&RESOURCE_KEY_VALUES);
+                data[100..104].copy_from_slice(&offset.to_le_bytes());
+                data.extend_from_slice(KEY_VALUES);
+            }
+            data
+        }
+    }
+
+    #[test]
+    fn read_header_fields() {
+        let mut vtf = TestVtf::new(2, 256, 128, 9);
+        vtf.flags = 0x2000;
+        vtf.format = DXT1;
+        let data = vtf.build(1);
+
+        let header = VtfHeader::read(&data).unwrap();
+        assert_eq!(header.version, (7, 2));
+        assert_eq!(header.header_size, 80);
+        assert_eq!((header.width, header.height, header.depth), (256, 128, 1));
+        assert_eq!(header.flags, 0x2000);
+        assert_eq!(header.frames, 1);
+        assert_eq!(header.format, ImageFormat(DXT1));
+        assert_eq!(header.mip_count, 9);
+        assert_eq!(header.low_res_format, ImageFormat(DXT1));
+        assert_eq!((header.low_res_width, header.low_res_height), (16, 16));
+        assert_eq!(header.expected_file_size(), Some(data.len()));
+        assert!(header.is_power_of_two());
+    }
+
+    #[test]
+    fn read_resources() {
+        let data = TestVtf::new(5, 64, 32, 7).build(1);
+
+        let header = VtfHeader::read(&data).unwrap();
+        assert_eq!(header.low_res_offset, Some(104));
+        assert_eq!(header.high_res_offset, Some(104 + 128));
+        assert_eq!(
+            header.expected_file_size(),
+            Some(data.len() - KEY_VALUES.len())
+        );
+    }
+
+    #[test]
+    fn cubemap_faces() {
+        let mut vtf = TestVtf::new(4, 32, 32, 6);
+        vtf.flags = FLAG_ENVMAP;
+        let with_spheremap = vtf.build(7);
+        let header = VtfHeader::read(&with_spheremap).unwrap();
+        assert_eq!(header.faces(), 7);
+        assert_eq!(
+            header.expected_file_size(),
+            Some(with_spheremap.len() - KEY_VALUES.len())
+        );
+
+        vtf.first_frame = NO_SPHEREMAP;
+        let without_spheremap = vtf.build(6);
+        let header = VtfHeader::read(&without_spheremap).unwrap();
+        assert_eq!(header.faces(), 6);
+        assert_eq!(
+            header.expected_file_size(),
+            Some(without_spheremap.len() - KEY_VALUES.len())
+        );
+
+        vtf.version = 5;
+        vtf.first_frame = 0;
+        let header = VtfHeader::read(&vtf.build(6)).unwrap();
+        assert_eq!(header.faces(), 6);
+    }
+
+    #[test]
+    fn read_rejects_invalid_data() {
+        assert!(VtfHeader::read(b"").is_none());
+        assert!(VtfHeader::read(b"VTF\0").is_none());
+        assert!(VtfHeader::read(&[0; 80]).is_none());
+        let data = TestVtf::new(1, 16, 16, 1).build(1);
+        assert!(VtfHeader::read(&data[..40]).is_none());
+    }
+
+    #[test]
+    fn non_power_of_two() {
+        let header = VtfHeader::read(&TestVtf::new(1, 300, 256, 1).build(1)).unwrap();
+        assert!(!header.is_power_of_two());
+    }
+
+    #[test]
+    fn strip_mips() {
+        let data = TestVtf::new(2, 1024, 512, 11).build(1);
+        let strip = MipStrip {
+            mips: 2,
+            min_size: 0,
+        };
+
+        let stripped = strip.apply(&data).unwrap();
+        let header = VtfHeader::read(&stripped).unwrap();
+        assert_eq!((header.width, header.height), (256, 128));
+        assert_eq!(header.mip_count, 9);
+        assert_eq!(header.expected_file_size(), Some(stripped.len()));
+        // Only the two largest mips are cut from the end; the thumbnail and smaller mips stay.
+        assert_eq!(stripped[80..], data[80..stripped.len()]);
+        assert_eq!(stripped.last(), Some(&2));
+    }
+
+    #[test]
+    fn strip_mips_of_cubemap_with_spheremap() {
+        let mut vtf = TestVtf::new(4, 64, 64, 7);
+        vtf.flags = FLAG_ENVMAP;
+        let data = vtf.build(7);
+        let strip = MipStrip {
+            mips: 1,
+            min_size: 0,
+        };
+
+        let stripped = strip.apply(&data).unwrap();
+        let header = VtfHeader::read(&stripped).unwrap();
+        assert_eq!((header.width, header.height), (32, 32));
+        assert_eq!(header.mip_count, 6);
+        assert_eq!(header.faces(), 7);
+        assert_eq!(header.low_res_offset, Some(104));
+        assert_eq!(header.high_res_offset, Some(104 + 128));
+        assert_eq!(stripped.len(), data.len() - 64 * 64 * 4 * 7);
+        // The key values stored after the image data move with it.
+        let key_values = stripped.len() - KEY_VALUES.len();
+        assert_eq!(header.expected_file_size(), Some(key_values));
+        assert_eq!(read_i32(&stripped, 100), Some(key_values as i32));
+        assert_eq!(stripped[key_values..], *KEY_VALUES);
+        assert_eq!(stripped[104..key_values], data[104..key_values]);
+    }
+
+    #[test]
+    fn strip_mips_thresholds() {
+        let strip = MipStrip {
+            mips: 3,
+            min_size: 512,
+        };
+        let header = VtfHeader::read(&TestVtf::new(1, 1024, 1024, 11).build(1)).unwrap();
+        assert_eq!(strip.strip_count(&header), 1);
+        let header = VtfHeader::read(&TestVtf::new(1, 512, 512, 10).build(1)).unwrap();
+        assert_eq!(strip.strip_count(&header), 0);
+        // At least one mip level is kept.
+        let header = VtfHeader::read(&TestVtf::new(1, 4096, 4096, 2).build(1)).unwrap();
+        assert_eq!(strip.strip_count(&header), 1);
+
+        let mut vtf = TestVtf::new(1, 2048, 2048, 12);
+        vtf.flags = FLAG_NOMIP;
+        assert!(strip.apply(&vtf.build(1)).is_none());
+    }
+
+    #[test]
+    fn strip_mips_skips_truncated_files() {
+        let data = TestVtf::new(2, 256, 256, 9).build(1);
+        let strip = MipStrip {
+            mips: 1,
+            min_size: 0,
+        };
+        assert!(strip.apply(&data[..data.len() - 1]).is_none());
+    }
+}